@@ -0,0 +1,1165 @@
+use crate::midi_export::to_track;
+use log::debug;
+use midly::{num::u24, Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::{collections::HashMap, error::Error, io};
+
+const TICKS_PER_QUARTER: u64 = 480;
+const TICKS_PER_WHOLE: u64 = TICKS_PER_QUARTER * 4;
+const VELOCITY: u8 = 80;
+// Channel 10 is reserved for drums
+const DRUM_CHANNEL: u8 = 9;
+// Longest broken rhythm, ">>>" makes a note 7/8 longer
+const MAX_BROKEN_RHYTHM: u32 = 3;
+// Longest written length and multi-measure rest, in units and bars
+const MAX_WRITTEN_LENGTH: u64 = 1024;
+// Semitones above C for C D E F G A B
+const LETTER_SEMITONES: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+// Position of F C G D A E B on the circle of fifths, used to build key signatures
+const SHARP_ORDER: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+
+/// A tune found in an ABC file, starting at its X: field
+pub struct AbcTune {
+    pub number: Option<u32>,
+    pub title: Option<String>,
+    lines: Vec<String>,
+}
+
+/// Splits a file into its tunes, each one ends at the first empty line
+pub fn split_tunes(text: &str) -> Vec<AbcTune> {
+    let mut tunes = Vec::new();
+    let mut current: Option<AbcTune> = None;
+
+    // Files with a single tune often leave out the X: field
+    let has_reference = text.lines().any(|line| line.starts_with("X:"));
+    if !has_reference {
+        current = Some(AbcTune {
+            number: None,
+            title: None,
+            lines: Vec::new(),
+        });
+    }
+
+    for line in text.lines() {
+        let line = line.trim_end();
+        if let Some(number) = line.strip_prefix("X:") {
+            tunes.extend(current.take());
+            current = Some(AbcTune {
+                number: number.trim().parse().ok(),
+                title: None,
+                lines: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(tune) = current.as_mut() else {
+            continue; // File header or free text between tunes
+        };
+        if line.is_empty() && has_reference {
+            tunes.extend(current.take());
+            continue;
+        }
+        if let Some(title) = line.strip_prefix("T:") {
+            tune.title.get_or_insert_with(|| title.trim().to_string());
+        }
+        tune.lines.push(line.to_string());
+    }
+    tunes.extend(current);
+
+    tunes.retain(|tune| tune.lines.iter().any(|line| line.starts_with("K:")));
+    tunes
+}
+
+impl AbcTune {
+    /// Converts the tune into Standard MIDI File bytes with one track per voice
+    pub fn to_smf(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut parser = Parser::default();
+        for line in &self.lines {
+            parser.parse_line(line);
+        }
+        if let Some(error) = &parser.error {
+            return Err(invalid_data(error));
+        }
+        if !parser.in_body {
+            return Err(invalid_data("tune has no K: field"));
+        }
+
+        let mut conductor = vec![(
+            0,
+            TrackEventKind::Meta(MetaMessage::KeySignature(
+                parser.key_fifths.clamp(-7, 7),
+                parser.key_minor,
+            )),
+        )];
+        let mut voice_tracks = Vec::new();
+        let mut channels = (0..16u8).filter(|&c| c != DRUM_CHANNEL).cycle();
+        for voice in &parser.voices {
+            let (notes, markers) = schedule(&expand_repeats(&voice.items))?;
+            if notes.is_empty() {
+                continue;
+            }
+            // Tempo and meter changes are taken from the first voice that has notes
+            if voice_tracks.is_empty() {
+                conductor.extend(markers);
+            }
+            voice_tracks.push((voice, notes, channels.next().unwrap()));
+        }
+        if voice_tracks.is_empty() {
+            return Err(invalid_data("tune has no notes"));
+        }
+        conductor.sort_by_key(|&(tick, _)| tick);
+
+        let title = self.title.clone().unwrap_or_default();
+        conductor.insert(
+            0,
+            (
+                0,
+                TrackEventKind::Meta(MetaMessage::TrackName(title.as_bytes())),
+            ),
+        );
+        let mut tracks = vec![to_track(conductor)];
+        for (voice, notes, channel) in &voice_tracks {
+            let mut events = vec![
+                (
+                    0,
+                    false,
+                    TrackEventKind::Meta(MetaMessage::TrackName(voice.name.as_bytes())),
+                ),
+                (
+                    0,
+                    false,
+                    TrackEventKind::Midi {
+                        channel: (*channel).into(),
+                        message: MidiMessage::ProgramChange {
+                            program: voice.program.into(),
+                        },
+                    },
+                ),
+            ];
+            for &(start, end, key) in notes {
+                events.push((
+                    start,
+                    true,
+                    TrackEventKind::Midi {
+                        channel: (*channel).into(),
+                        message: MidiMessage::NoteOn {
+                            key: key.into(),
+                            vel: VELOCITY.into(),
+                        },
+                    },
+                ));
+                events.push((
+                    end,
+                    false,
+                    TrackEventKind::Midi {
+                        channel: (*channel).into(),
+                        message: MidiMessage::NoteOff {
+                            key: key.into(),
+                            vel: 0.into(),
+                        },
+                    },
+                ));
+            }
+            // Note offs first so repeated notes aren't cut short
+            events.sort_by_key(|&(tick, is_on, _)| (tick, is_on));
+            tracks.push(to_track(
+                events
+                    .into_iter()
+                    .map(|(tick, _, kind)| (tick, kind))
+                    .collect(),
+            ));
+        }
+
+        let smf = Smf {
+            header: Header::new(
+                Format::Parallel,
+                Timing::Metrical((TICKS_PER_QUARTER as u16).into()),
+            ),
+            tracks,
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+fn invalid_data(message: &str) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+// Durations are kept as fractions of a whole note until they are scheduled
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Ratio(u64, u64);
+
+impl Ratio {
+    fn mul(self, other: Ratio) -> Ratio {
+        // Wide enough that the product of two lengths can't overflow
+        let (num, den) = (
+            self.0 as u128 * other.0 as u128,
+            self.1 as u128 * other.1 as u128,
+        );
+        let divisor = gcd(num, den).max(1);
+        let (num, den) = (num / divisor, den / divisor);
+        // Too fine to keep exactly, both are shortened alike so the value stays
+        let shift = (128 - num.max(den).leading_zeros()).saturating_sub(64);
+        match (den >> shift) as u64 {
+            // Longer than a ratio can hold
+            0 => Ratio(u64::MAX, 1),
+            den => Ratio((num >> shift) as u64, den),
+        }
+    }
+
+    fn ticks(self) -> u64 {
+        let ticks =
+            (TICKS_PER_WHOLE as u128 * self.0 as u128 + self.1 as u128 / 2) / self.1.max(1) as u128;
+        u64::try_from(ticks).unwrap_or(u64::MAX)
+    }
+
+    fn as_f64(self) -> f64 {
+        self.0 as f64 / self.1.max(1) as f64
+    }
+}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Item {
+    Notes {
+        keys: Vec<u8>,
+        length: Ratio,
+        tie: bool,
+    },
+    Rest(Ratio),
+    Bar,
+    DoubleBar,
+    RepeatStart,
+    RepeatEnd,
+    Ending(Vec<u32>),
+    // Micros per quarter note
+    Tempo(u32),
+    Meter(u8, u8),
+}
+
+struct Voice {
+    id: String,
+    name: String,
+    program: u8,
+    items: Vec<Item>,
+}
+
+struct Tuplet {
+    ratio: Ratio,
+    remaining: u32,
+}
+
+#[derive(Default)]
+struct Parser {
+    in_body: bool,
+    unit_length: Option<Ratio>,
+    meter: Option<(u8, u8)>,
+    // Alteration in semitones of each letter from the key signature
+    key_accidentals: [i8; 7],
+    key_fifths: i8,
+    key_minor: bool,
+    // Accidentals written earlier in the bar, by letter and octave
+    bar_accidentals: HashMap<(usize, i32), i8>,
+    voices: Vec<Voice>,
+    current_voice: usize,
+    tuplet: Option<Tuplet>,
+    // Length multiplier left for the next note by a broken rhythm (> or <)
+    broken: Option<Ratio>,
+    // The first thing found that can't be played
+    error: Option<String>,
+}
+
+impl Parser {
+    fn parse_line(&mut self, line: &str) {
+        if let Some(directive) = line.strip_prefix("%%MIDI program") {
+            if let Ok(program) = directive.trim().parse::<u8>() {
+                self.voice().program = program.min(127);
+            }
+            return;
+        }
+        if line.starts_with('%') {
+            return;
+        }
+
+        let bytes = line.as_bytes();
+        if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+            self.parse_field(bytes[0] as char, &line[2..]);
+        } else if self.in_body {
+            self.parse_music(line);
+        }
+    }
+
+    fn parse_field(&mut self, field: char, value: &str) {
+        let value = value.split('%').next().unwrap_or("").trim();
+        match field {
+            'K' => {
+                self.parse_key(value);
+                self.in_body = true;
+            }
+            'L' => self.unit_length = parse_fraction(value),
+            'M' => {
+                self.meter = parse_meter(value);
+                if let Some((numerator, denominator)) = self.meter {
+                    self.push(Item::Meter(numerator, denominator));
+                }
+            }
+            'Q' => {
+                if let Some(tempo) = parse_tempo(value, self.unit_length()) {
+                    self.push(Item::Tempo(tempo));
+                }
+            }
+            'V' => self.select_voice(value),
+            _ => {}
+        }
+    }
+
+    fn parse_key(&mut self, value: &str) {
+        let mut parts = value.split_whitespace();
+        let tonic = parts.next().unwrap_or("C");
+        self.key_accidentals = [0; 7];
+        self.bar_accidentals.clear();
+
+        let mut fifths = 0i32;
+        let mut chars = tonic.chars().peekable();
+        if let Some(letter) = chars.next().filter(|c| ('A'..='G').contains(c)) {
+            // Major key fifths: F=-1 C=0 G=1 D=2 A=3 E=4 B=5
+            fifths = match letter {
+                'F' => -1,
+                'C' => 0,
+                'G' => 1,
+                'D' => 2,
+                'A' => 3,
+                'E' => 4,
+                _ => 5,
+            };
+            match chars.peek() {
+                Some('#') => {
+                    fifths += 7;
+                    chars.next();
+                }
+                Some('b') => {
+                    fifths -= 7;
+                    chars.next();
+                }
+                _ => {}
+            }
+            let mode: String = chars.collect::<String>().to_lowercase();
+            let mode = if mode.is_empty() {
+                parts.clone().next().unwrap_or("").to_lowercase()
+            } else {
+                mode
+            };
+            let offset = match mode.get(..3).unwrap_or(mode.as_str()) {
+                "m" | "min" | "aeo" => -3,
+                "mix" => -1,
+                "dor" => -2,
+                "phr" => -4,
+                "loc" => -5,
+                "lyd" => 1,
+                _ => 0,
+            };
+            self.key_minor = offset == -3;
+            fifths += offset;
+        } else if tonic.eq_ignore_ascii_case("HP") {
+            // Highland pipes, written with no signature but played in D mixolydian
+            fifths = 2;
+        }
+        self.key_fifths = fifths.clamp(-7, 7) as i8;
+
+        if fifths > 0 {
+            for &letter in SHARP_ORDER.iter().take(fifths.min(7) as usize) {
+                self.key_accidentals[letter] = 1;
+            }
+        } else {
+            for &letter in SHARP_ORDER.iter().rev().take((-fifths).min(7) as usize) {
+                self.key_accidentals[letter] = -1;
+            }
+        }
+
+        // Explicit accidentals such as "K:D exp ^g" or "K:G _b"
+        for part in value.split_whitespace().skip(1) {
+            let chars: Vec<char> = part.chars().collect();
+            let (alter, rest) = match chars.as_slice() {
+                ['^', '^', rest @ ..] => (2, rest),
+                ['^', rest @ ..] => (1, rest),
+                ['_', '_', rest @ ..] => (-2, rest),
+                ['_', rest @ ..] => (-1, rest),
+                ['=', rest @ ..] => (0, rest),
+                _ => continue,
+            };
+            if let Some(index) = rest.first().and_then(|c| letter_index(*c)) {
+                self.key_accidentals[index] = alter;
+            }
+        }
+    }
+
+    fn select_voice(&mut self, value: &str) {
+        let id = value.split_whitespace().next().unwrap_or("").to_string();
+        let name = value
+            .split("name=")
+            .nth(1)
+            .map(|name| name.trim_matches(|c: char| c == '"' || c.is_whitespace()))
+            .map(|name| name.split('"').next().unwrap_or(name).to_string());
+
+        self.current_voice = match self.voices.iter().position(|v| v.id == id) {
+            Some(index) => index,
+            None => {
+                // Music written before the first V: field belongs to the first voice
+                if self.voices.len() == 1 && self.voices[0].id.is_empty() {
+                    self.voices[0].id = id.clone();
+                } else {
+                    self.voices.push(new_voice(&id, self.voices.len()));
+                }
+                self.voices.len() - 1
+            }
+        };
+        if let Some(name) = name {
+            self.voices[self.current_voice].name = name;
+        }
+        self.tuplet = None;
+        self.broken = None;
+    }
+
+    fn voice(&mut self) -> &mut Voice {
+        if self.voices.is_empty() {
+            self.voices.push(new_voice("", 0));
+            self.current_voice = 0;
+        }
+        &mut self.voices[self.current_voice]
+    }
+
+    fn push(&mut self, item: Item) {
+        self.voice().items.push(item);
+    }
+
+    fn malformed(&mut self, message: String) {
+        self.error.get_or_insert(message);
+    }
+
+    // A written length, or a whole unit when it's too short or too long to be
+    // played
+    fn written_length(&mut self, chars: &[char], i: &mut usize) -> Ratio {
+        let start = *i;
+        let length = parse_length(chars, i);
+        let written: String = chars[start..*i].iter().collect();
+        match length {
+            Some(length) if length.0 > MAX_WRITTEN_LENGTH.saturating_mul(length.1) => {
+                self.malformed(format!("note length \"{}\" is too long", written));
+                Ratio(1, 1)
+            }
+            Some(length) => length,
+            None => {
+                self.malformed(format!("note length \"{}\" is too short", written));
+                Ratio(1, 1)
+            }
+        }
+    }
+
+    fn unit_length(&self) -> Ratio {
+        self.unit_length.unwrap_or(match self.meter {
+            // Short meters default to sixteenth notes
+            Some((numerator, denominator)) if (numerator as f64 / denominator as f64) < 0.75 => {
+                Ratio(1, 16)
+            }
+            _ => Ratio(1, 8),
+        })
+    }
+
+    fn bar(&mut self, item: Item) {
+        self.bar_accidentals.clear();
+        self.push(item);
+    }
+
+    fn parse_music(&mut self, line: &str) {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '%' => break,
+                '"' | '!' | '+' => i = skip_past(&chars, i + 1, c),
+                '{' => i = skip_past(&chars, i + 1, '}'),
+                '(' if chars.get(i + 1).is_some_and(char::is_ascii_digit) => {
+                    i = self.parse_tuplet(&chars, i + 1);
+                }
+                '[' => i = self.parse_bracket(&chars, i + 1),
+                '|' => i = self.parse_bar(&chars, i),
+                ':' => i = self.parse_bar(&chars, i),
+                '>' | '<' => {
+                    let mut count = 0;
+                    while chars.get(i) == Some(&c) {
+                        count += 1;
+                        i += 1;
+                    }
+                    self.parse_broken_rhythm(c == '>', count);
+                }
+                'z' | 'x' => {
+                    i += 1;
+                    let length = self.written_length(&chars, &mut i);
+                    let length = self.note_length(length);
+                    self.push(Item::Rest(length));
+                }
+                'Z' | 'X' => {
+                    // Multi-measure rest
+                    i += 1;
+                    let mut bars = parse_number(&chars, &mut i).unwrap_or(1) as u64;
+                    if bars > MAX_WRITTEN_LENGTH {
+                        self.malformed(format!("multi-measure rest of {} bars is too long", bars));
+                        bars = 1;
+                    }
+                    let (numerator, denominator) = self.meter.unwrap_or((4, 4));
+                    self.push(Item::Rest(Ratio(
+                        numerator as u64 * bars,
+                        denominator as u64,
+                    )));
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => match self.parse_note(&chars, &mut i) {
+                    Some((key, length, tie)) => {
+                        let length = self.note_length(length);
+                        self.push(Item::Notes {
+                            keys: vec![key],
+                            length,
+                            tie,
+                        });
+                    }
+                    None => i += 1,
+                },
+                _ => {
+                    if !c.is_whitespace() && !".~HLMOPSTuvy()-\\&`'0123456789/,".contains(c) {
+                        debug!("Skipping unknown ABC symbol '{}'", c);
+                    }
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    fn parse_bar(&mut self, chars: &[char], mut i: usize) -> usize {
+        let mut colons_before = 0;
+        while chars.get(i) == Some(&':') {
+            colons_before += 1;
+            i += 1;
+        }
+        let mut bars = 0;
+        let mut thick = false;
+        while let Some(&c) = chars.get(i) {
+            match c {
+                '|' => bars += 1,
+                ']' if bars > 0 => thick = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        let mut colons_after = 0;
+        while chars.get(i) == Some(&':') {
+            colons_after += 1;
+            i += 1;
+        }
+
+        if colons_before > 0 && bars == 0 && colons_after == 0 {
+            // "::" is shorthand for ":|:"
+            if colons_before >= 2 {
+                self.bar(Item::RepeatEnd);
+                self.push(Item::RepeatStart);
+            }
+            return i;
+        }
+        if colons_before > 0 {
+            self.bar(Item::RepeatEnd);
+        } else if bars > 1 || thick {
+            self.bar(Item::DoubleBar);
+        } else {
+            self.bar(Item::Bar);
+        }
+        if colons_after > 0 {
+            self.push(Item::RepeatStart);
+        }
+        // First and second endings written as "|1" or ":|2"
+        if chars.get(i).is_some_and(char::is_ascii_digit) {
+            let endings = parse_endings(chars, &mut i);
+            self.push(Item::Ending(endings));
+        }
+        i
+    }
+
+    fn parse_bracket(&mut self, chars: &[char], mut i: usize) -> usize {
+        match (chars.get(i), chars.get(i + 1)) {
+            // Inline field such as [K:G] or [M:3/4]
+            (Some(&field), Some(':')) if field.is_ascii_alphabetic() => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| c == ']')
+                    .map_or(chars.len(), |p| i + p);
+                let value: String = chars[i + 2..end].iter().collect();
+                self.parse_field(field, &value);
+                end + 1
+            }
+            (Some(c), _) if c.is_ascii_digit() => {
+                let endings = parse_endings(chars, &mut i);
+                self.push(Item::Ending(endings));
+                i
+            }
+            (Some('|'), _) => {
+                self.bar(Item::DoubleBar);
+                i + 1
+            }
+            _ => {
+                // Chord, the first note sets the length
+                let mut keys = Vec::new();
+                let mut length = None;
+                let mut tie = false;
+                while i < chars.len() && chars[i] != ']' {
+                    match self.parse_note(chars, &mut i) {
+                        Some((key, note_length, note_tie)) => {
+                            keys.push(key);
+                            length.get_or_insert(note_length);
+                            tie |= note_tie;
+                        }
+                        None => i += 1,
+                    }
+                }
+                i += 1;
+                let length = length
+                    .unwrap_or(Ratio(1, 1))
+                    .mul(self.written_length(chars, &mut i));
+                if chars.get(i) == Some(&'-') {
+                    tie = true;
+                    i += 1;
+                }
+                if !keys.is_empty() {
+                    let length = self.note_length(length);
+                    self.push(Item::Notes { keys, length, tie });
+                }
+                i
+            }
+        }
+    }
+
+    fn parse_tuplet(&mut self, chars: &[char], mut i: usize) -> usize {
+        let p = parse_number(chars, &mut i).unwrap_or(3).max(1);
+        let mut q = None;
+        let mut r = None;
+        if chars.get(i) == Some(&':') {
+            i += 1;
+            q = parse_number(chars, &mut i);
+            if chars.get(i) == Some(&':') {
+                i += 1;
+                r = parse_number(chars, &mut i);
+            }
+        }
+        let compound = self
+            .meter
+            .is_some_and(|(numerator, _)| numerator % 3 == 0 && numerator > 3);
+        let q = q.unwrap_or(match p {
+            2 | 4 | 8 => 3,
+            3 | 6 => 2,
+            _ if compound => 3,
+            _ => 2,
+        });
+        self.tuplet = Some(Tuplet {
+            ratio: Ratio(q as u64, p as u64),
+            remaining: r.unwrap_or(p).max(1),
+        });
+        i
+    }
+
+    fn parse_broken_rhythm(&mut self, dotted_first: bool, count: u32) {
+        if count > MAX_BROKEN_RHYTHM {
+            let symbol = if dotted_first { ">" } else { "<" };
+            self.malformed(format!(
+                "broken rhythm \"{}\" is longer than {}",
+                symbol.repeat(count as usize),
+                symbol.repeat(MAX_BROKEN_RHYTHM as usize)
+            ));
+            return;
+        }
+        let short = Ratio(1, 1 << count);
+        let long = Ratio((2 << count) - 1, 1 << count);
+        let (previous, next) = if dotted_first {
+            (long, short)
+        } else {
+            (short, long)
+        };
+
+        let last = self
+            .voice()
+            .items
+            .iter_mut()
+            .rev()
+            .find(|item| matches!(item, Item::Notes { .. } | Item::Rest(_)));
+        match last {
+            Some(Item::Notes { length, .. }) | Some(Item::Rest(length)) => {
+                *length = length.mul(previous);
+            }
+            _ => {}
+        }
+        self.broken = Some(next);
+    }
+
+    // Applies the unit note length, tuplets and broken rhythms to a written length
+    fn note_length(&mut self, written: Ratio) -> Ratio {
+        let mut length = self.unit_length().mul(written);
+        if let Some(tuplet) = self.tuplet.as_mut() {
+            length = length.mul(tuplet.ratio);
+            tuplet.remaining -= 1;
+            if tuplet.remaining == 0 {
+                self.tuplet = None;
+            }
+        }
+        if let Some(broken) = self.broken.take() {
+            length = length.mul(broken);
+        }
+        length
+    }
+
+    // Returns the MIDI key, the written length and whether the note is tied
+    fn parse_note(&mut self, chars: &[char], i: &mut usize) -> Option<(u8, Ratio, bool)> {
+        let mut explicit = None;
+        match (chars.get(*i), chars.get(*i + 1)) {
+            (Some('^'), Some('^')) => explicit = Some(2),
+            (Some('_'), Some('_')) => explicit = Some(-2),
+            _ => {}
+        }
+        if explicit.is_some() {
+            *i += 2;
+        } else {
+            explicit = match chars.get(*i) {
+                Some('^') => Some(1),
+                Some('_') => Some(-1),
+                Some('=') => Some(0),
+                _ => None,
+            };
+            if explicit.is_some() {
+                *i += 1;
+            }
+        }
+
+        let letter = *chars.get(*i)?;
+        let index = letter_index(letter)?;
+        *i += 1;
+        let mut octave: i32 = if letter.is_ascii_lowercase() { 1 } else { 0 };
+        while let Some(&mark) = chars.get(*i) {
+            match mark {
+                '\'' => octave += 1,
+                ',' => octave -= 1,
+                _ => break,
+            }
+            *i += 1;
+        }
+
+        let alter = match explicit {
+            Some(alter) => {
+                self.bar_accidentals.insert((index, octave), alter);
+                alter
+            }
+            None => self
+                .bar_accidentals
+                .get(&(index, octave))
+                .copied()
+                .unwrap_or(self.key_accidentals[index]),
+        };
+        let key = 60 + octave * 12 + LETTER_SEMITONES[index] as i32 + alter as i32;
+
+        let length = self.written_length(chars, i);
+        let tie = chars.get(*i) == Some(&'-');
+        if tie {
+            *i += 1;
+        }
+        Some((key.clamp(0, 127) as u8, length, tie))
+    }
+}
+
+fn new_voice(id: &str, index: usize) -> Voice {
+    Voice {
+        id: id.to_string(),
+        name: if id.is_empty() {
+            format!("Voice {}", index + 1)
+        } else {
+            format!("Voice {}", id)
+        },
+        program: 0,
+        items: Vec::new(),
+    }
+}
+
+fn letter_index(letter: char) -> Option<usize> {
+    "CDEFGAB".find(letter.to_ascii_uppercase())
+}
+
+fn skip_past(chars: &[char], start: usize, end: char) -> usize {
+    chars[start..]
+        .iter()
+        .position(|&c| c == end)
+        .map_or(chars.len(), |p| start + p + 1)
+}
+
+fn parse_number(chars: &[char], i: &mut usize) -> Option<u32> {
+    let start = *i;
+    while chars.get(*i).is_some_and(char::is_ascii_digit) {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect::<String>().parse().ok()
+}
+
+// "1", "1,2" or "1-3"
+fn parse_endings(chars: &[char], i: &mut usize) -> Vec<u32> {
+    let mut endings = Vec::new();
+    while let Some(first) = parse_number(chars, i) {
+        if chars.get(*i) == Some(&'-') {
+            *i += 1;
+            let last = parse_number(chars, i).unwrap_or(first);
+            endings.extend(first..=last);
+        } else {
+            endings.push(first);
+        }
+        if chars.get(*i) != Some(&',') {
+            break;
+        }
+        *i += 1;
+    }
+    endings
+}
+
+// Length multipliers such as "2", "3/2", "/", "//" or "/4", `None` when the
+// denominator doesn't fit
+fn parse_length(chars: &[char], i: &mut usize) -> Option<Ratio> {
+    let numerator = parse_number(chars, i).unwrap_or(1) as u64;
+    let mut denominator = Some(1u64);
+    while chars.get(*i) == Some(&'/') {
+        *i += 1;
+        let divisor = parse_number(chars, i).unwrap_or(2) as u64;
+        denominator = denominator.and_then(|denominator| denominator.checked_mul(divisor));
+    }
+    Some(Ratio(numerator.max(1), denominator?.max(1)).mul(Ratio(1, 1)))
+}
+
+fn parse_fraction(value: &str) -> Option<Ratio> {
+    let (numerator, denominator) = value.trim().split_once('/')?;
+    let numerator: u64 = numerator.trim().parse().ok()?;
+    let denominator: u64 = denominator.trim().parse().ok()?;
+    (numerator > 0 && denominator > 0).then(|| Ratio(numerator, denominator).mul(Ratio(1, 1)))
+}
+
+fn parse_meter(value: &str) -> Option<(u8, u8)> {
+    match value.trim() {
+        "C" => Some((4, 4)),
+        "C|" => Some((2, 2)),
+        value => {
+            let (numerator, denominator) = value.split_once('/')?;
+            // Complex meters such as "2+3/8"
+            let numerator: u32 = numerator
+                .split('+')
+                .filter_map(|n| n.trim().parse::<u32>().ok())
+                .sum();
+            let denominator: u8 = denominator.trim().parse().ok()?;
+            (numerator > 0 && denominator.is_power_of_two())
+                .then_some((numerator.min(255) as u8, denominator))
+        }
+    }
+}
+
+// "1/4=120", "3/8=60", "\"Allegro\" 1/4=140" or a bare "120" in quarter notes
+fn parse_tempo(value: &str, unit_length: Ratio) -> Option<u32> {
+    let unquoted: String = value.split('"').step_by(2).collect::<Vec<_>>().join(" ");
+    let (beat, bpm) = match unquoted.split_once('=') {
+        Some((beats, bpm)) => {
+            let beat: f64 = beats
+                .split_whitespace()
+                .filter_map(parse_fraction)
+                .map(Ratio::as_f64)
+                .sum();
+            (
+                if beat > 0.0 {
+                    beat
+                } else {
+                    unit_length.as_f64()
+                },
+                bpm,
+            )
+        }
+        None => (0.25, unquoted.as_str()),
+    };
+    let bpm: f64 = bpm.trim().parse().ok()?;
+    if bpm <= 0.0 {
+        return None;
+    }
+    // Beats per minute of `beat` whole notes, converted to micros per quarter
+    let micros = 60_000_000.0 / (bpm * beat * 4.0);
+    Some((micros as u32).clamp(1, u24::max_value().as_int()))
+}
+
+// Plays repeats and first/second endings out in order
+fn expand_repeats(items: &[Item]) -> Vec<Item> {
+    let mut expanded = Vec::new();
+    let mut repeat_start = 0;
+    let mut pass = 1;
+    let mut skipping = false;
+    // In the ending after a skipped first ending, which runs to the next
+    // double bar or :|
+    let mut last_ending = false;
+    let mut i = 0;
+
+    while i < items.len() {
+        match &items[i] {
+            Item::RepeatStart => {
+                repeat_start = i + 1;
+                pass = 1;
+                skipping = false;
+                last_ending = false;
+            }
+            Item::RepeatEnd => {
+                if skipping {
+                    // End of a first ending skipped on the second pass, a
+                    // later :| repeats from here
+                    skipping = false;
+                    repeat_start = i + 1;
+                    if matches!(items.get(i + 1), Some(Item::Ending(_))) {
+                        last_ending = true;
+                    } else {
+                        pass = 1;
+                    }
+                } else if pass == 1 {
+                    pass = 2;
+                    i = repeat_start;
+                    continue;
+                } else {
+                    // A new section repeats from here if another :| follows
+                    pass = 1;
+                    repeat_start = i + 1;
+                    last_ending = false;
+                }
+            }
+            Item::Ending(endings) => skipping = !endings.contains(&pass),
+            Item::DoubleBar => {
+                skipping = false;
+                if last_ending {
+                    // The repeat is over, a new section repeats from here
+                    last_ending = false;
+                    pass = 1;
+                    repeat_start = i + 1;
+                }
+            }
+            item if !skipping => expanded.push(item.clone()),
+            _ => {}
+        }
+        i += 1;
+    }
+    expanded
+}
+
+type Marker<'a> = (u64, TrackEventKind<'a>);
+
+// (start tick, end tick, key) for every note, then the tempo and meter changes
+type Schedule<'a> = (Vec<(u64, u64, u8)>, Vec<Marker<'a>>);
+
+// Fails when the tune runs past the last tick that can be held
+fn schedule<'a>(items: &[Item]) -> Result<Schedule<'a>, Box<dyn Error>> {
+    let too_long = || invalid_data("tune is too long");
+    let mut notes: Vec<(u64, u64, u8)> = Vec::new();
+    let mut markers = Vec::new();
+    // Notes waiting for the next note of the same key to continue them
+    let mut ties: HashMap<u8, usize> = HashMap::new();
+    let mut tick = 0u64;
+
+    for item in items {
+        match item {
+            Item::Notes { keys, length, tie } => {
+                let end = tick.checked_add(length.ticks()).ok_or_else(too_long)?;
+                let mut next_ties = HashMap::new();
+                for &key in keys {
+                    let index = match ties.get(&key) {
+                        Some(&index) => {
+                            notes[index].1 = end;
+                            index
+                        }
+                        None => {
+                            notes.push((tick, end, key));
+                            notes.len() - 1
+                        }
+                    };
+                    if *tie {
+                        next_ties.insert(key, index);
+                    }
+                }
+                ties = next_ties;
+                tick = end;
+            }
+            Item::Rest(length) => {
+                ties.clear();
+                tick = tick.checked_add(length.ticks()).ok_or_else(too_long)?;
+            }
+            Item::Tempo(tempo) => markers.push((
+                tick,
+                TrackEventKind::Meta(MetaMessage::Tempo(u24::new(*tempo))),
+            )),
+            Item::Meter(numerator, denominator) => markers.push((
+                tick,
+                TrackEventKind::Meta(MetaMessage::TimeSignature(
+                    *numerator,
+                    denominator.trailing_zeros() as u8,
+                    24,
+                    8,
+                )),
+            )),
+            _ => {}
+        }
+    }
+    Ok((notes, markers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUARTER: u64 = TICKS_PER_QUARTER;
+
+    // (start, end, key) of every note in a tune body written in quarter notes
+    fn notes(body: &str) -> Vec<(u64, u64, u8)> {
+        let mut parser = Parser::default();
+        parser.parse_line("L:1/4");
+        parser.parse_line("K:C");
+        for line in body.lines() {
+            parser.parse_line(line);
+        }
+        assert_eq!(parser.error, None);
+        schedule(&expand_repeats(&parser.voices[0].items))
+            .unwrap()
+            .0
+    }
+
+    fn keys(body: &str) -> Vec<u8> {
+        notes(body).into_iter().map(|(_, _, key)| key).collect()
+    }
+
+    fn lengths(body: &str) -> Vec<u64> {
+        notes(body)
+            .into_iter()
+            .map(|(start, end, _)| end - start)
+            .collect()
+    }
+
+    fn convert(body: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        split_tunes(&format!("X:1\nK:C\n{}\n", body))[0].to_smf()
+    }
+
+    #[test]
+    fn repeat_plays_twice() {
+        assert_eq!(keys("|: C D :|"), [60, 62, 60, 62]);
+        assert_eq!(keys("C D :| E"), [60, 62, 60, 62, 64]);
+    }
+
+    #[test]
+    fn double_bar_inside_repeat_does_not_restart_it() {
+        assert_eq!(keys("|: C || D :|"), [60, 62, 60, 62]);
+    }
+
+    #[test]
+    fn sections_repeat_one_after_another() {
+        assert_eq!(keys("|: C :|: D :|"), [60, 60, 62, 62]);
+        assert_eq!(keys("C :| D :|"), [60, 60, 62, 62]);
+    }
+
+    #[test]
+    fn first_and_second_endings() {
+        assert_eq!(keys("|: C |1 D :|2 E |]"), [60, 62, 60, 64]);
+        assert_eq!(keys("|: C [1 D :| [2 E |]"), [60, 62, 60, 64]);
+        assert_eq!(keys("|: C |1 D :|2 E :|"), [60, 62, 60, 64]);
+    }
+
+    #[test]
+    fn repeat_after_endings() {
+        assert_eq!(keys("|: C |1 D :|2 E || F :|"), [60, 62, 60, 64, 65, 65]);
+        assert_eq!(keys("|: C |1 D :| E :|"), [60, 62, 60, 64, 64]);
+    }
+
+    #[test]
+    fn tuplets() {
+        assert_eq!(
+            lengths("(3CDE F"),
+            [QUARTER * 2 / 3, QUARTER * 2 / 3, QUARTER * 2 / 3, QUARTER]
+        );
+        assert_eq!(
+            lengths("(2CD E"),
+            [QUARTER * 3 / 2, QUARTER * 3 / 2, QUARTER]
+        );
+        assert_eq!(
+            lengths("(3:2:2CD E"),
+            [QUARTER * 2 / 3, QUARTER * 2 / 3, QUARTER]
+        );
+    }
+
+    #[test]
+    fn broken_rhythm() {
+        assert_eq!(lengths("C>D"), [QUARTER * 3 / 2, QUARTER / 2]);
+        assert_eq!(lengths("C<D"), [QUARTER / 2, QUARTER * 3 / 2]);
+        assert_eq!(lengths("C>>D"), [QUARTER * 7 / 4, QUARTER / 4]);
+        assert_eq!(lengths("C>>>D"), [QUARTER * 15 / 8, QUARTER / 8]);
+    }
+
+    #[test]
+    fn ties_join_notes() {
+        assert_eq!(
+            notes("C-C D"),
+            [(0, QUARTER * 2, 60), (QUARTER * 2, QUARTER * 3, 62)]
+        );
+        assert_eq!(notes("[CE]-[CE]").len(), 2);
+        // A tie to a different note doesn't join them
+        assert_eq!(notes("C-D").len(), 2);
+    }
+
+    #[test]
+    fn lengths_and_accidentals() {
+        assert_eq!(
+            lengths("C2 D/ E// F3/2"),
+            [QUARTER * 2, QUARTER / 2, QUARTER / 4, QUARTER * 3 / 2]
+        );
+        assert_eq!(keys("^C C | C _B, =B,"), [61, 61, 60, 58, 59]);
+    }
+
+    #[test]
+    fn long_broken_rhythm_is_malformed() {
+        assert!(convert("C>>>>D").is_err());
+        assert!(convert(&format!("C{}D", "<".repeat(70))).is_err());
+    }
+
+    #[test]
+    fn overflowing_length_is_malformed() {
+        assert!(convert(&format!("C{}", "/".repeat(70))).is_err());
+        assert!(convert("C/4000000000/4000000000/4000000000").is_err());
+        assert!(convert("C/2 D//").is_ok());
+    }
+
+    #[test]
+    fn long_length_is_malformed() {
+        assert!(convert("C4000000000").is_err());
+        assert!(convert("[C4000000000E]").is_err());
+        assert!(convert("[CE]4000000000").is_err());
+        assert!(convert("C2049/2").is_err());
+        assert!(convert("Z4000000000").is_err());
+        assert!(convert(&"C1024 ".repeat(8)).is_ok());
+    }
+
+    #[test]
+    fn scheduling_past_the_last_tick_is_an_error() {
+        let long = Item::Rest(Ratio(u64::MAX, 1));
+        assert!(schedule(&[long.clone(), long]).is_err());
+    }
+
+    #[test]
+    fn fine_products_keep_their_value() {
+        let product = Ratio(u64::MAX, u64::MAX - 1).mul(Ratio(u64::MAX - 3, u64::MAX - 4));
+        assert!((product.as_f64() - 1.0).abs() < 1e-9);
+        assert_eq!(
+            Ratio(u64::MAX, 3).mul(Ratio(u64::MAX, 5)),
+            Ratio(u64::MAX, 1)
+        );
+    }
+}
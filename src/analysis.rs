@@ -0,0 +1,229 @@
+use crate::{
+    arrangement::{note_name, optimal_shift, ArrangeOptions, Arrangement, STRING_COUNT},
+    picker::format_duration,
+};
+use midly::{MetaMessage, MidiMessage, Smf, TrackEventKind};
+use std::{collections::HashMap, fmt::Write};
+use tabled::{builder::Builder, settings::Style};
+
+// How many notes without a free string are listed under the report
+const LISTED_FAILURES: usize = 10;
+
+/// How well a set of tracks fits the in-game guitar
+pub struct Analysis {
+    pub note_count: usize,
+    // Lowest and highest key in the source file
+    pub range: Option<(u8, u8)>,
+    pub shift: i8,
+    // Share of notes played as written, from 0 to 100
+    pub playable: f64,
+    // Most notes starting at the same time
+    pub max_chord: usize,
+    // Most notes started within one second at the chosen speed
+    pub peak_rate: usize,
+    // In range notes that find_best_string had no string for, as
+    // (seconds into the song, note)
+    pub string_failures: Vec<(f64, u8)>,
+    // 0 to 100, playable share scaled down when the song is faster than the
+    // game can take inputs
+    pub score: f64,
+}
+
+impl Analysis {
+    pub fn new(
+        smf: &Smf,
+        tracks: &[usize],
+        options: &ArrangeOptions,
+        speed: f64,
+        input_rate: f64,
+    ) -> Self {
+        let arrangement = Arrangement::new(smf, tracks, options);
+        let notes = &arrangement.notes;
+
+        let range = notes
+            .iter()
+            .map(|note| note.key)
+            .min()
+            .zip(notes.iter().map(|note| note.key).max());
+
+        let mut chords: HashMap<u64, usize> = HashMap::new();
+        for note in notes {
+            *chords.entry(note.tick).or_default() += 1;
+        }
+        let max_chord = chords.into_values().max().unwrap_or(0);
+
+        // Notes are sorted by time, so a window of one second slides along them
+        let times: Vec<f64> = notes
+            .iter()
+            .map(|note| note.micros as f64 / speed)
+            .collect();
+        let mut peak_rate = 0;
+        let mut start = 0;
+        for (end, &time) in times.iter().enumerate() {
+            while time - times[start] >= 1_000_000.0 {
+                start += 1;
+            }
+            peak_rate = peak_rate.max(end - start + 1);
+        }
+
+        let string_failures = notes
+            .iter()
+            .filter(|note| {
+                note.position.is_none() && options.fit_note(note.key, arrangement.shift).is_some()
+            })
+            .map(|note| (note.micros as f64 / speed / 1_000_000.0, note.note))
+            .collect();
+
+        let playable = arrangement.playability();
+        let rate_factor = if peak_rate as f64 > input_rate {
+            input_rate / peak_rate as f64
+        } else {
+            1.0
+        };
+
+        Analysis {
+            note_count: notes.len(),
+            range,
+            shift: arrangement.shift,
+            playable,
+            max_chord,
+            peak_rate,
+            string_failures,
+            score: playable * rate_factor,
+        }
+    }
+
+    pub fn difficulty(&self) -> &'static str {
+        match self.score {
+            score if score >= 90.0 => "Easy",
+            score if score >= 70.0 => "Moderate",
+            score if score >= 40.0 => "Hard",
+            _ => "Unplayable",
+        }
+    }
+
+    fn row(&self, label: String) -> [String; 9] {
+        [
+            label,
+            self.note_count.to_string(),
+            self.range.map_or("-".to_string(), |(low, high)| {
+                format!("{}-{}", note_name(low), note_name(high))
+            }),
+            format!("{:+}", self.shift),
+            format!("{:.0}%", self.playable),
+            format!("{}/{}", self.max_chord, STRING_COUNT),
+            self.peak_rate.to_string(),
+            self.string_failures.len().to_string(),
+            format!("{:.0} {}", self.score, self.difficulty()),
+        ]
+    }
+}
+
+/// The notes a track starts, used to find a track's own best shift
+pub fn track_keys(smf: &Smf, track: usize) -> Vec<u8> {
+    smf.tracks[track]
+        .iter()
+        .filter_map(|event| match event.kind {
+            TrackEventKind::Midi {
+                message: MidiMessage::NoteOn { key, vel },
+                ..
+            } if vel.as_int() > 0 => Some(key.as_int()),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn track_name(smf: &Smf, track: usize) -> Option<String> {
+    smf.tracks[track].iter().find_map(|event| match event.kind {
+        TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+            Some(String::from_utf8_lossy(name).trim().to_string())
+        }
+        _ => None,
+    })
+}
+
+/// Renders the playability of every track with notes at its own best shift,
+/// then of the selected tracks as they'll be played
+pub fn report(
+    smf: &Smf,
+    tracks: &[usize],
+    options: &ArrangeOptions,
+    speed: f64,
+    min_framerate: u64,
+) -> String {
+    // Each note holds its key down for one frame
+    let input_rate = min_framerate as f64;
+
+    let mut builder = Builder::new();
+    builder.push_record([
+        "Track",
+        "Notes",
+        "Range",
+        "Shift",
+        "Playable",
+        "Max chord",
+        "Peak notes/s",
+        "No string",
+        "Score",
+    ]);
+    for track in 0..smf.tracks.len() {
+        let keys = track_keys(smf, track);
+        if keys.is_empty() {
+            continue;
+        }
+        let track_options = ArrangeOptions {
+            shift: options
+                .shift
+                .or_else(|| Some(optimal_shift(&keys, options.min_note, options.max_note).0)),
+            ..*options
+        };
+        let analysis = Analysis::new(smf, &[track], &track_options, speed, input_rate);
+        let label = match track_name(smf, track) {
+            Some(name) if !name.is_empty() => format!("{} {}", track, name),
+            _ => track.to_string(),
+        };
+        builder.push_record(analysis.row(label));
+    }
+
+    let selection = Analysis::new(smf, tracks, options, speed, input_rate);
+    builder.push_record(selection.row("Selection".to_string()));
+
+    let mut text = builder.build().with(Style::psql()).to_string();
+    let _ = write!(
+        text,
+        "\n\nThe game takes about {} notes per second at {} FPS",
+        input_rate, min_framerate
+    );
+    if selection.peak_rate as f64 > input_rate {
+        let _ = write!(
+            text,
+            ", the selection peaks at {} so some notes may be missed",
+            selection.peak_rate
+        );
+    }
+    if selection.max_chord > STRING_COUNT {
+        let _ = write!(
+            text,
+            "\nChords of up to {} notes, only {} can be played at once",
+            selection.max_chord, STRING_COUNT
+        );
+    }
+    if !selection.string_failures.is_empty() {
+        let _ = write!(
+            text,
+            "\n{} notes have no free string:",
+            selection.string_failures.len()
+        );
+        for &(secs, note) in selection.string_failures.iter().take(LISTED_FAILURES) {
+            let _ = write!(text, "\n  {} {}", format_duration(secs), note_name(note));
+        }
+        if selection.string_failures.len() > LISTED_FAILURES {
+            let _ = write!(
+                text,
+                "\n  and {} more",
+                selection.string_failures.len() - LISTED_FAILURES
+            );
+        }
+    }
+    text
+}
@@ -0,0 +1,694 @@
+use log::info;
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::collections::{HashMap, VecDeque};
+
+pub const MIN_NOTE: u8 = 40;
+pub const MAX_NOTE: u8 = 79;
+pub const STRING_COUNT: usize = 6;
+// Notes of the open strings, from the low E to the high E
+pub const OPEN_STRINGS: [u8; STRING_COUNT] = [40, 45, 50, 55, 59, 64];
+// Open string plus frets 1-15
+pub const FRET_COUNT: i32 = 16;
+// 120 BPM, the MIDI default when a file has no tempo event
+pub const DEFAULT_TEMPO: u64 = 500_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuitarPosition {
+    pub string: i32, // 0-5, where 0 is the lowest E string
+    pub fret: i32,   // 0 means open string, 1-15 for frets
+}
+
+/// String allocation state shared by the live player and the offline tools
+/// so both pick the same string for every note
+#[derive(Default)]
+pub struct Fretboard {
+    strings_played: [bool; STRING_COUNT],
+    last_string_usage: [u64; STRING_COUNT],
+    clock: u64,
+}
+
+impl Fretboard {
+    /// Frees all strings, called whenever time moves forward
+    pub fn next_chord(&mut self) {
+        self.strings_played = [false; STRING_COUNT];
+    }
+
+    pub fn find_best_string(&mut self, note: u8) -> Option<GuitarPosition> {
+        // Create a vector to hold candidates based on last usage time
+        let mut candidates: Vec<(i32, i32)> = Vec::new();
+
+        for (string_index, &open) in OPEN_STRINGS.iter().enumerate() {
+            if self.strings_played[string_index] {
+                continue; // Skip if this string has already been played
+            }
+
+            let fret = note as i32 - open as i32;
+            if (0..FRET_COUNT).contains(&fret) {
+                // Found a match, add to candidates
+                candidates.push((string_index as i32, fret));
+            }
+        }
+
+        // Sort candidates by last usage time (ascending order)
+        candidates.sort_by_key(|&(string_index, _)| self.last_string_usage[string_index as usize]);
+
+        // Select the best candidate (the one with the least last usage time)
+        let &(string_index, fret) = candidates.first()?;
+        self.clock += 1;
+        self.last_string_usage[string_index as usize] = self.clock;
+
+        Some(GuitarPosition {
+            string: string_index,
+            fret,
+        })
+    }
+
+    pub fn mark_played(&mut self, string: i32) {
+        self.strings_played[string as usize] = true;
+    }
+}
+
+/// What happens to notes that are still outside the range after the shift
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RangePolicy {
+    Clamp,
+    Drop,
+    Octave,
+}
+
+impl RangePolicy {
+    pub const ALL: [RangePolicy; 3] = [RangePolicy::Clamp, RangePolicy::Drop, RangePolicy::Octave];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RangePolicy::Clamp => "Clamp to the nearest playable note",
+            RangePolicy::Drop => "Skip them",
+            RangePolicy::Octave => "Move them by octaves into range",
+        }
+    }
+
+    /// Name stored in the database
+    pub fn name(self) -> &'static str {
+        match self {
+            RangePolicy::Clamp => "clamp",
+            RangePolicy::Drop => "drop",
+            RangePolicy::Octave => "octave",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "drop" => RangePolicy::Drop,
+            "octave" => RangePolicy::Octave,
+            _ => RangePolicy::Clamp,
+        }
+    }
+}
+
+/// How a song's notes are fitted onto the guitar
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArrangeOptions {
+    // None picks the shift that keeps the most notes in range
+    pub shift: Option<i8>,
+    pub policy: RangePolicy,
+    // Notes the song is fitted into, within MIN_NOTE and MAX_NOTE
+    pub min_note: u8,
+    pub max_note: u8,
+}
+
+impl Default for ArrangeOptions {
+    fn default() -> Self {
+        ArrangeOptions {
+            shift: None,
+            policy: RangePolicy::Clamp,
+            min_note: MIN_NOTE,
+            max_note: MAX_NOTE,
+        }
+    }
+}
+
+impl ArrangeOptions {
+    /// Applies the song shift and fits the result into the range, None if the
+    /// note should be skipped
+    pub fn fit_note(&self, key: u8, shift: i8) -> Option<u8> {
+        let (min, max) = (self.min_note as i16, self.max_note as i16);
+        let mut note = key as i16 + shift as i16;
+        if (min..=max).contains(&note) {
+            return Some(note as u8);
+        }
+        match self.policy {
+            RangePolicy::Clamp => {}
+            RangePolicy::Drop => return None,
+            RangePolicy::Octave => {
+                if note < min {
+                    note += (min - note + 11) / 12 * 12;
+                } else {
+                    note -= (note - max + 11) / 12 * 12;
+                }
+            }
+        }
+        // Ranges narrower than an octave can still miss
+        Some(note.clamp(min, max) as u8)
+    }
+}
+
+pub fn get_notes(smf: &Smf) -> Vec<u8> {
+    smf.tracks
+        .iter()
+        .flatten()
+        .filter_map(|event| match event.kind {
+            TrackEventKind::Midi { ref message, .. } => Some(message),
+            _ => None,
+        })
+        .filter_map(|message| match message {
+            MidiMessage::NoteOn { key, .. } => Some(key.as_int()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the shift that keeps the most notes in range and how many that is
+pub fn optimal_shift(notes: &[u8], min_note: u8, max_note: u8) -> (i8, usize) {
+    let mut best_shift: i16 = 0;
+    let mut max_playable_notes = 0;
+
+    for shift in -127..=127i16 {
+        let playable_notes = notes
+            .iter()
+            .filter(|&&n| {
+                (n as i16 + shift) >= min_note as i16 && (n as i16 + shift) <= max_note as i16
+            })
+            .count();
+
+        // The best shift is the one with the most playable notes that is closest to 0
+        if playable_notes > max_playable_notes
+            || (playable_notes == max_playable_notes && shift.abs() < best_shift.abs())
+        {
+            max_playable_notes = playable_notes;
+            best_shift = shift;
+        }
+    }
+
+    (best_shift as i8, max_playable_notes)
+}
+
+pub fn calculate_optimal_shift(notes: &[u8], options: &ArrangeOptions) -> i8 {
+    if let Some(shift) = options.shift {
+        info!("Saved shift: {}", shift);
+        return shift;
+    }
+
+    let total_notes = notes.len();
+    let (best_shift, max_playable_notes) = optimal_shift(notes, options.min_note, options.max_note);
+
+    info!("Optimal shift: {}", best_shift);
+    info!(
+        "Total notes: {} | Playable notes: {} | Clamped notes {} : {}% playable",
+        total_notes,
+        max_playable_notes,
+        total_notes - max_playable_notes,
+        max_playable_notes as f32 / total_notes as f32 * 100.0
+    );
+
+    best_shift
+}
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Scientific pitch name such as "E2" for MIDI key 40
+pub fn note_name(key: u8) -> String {
+    format!("{}{}", NOTE_NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+/// Converts ticks to wall clock time using every tempo change in the file
+pub struct TempoMap {
+    timing: Timing,
+    // (tick, micros elapsed at that tick, micros per beat from that tick)
+    changes: Vec<(u64, u64, u64)>,
+}
+
+impl TempoMap {
+    pub fn new(smf: &Smf) -> Self {
+        let mut tempos = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = event.kind {
+                    tempos.push((tick, tempo.as_int() as u64));
+                }
+            }
+        }
+        tempos.sort_by_key(|&(tick, _)| tick);
+
+        let mut map = TempoMap {
+            timing: smf.header.timing,
+            changes: vec![(0, 0, DEFAULT_TEMPO)],
+        };
+        for (tick, tempo) in tempos {
+            let micros = map.micros_at(tick);
+            map.changes.push((tick, micros, tempo));
+        }
+        map
+    }
+
+    pub fn micros_at(&self, tick: u64) -> u64 {
+        match self.timing {
+            Timing::Metrical(ppq) => {
+                let &(start_tick, start_micros, tempo) = self
+                    .changes
+                    .iter()
+                    .rev()
+                    .find(|(change_tick, _, _)| *change_tick <= tick)
+                    .unwrap_or(&self.changes[0]);
+                start_micros + (tick - start_tick) * tempo / ppq.as_int().max(1) as u64
+            }
+            Timing::Timecode(fps, subframe) => {
+                let ticks_per_second = fps.as_f32() as f64 * subframe as f64;
+                (tick as f64 * 1_000_000.0 / ticks_per_second) as u64
+            }
+        }
+    }
+
+    /// The tick playing `micros` into the song, the inverse of micros_at
+    pub fn tick_at(&self, micros: u64) -> u64 {
+        match self.timing {
+            Timing::Metrical(ppq) => {
+                let &(start_tick, start_micros, tempo) = self
+                    .changes
+                    .iter()
+                    .rev()
+                    .find(|(_, change_micros, _)| *change_micros <= micros)
+                    .unwrap_or(&self.changes[0]);
+                start_tick + (micros - start_micros) * ppq.as_int().max(1) as u64 / tempo.max(1)
+            }
+            Timing::Timecode(fps, subframe) => {
+                let ticks_per_second = fps.as_f32() as f64 * subframe as f64;
+                (micros as f64 * ticks_per_second / 1_000_000.0) as u64
+            }
+        }
+    }
+}
+
+struct SourceNote {
+    tick: u64,
+    track: usize,
+    // Position of the event in its track, keeps simultaneous notes in file order
+    index: usize,
+    key: u8,
+    velocity: u8,
+    end_tick: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ArrangedNote {
+    pub tick: u64,
+    pub micros: u64,
+    // When the source note is released
+    pub end_tick: u64,
+    pub end_micros: u64,
+    pub track: usize,
+    // The key in the source file
+    pub key: u8,
+    pub velocity: u8,
+    // The key after shifting and fitting into the range
+    pub note: u8,
+    // Not played as written, either moved into the range or skipped
+    pub clamped: bool,
+    // None when the note was skipped or every string that could play it was
+    // already in use
+    pub position: Option<GuitarPosition>,
+}
+
+/// The notes of the selected tracks as the guitar will perform them
+pub struct Arrangement {
+    pub shift: i8,
+    pub notes: Vec<ArrangedNote>,
+    pub length_micros: u64,
+}
+
+impl Arrangement {
+    pub fn new(smf: &Smf, tracks: &[usize], options: &ArrangeOptions) -> Self {
+        let shift = options.shift.unwrap_or_else(|| {
+            optimal_shift(&get_notes(smf), options.min_note, options.max_note).0
+        });
+        let tempo_map = TempoMap::new(smf);
+
+        let mut source: Vec<SourceNote> = Vec::new();
+        let mut last_tick = 0;
+        for (track_num, track) in smf.tracks.iter().enumerate() {
+            let should_play = tracks.contains(&track_num);
+            let mut open: HashMap<(u8, u8), VecDeque<usize>> = HashMap::new();
+            let mut tick = 0;
+            for (index, event) in track.iter().enumerate() {
+                tick += event.delta.as_int() as u64;
+                let TrackEventKind::Midi { channel, message } = event.kind else {
+                    continue;
+                };
+                if !should_play {
+                    continue;
+                }
+                let channel = channel.as_int();
+                match message {
+                    MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                        open.entry((channel, key.as_int()))
+                            .or_default()
+                            .push_back(source.len());
+                        source.push(SourceNote {
+                            tick,
+                            track: track_num,
+                            index,
+                            key: key.as_int(),
+                            velocity: vel.as_int(),
+                            end_tick: None,
+                        });
+                    }
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        if let Some(started) = open
+                            .get_mut(&(channel, key.as_int()))
+                            .and_then(|queue| queue.pop_front())
+                        {
+                            source[started].end_tick = Some(tick);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            last_tick = last_tick.max(tick);
+        }
+        source.sort_by_key(|note| (note.tick, note.track, note.index));
+
+        let mut fretboard = Fretboard::default();
+        let mut chord_tick = None;
+        let notes = source
+            .into_iter()
+            .map(|source_note| {
+                let SourceNote {
+                    tick,
+                    track,
+                    key,
+                    velocity,
+                    end_tick,
+                    ..
+                } = source_note;
+                let end_tick = end_tick.unwrap_or(tick);
+                if chord_tick != Some(tick) {
+                    fretboard.next_chord();
+                    chord_tick = Some(tick);
+                }
+                let fitted = options.fit_note(key, shift);
+                let position = fitted.and_then(|note| fretboard.find_best_string(note));
+                if let Some(position) = position {
+                    fretboard.mark_played(position.string);
+                }
+                let note =
+                    fitted.unwrap_or_else(|| (key as i16 + shift as i16).clamp(0, 127) as u8);
+                ArrangedNote {
+                    tick,
+                    micros: tempo_map.micros_at(tick),
+                    end_tick,
+                    end_micros: tempo_map.micros_at(end_tick),
+                    track,
+                    key,
+                    velocity,
+                    note,
+                    clamped: fitted.is_none_or(|note| key as i16 + shift as i16 != note as i16),
+                    position,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let length_micros = notes
+            .iter()
+            .map(|note| note.end_micros)
+            .max()
+            .unwrap_or(0)
+            .max(tempo_map.micros_at(last_tick));
+
+        Arrangement {
+            shift,
+            notes,
+            length_micros,
+        }
+    }
+
+    pub fn dropped_notes(&self) -> usize {
+        self.notes.iter().filter(|n| n.position.is_none()).count()
+    }
+
+    pub fn clamped_notes(&self) -> usize {
+        self.notes.iter().filter(|n| n.clamped).count()
+    }
+
+    /// Share of notes played as written (after the shift), from 0 to 100
+    pub fn playability(&self) -> f64 {
+        if self.notes.is_empty() {
+            return 0.0;
+        }
+        let played = self
+            .notes
+            .iter()
+            .filter(|n| !n.clamped && n.position.is_some())
+            .count();
+        played as f64 / self.notes.len() as f64 * 100.0
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::midi_export::to_track;
+    use midly::{num::u24, Format, Fps, Header};
+
+    pub(crate) const PPQ: u16 = 480;
+
+    /// A track of (tick, key, length in ticks) notes
+    pub(crate) fn notes_track(notes: &[(u64, u8, u64)]) -> Vec<(u64, TrackEventKind<'static>)> {
+        let mut events = Vec::new();
+        for &(tick, key, length) in notes {
+            let message = |on| TrackEventKind::Midi {
+                channel: 0.into(),
+                message: if on {
+                    MidiMessage::NoteOn {
+                        key: key.into(),
+                        vel: 100.into(),
+                    }
+                } else {
+                    MidiMessage::NoteOff {
+                        key: key.into(),
+                        vel: 0.into(),
+                    }
+                },
+            };
+            events.push((tick + length, false, message(false)));
+            events.push((tick, true, message(true)));
+        }
+        events.sort_by_key(|&(tick, is_on, _)| (tick, is_on));
+        events
+            .into_iter()
+            .map(|(tick, _, kind)| (tick, kind))
+            .collect()
+    }
+
+    pub(crate) fn smf(
+        timing: Timing,
+        tracks: Vec<Vec<(u64, TrackEventKind<'static>)>>,
+    ) -> Smf<'static> {
+        Smf {
+            header: Header::new(Format::Parallel, timing),
+            tracks: tracks.into_iter().map(to_track).collect(),
+        }
+    }
+
+    /// A one track song at 120 BPM
+    pub(crate) fn song(notes: &[(u64, u8, u64)]) -> Smf<'static> {
+        smf(Timing::Metrical(PPQ.into()), vec![notes_track(notes)])
+    }
+
+    fn tempo(tick: u64, micros_per_beat: u32) -> (u64, TrackEventKind<'static>) {
+        (
+            tick,
+            TrackEventKind::Meta(MetaMessage::Tempo(u24::new(micros_per_beat))),
+        )
+    }
+
+    fn options(policy: RangePolicy) -> ArrangeOptions {
+        ArrangeOptions {
+            policy,
+            ..ArrangeOptions::default()
+        }
+    }
+
+    #[test]
+    fn clamp_moves_notes_to_the_nearest_limit() {
+        let clamp = options(RangePolicy::Clamp);
+        assert_eq!(clamp.fit_note(MIN_NOTE, 0), Some(MIN_NOTE));
+        assert_eq!(clamp.fit_note(MAX_NOTE, 0), Some(MAX_NOTE));
+        assert_eq!(clamp.fit_note(MIN_NOTE - 1, 0), Some(MIN_NOTE));
+        assert_eq!(clamp.fit_note(MAX_NOTE + 1, 0), Some(MAX_NOTE));
+        assert_eq!(clamp.fit_note(0, 0), Some(MIN_NOTE));
+        assert_eq!(clamp.fit_note(127, 0), Some(MAX_NOTE));
+        // The shift is applied first
+        assert_eq!(clamp.fit_note(MIN_NOTE - 2, 2), Some(MIN_NOTE));
+        assert_eq!(clamp.fit_note(MAX_NOTE, 1), Some(MAX_NOTE));
+        assert_eq!(clamp.fit_note(127, 127), Some(MAX_NOTE));
+        assert_eq!(clamp.fit_note(0, -128), Some(MIN_NOTE));
+    }
+
+    #[test]
+    fn drop_skips_notes_outside_the_range() {
+        let drop = options(RangePolicy::Drop);
+        assert_eq!(drop.fit_note(MIN_NOTE, 0), Some(MIN_NOTE));
+        assert_eq!(drop.fit_note(MAX_NOTE, 0), Some(MAX_NOTE));
+        assert_eq!(drop.fit_note(MIN_NOTE - 1, 0), None);
+        assert_eq!(drop.fit_note(MAX_NOTE + 1, 0), None);
+        assert_eq!(drop.fit_note(MAX_NOTE, 1), None);
+        assert_eq!(drop.fit_note(MAX_NOTE + 1, -1), Some(MAX_NOTE));
+    }
+
+    #[test]
+    fn octave_moves_notes_by_whole_octaves() {
+        let octave = options(RangePolicy::Octave);
+        assert_eq!(octave.fit_note(MIN_NOTE, 0), Some(MIN_NOTE));
+        assert_eq!(octave.fit_note(MAX_NOTE, 0), Some(MAX_NOTE));
+        assert_eq!(octave.fit_note(MIN_NOTE - 1, 0), Some(MIN_NOTE + 11));
+        assert_eq!(octave.fit_note(MIN_NOTE - 12, 0), Some(MIN_NOTE));
+        assert_eq!(octave.fit_note(MIN_NOTE - 13, 0), Some(MIN_NOTE + 11));
+        assert_eq!(octave.fit_note(MAX_NOTE + 1, 0), Some(MAX_NOTE - 11));
+        assert_eq!(octave.fit_note(MAX_NOTE + 12, 0), Some(MAX_NOTE));
+        assert_eq!(octave.fit_note(MAX_NOTE + 13, 0), Some(MAX_NOTE - 11));
+
+        // No octave of 70 lands in a range narrower than an octave, so it's clamped
+        let narrow = ArrangeOptions {
+            min_note: 60,
+            max_note: 64,
+            ..octave
+        };
+        assert_eq!(narrow.fit_note(70, 0), Some(60));
+        assert_eq!(narrow.fit_note(74, 0), Some(62));
+    }
+
+    #[test]
+    fn strings_are_used_least_recently_first() {
+        let mut fretboard = Fretboard::default();
+        // A2 is the 5th fret of the low E or the open A
+        let a2 = OPEN_STRINGS[1];
+        let first = fretboard.find_best_string(a2).unwrap();
+        assert_eq!(first, GuitarPosition { string: 0, fret: 5 });
+        fretboard.next_chord();
+        let second = fretboard.find_best_string(a2).unwrap();
+        assert_eq!(second, GuitarPosition { string: 1, fret: 0 });
+        fretboard.next_chord();
+        assert_eq!(fretboard.find_best_string(a2), Some(first));
+    }
+
+    #[test]
+    fn strings_played_in_a_chord_are_taken() {
+        let mut fretboard = Fretboard::default();
+        let a2 = OPEN_STRINGS[1];
+        for string in 0..2 {
+            let position = fretboard.find_best_string(a2).unwrap();
+            assert_eq!(position.string, string);
+            fretboard.mark_played(position.string);
+        }
+        // Only the two lowest strings reach A2
+        assert_eq!(fretboard.find_best_string(a2), None);
+        fretboard.next_chord();
+        assert!(fretboard.find_best_string(a2).is_some());
+    }
+
+    #[test]
+    fn only_notes_on_the_fretboard_find_a_string() {
+        let mut fretboard = Fretboard::default();
+        assert_eq!(fretboard.find_best_string(MIN_NOTE - 1), None);
+        assert_eq!(fretboard.find_best_string(MAX_NOTE + 1), None);
+        assert_eq!(
+            fretboard.find_best_string(MIN_NOTE),
+            Some(GuitarPosition { string: 0, fret: 0 })
+        );
+        assert_eq!(
+            fretboard.find_best_string(MAX_NOTE),
+            Some(GuitarPosition {
+                string: STRING_COUNT as i32 - 1,
+                fret: FRET_COUNT - 1
+            })
+        );
+    }
+
+    #[test]
+    fn optimal_shift_keeps_the_most_notes_closest_to_zero() {
+        assert_eq!(optimal_shift(&[50, 60, 70], MIN_NOTE, MAX_NOTE), (0, 3));
+        assert_eq!(optimal_shift(&[30, 31], MIN_NOTE, MAX_NOTE), (10, 2));
+        assert_eq!(optimal_shift(&[85, 90], MIN_NOTE, MAX_NOTE), (-11, 2));
+        // Wider than the range, the most notes that fit win
+        assert_eq!(optimal_shift(&[20, 30, 80], MIN_NOTE, MAX_NOTE), (20, 2));
+        assert_eq!(optimal_shift(&[], MIN_NOTE, MAX_NOTE), (0, 0));
+    }
+
+    #[test]
+    fn tempo_map_follows_tempo_changes() {
+        let ppq = PPQ as u64;
+        let conductor = vec![tempo(0, 500_000), tempo(ppq * 2, 250_000)];
+        let map = TempoMap::new(&smf(Timing::Metrical(PPQ.into()), vec![conductor]));
+
+        assert_eq!(map.micros_at(0), 0);
+        assert_eq!(map.micros_at(ppq), 500_000);
+        assert_eq!(map.micros_at(ppq * 2), 1_000_000);
+        assert_eq!(map.micros_at(ppq * 3), 1_250_000);
+        assert_eq!(map.tick_at(500_000), ppq);
+        assert_eq!(map.tick_at(1_000_000), ppq * 2);
+        assert_eq!(map.tick_at(1_250_000), ppq * 3);
+        for tick in [0, ppq / 2, ppq * 5 / 2, ppq * 4] {
+            assert_eq!(map.tick_at(map.micros_at(tick)), tick);
+        }
+    }
+
+    #[test]
+    fn tempo_map_defaults_to_120_bpm() {
+        let map = TempoMap::new(&song(&[]));
+        assert_eq!(map.micros_at(PPQ as u64), DEFAULT_TEMPO);
+        assert_eq!(map.tick_at(DEFAULT_TEMPO * 4), PPQ as u64 * 4);
+    }
+
+    #[test]
+    fn timecode_ticks_are_fixed_lengths_of_time() {
+        // 25 frames of 40 ticks, a millisecond each
+        let timing = Timing::Timecode(Fps::Fps25, 40);
+        let map = TempoMap::new(&smf(timing, vec![vec![tempo(0, 250_000)]]));
+        assert_eq!(map.micros_at(1000), 1_000_000);
+        assert_eq!(map.micros_at(2500), 2_500_000);
+        assert_eq!(map.tick_at(2_500_000), 2500);
+
+        let song = smf(timing, vec![notes_track(&[(1000, 60, 500)])]);
+        let arrangement = Arrangement::new(&song, &[0], &ArrangeOptions::default());
+        assert_eq!(arrangement.notes[0].micros, 1_000_000);
+        assert_eq!(arrangement.notes[0].end_micros, 1_500_000);
+        assert_eq!(arrangement.length_micros, 1_500_000);
+    }
+
+    #[test]
+    fn arrangement_plays_the_selected_tracks() {
+        let ppq = PPQ as u64;
+        let song = smf(
+            Timing::Metrical(PPQ.into()),
+            vec![
+                notes_track(&[(0, 60, ppq)]),
+                notes_track(&[(0, 30, ppq), (ppq, 64, ppq)]),
+            ],
+        );
+        let drop = ArrangeOptions {
+            shift: Some(0),
+            ..options(RangePolicy::Drop)
+        };
+        let arrangement = Arrangement::new(&song, &[1], &drop);
+        let keys: Vec<u8> = arrangement.notes.iter().map(|note| note.key).collect();
+        assert_eq!(keys, [30, 64]);
+        assert!(arrangement.notes[0].clamped);
+        assert_eq!(arrangement.notes[0].position, None);
+        assert_eq!(arrangement.notes[1].micros, 500_000);
+        assert_eq!(arrangement.dropped_notes(), 1);
+        assert_eq!(arrangement.playability(), 50.0);
+    }
+}
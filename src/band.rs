@@ -0,0 +1,895 @@
+use indicatif::MultiProgress;
+use log::{debug, error, info, warn};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Ipv4Addr, Shutdown, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, sleep},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use xcap::Window;
+
+use crate::{
+    arrangement::{ArrangeOptions, RangePolicy},
+    history,
+    hotkeys::{HotkeyAction, Hotkeys},
+    library,
+    profiles::SongProfile,
+    queue::QueuedSong,
+    remote::{Command, Remote},
+    songs::SongSource,
+    webfishing_player::{PlayEnd, PlayerSettings, WebfishingPlayer},
+};
+
+pub const DEFAULT_PORT: u16 = 7880;
+// Round trips measured to find the clock offset, the quickest one wins
+const SYNC_ROUNDS: usize = 8;
+// Lead time for every member to hear about the start before it happens
+const START_DELAY: Duration = Duration::from_secs(3);
+// How long members get to load a song before it starts without them
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Longest line a member may send, they only say hello, ping and ready
+const MAX_MEMBER_MESSAGE: u64 = 4096;
+// Longest line from the conductor, songs are sent as hex so this allows an
+// 8 MB MIDI file
+const MAX_CONDUCTOR_MESSAGE: u64 = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy)]
+pub enum Role {
+    Conductor,
+    Member,
+}
+
+impl Role {
+    pub const ALL: [Role; 2] = [Role::Conductor, Role::Member];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Conductor => "Conduct: choose the songs and who plays what",
+            Role::Member => "Join a conductor",
+        }
+    }
+}
+
+/// What the conductor does between songs
+#[derive(Clone, Copy)]
+pub enum ConductorAction {
+    ChooseSong,
+    Refresh,
+    Stop,
+}
+
+impl ConductorAction {
+    pub const ALL: [ConductorAction; 3] = [
+        ConductorAction::ChooseSong,
+        ConductorAction::Refresh,
+        ConductorAction::Stop,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ConductorAction::ChooseSong => "Choose the next song",
+            ConductorAction::Refresh => "Check who joined",
+            ConductorAction::Stop => "Stop conducting",
+        }
+    }
+}
+
+/// One line of JSON between the conductor and a member. Times are
+/// microseconds since the Unix epoch on the conductor's clock.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    // `code` is the band code the conductor was given
+    Hello {
+        name: String,
+        code: String,
+    },
+    Ping {
+        sent: i64,
+    },
+    Pong {
+        sent: i64,
+        conductor: i64,
+    },
+    // The song as hex encoded MIDI and the tracks this member plays from it
+    Song {
+        title: String,
+        midi: String,
+        tracks: Vec<usize>,
+        shift: Option<i8>,
+        policy: String,
+        min_note: u8,
+        max_note: u8,
+        speed: f64,
+    },
+    Ready,
+    Start {
+        at: i64,
+    },
+    Pause,
+    // The conductor was at position_secs at the time `at`
+    Resume {
+        position_secs: f64,
+        at: i64,
+    },
+    Seek {
+        position_secs: f64,
+        at: i64,
+    },
+    Stop,
+}
+
+struct Member {
+    id: u64,
+    name: String,
+    stream: TcpStream,
+    ready: bool,
+}
+
+/// Hands out songs to the members that joined and starts them together
+pub struct Conductor {
+    members: Arc<Mutex<Vec<Member>>>,
+    closed: Arc<AtomicBool>,
+    port: u16,
+    code: String,
+}
+
+impl Conductor {
+    /// Listens for members on every network interface. Only members that
+    /// send the band code get in.
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        let port = listener.local_addr()?.port();
+        // Polled so the port is freed once conducting stops
+        listener.set_nonblocking(true)?;
+        // From the OS, the code is all that keeps the rest of the network out
+        let mut bytes = [0u8; 8];
+        getrandom::getrandom(&mut bytes).map_err(io::Error::other)?;
+        let code = to_hex(&bytes);
+
+        let members = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let shared_members = Arc::clone(&members);
+        let shared_closed = Arc::clone(&closed);
+        let shared_code = code.clone();
+        thread::spawn(move || {
+            let mut next_id = 0;
+            while !shared_closed.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let id = next_id;
+                        next_id += 1;
+                        let members = Arc::clone(&shared_members);
+                        let code = shared_code.clone();
+                        thread::spawn(move || serve_member(id, stream, &code, &members));
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => sleep(POLL_INTERVAL),
+                    Err(e) => debug!("Band connection failed: {}", e),
+                }
+            }
+        });
+
+        Ok(Conductor {
+            members,
+            closed,
+            port,
+            code,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// The ids and names of everyone connected now
+    pub fn members(&self) -> Vec<(u64, String)> {
+        self.members
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|member| (member.id, member.name.clone()))
+            .collect()
+    }
+
+    /// Sends every member the song and the tracks they play, then waits for
+    /// them to load it. Returns how many are ready.
+    pub fn prepare(
+        &self,
+        title: &str,
+        midi: &[u8],
+        assignments: &[(u64, Vec<usize>)],
+        options: &ArrangeOptions,
+        speed: f64,
+    ) -> usize {
+        let midi = to_hex(midi);
+        for member in self.members.lock().unwrap().iter_mut() {
+            member.ready = false;
+            // Members who joined after the parts were handed out sit this one out
+            let tracks = assignments
+                .iter()
+                .find(|(id, _)| *id == member.id)
+                .map(|(_, tracks)| tracks.clone())
+                .unwrap_or_default();
+            let song = Message::Song {
+                title: title.to_string(),
+                midi: midi.clone(),
+                tracks,
+                shift: options.shift,
+                policy: options.policy.name().to_string(),
+                min_note: options.min_note,
+                max_note: options.max_note,
+                speed,
+            };
+            if let Err(e) = send(&member.stream, &song) {
+                warn!("Couldn't send the song to {}: {}", member.name, e);
+            }
+        }
+
+        let deadline = Instant::now() + READY_TIMEOUT;
+        loop {
+            let members = self.members.lock().unwrap();
+            let waiting: Vec<&str> = members
+                .iter()
+                .filter(|member| !member.ready)
+                .map(|member| member.name.as_str())
+                .collect();
+            if waiting.is_empty() {
+                return members.len();
+            }
+            if Instant::now() >= deadline {
+                warn!("Starting without {}", waiting.join(", "));
+                return members.len() - waiting.len();
+            }
+            drop(members);
+            sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Tells everyone when to start, returns that instant
+    pub fn count_in(&self) -> Instant {
+        self.broadcast(&Message::Start {
+            at: now_micros() + START_DELAY.as_micros() as i64,
+        });
+        Instant::now() + START_DELAY
+    }
+
+    /// Plays the conductor's own part, passing pauses, seeks and stops on to
+    /// every member. Returns how it ended and the notes dropped, None when the
+    /// player couldn't be created.
+    #[allow(clippy::too_many_arguments)]
+    pub fn play<'a>(
+        &self,
+        settings: PlayerSettings<'a>,
+        start: Instant,
+        window: &'a Window,
+        input_sleep_duration: u64,
+        multi: &'a MultiProgress,
+        remote: &'a Remote,
+        hotkeys: &'a Hotkeys,
+    ) -> Option<(PlayEnd, usize)> {
+        let events = remote.subscribe();
+        let done = AtomicBool::new(false);
+        let done = &done;
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    let Ok(event) = events.recv_timeout(POLL_INTERVAL) else {
+                        continue;
+                    };
+                    let Ok(event) = serde_json::from_str::<Value>(&event) else {
+                        continue;
+                    };
+                    let position_secs = event["position_secs"]
+                        .as_f64()
+                        .unwrap_or_else(|| remote.status().position_secs);
+                    let message = match event["event"].as_str() {
+                        Some("paused") => Message::Pause,
+                        Some("resumed") => Message::Resume {
+                            position_secs,
+                            at: now_micros(),
+                        },
+                        Some("seeked") => Message::Seek {
+                            position_secs,
+                            at: now_micros(),
+                        },
+                        _ => continue,
+                    };
+                    self.broadcast(&message);
+                }
+            });
+
+            let played = play_at(
+                settings,
+                start,
+                window,
+                input_sleep_duration,
+                multi,
+                remote,
+                hotkeys,
+            );
+            done.store(true, Ordering::Relaxed);
+            if !matches!(played, Some((PlayEnd::Finished, _))) {
+                self.broadcast(&Message::Stop);
+            }
+            played
+        })
+    }
+
+    fn broadcast(&self, message: &Message) {
+        for member in self.members.lock().unwrap().iter() {
+            if let Err(e) = send(&member.stream, message) {
+                debug!("Couldn't reach {}: {}", member.name, e);
+            }
+        }
+    }
+}
+
+impl Drop for Conductor {
+    // Members see the band end when their connection closes
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        for member in self.members.lock().unwrap().drain(..) {
+            let _ = member.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// Answers a member's clock pings and notes when it's ready, until it leaves
+fn serve_member(id: u64, stream: TcpStream, code: &str, members: &Mutex<Vec<Member>>) {
+    // Only the listener is polled
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_nodelay(true);
+    let Ok(mut reader) = stream.try_clone().map(BufReader::new) else {
+        return;
+    };
+    let Ok(Some(Message::Hello {
+        name,
+        code: member_code,
+    })) = read(&mut reader, MAX_MEMBER_MESSAGE)
+    else {
+        return;
+    };
+    if member_code.trim() != code {
+        warn!("Turned {} away, their band code is wrong", name);
+        return;
+    }
+    info!("{} joined the band", name);
+    members.lock().unwrap().push(Member {
+        id,
+        name: name.clone(),
+        stream,
+        ready: false,
+    });
+
+    while let Ok(Some(message)) = read(&mut reader, MAX_MEMBER_MESSAGE) {
+        let mut members = members.lock().unwrap();
+        let Some(member) = members.iter_mut().find(|member| member.id == id) else {
+            break;
+        };
+        match message {
+            // Writes go through the lock so they don't interleave with broadcasts
+            Message::Ping { sent } => {
+                let pong = Message::Pong {
+                    sent,
+                    conductor: now_micros(),
+                };
+                if send(&member.stream, &pong).is_err() {
+                    break;
+                }
+            }
+            Message::Ready => member.ready = true,
+            message => debug!("Unexpected message from {}: {:?}", name, message),
+        }
+    }
+
+    members.lock().unwrap().retain(|member| member.id != id);
+    info!("{} left the band", name);
+}
+
+// What the member's connection thread hands to the player
+enum Cue {
+    Song(Part),
+    Start(Instant),
+}
+
+// A member's share of a song
+struct Part {
+    title: String,
+    midi: Vec<u8>,
+    tracks: Vec<usize>,
+    options: ArrangeOptions,
+    speed: f64,
+}
+
+/// Joins a conductor with its band code and plays the parts it hands out,
+/// until the stop key is pressed between songs or the conductor stops. Every
+/// part played is recorded in the history.
+#[allow(clippy::too_many_arguments)]
+pub fn join(
+    conn: &Connection,
+    address: &str,
+    code: &str,
+    name: &str,
+    window: &Window,
+    input_sleep_duration: u64,
+    multi: &MultiProgress,
+    remote: &Arc<Remote>,
+    hotkeys: &Hotkeys,
+) -> Result<(), Box<dyn Error>> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    send(
+        &stream,
+        &Message::Hello {
+            name: name.to_string(),
+            code: code.to_string(),
+        },
+    )?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (offset, round_trip) = sync_clock(&stream, &mut reader).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => {
+            io::Error::new(e.kind(), "the conductor hung up, check the band code")
+        }
+        _ => e,
+    })?;
+    info!(
+        "Joined the band at {}, clock offset {:.1}ms, round trip {:.1}ms",
+        address,
+        offset as f64 / 1000.0,
+        round_trip as f64 / 1000.0
+    );
+
+    let (cues, receiver) = mpsc::channel();
+    let connection = stream.try_clone()?;
+    let follower_remote = Arc::clone(remote);
+    thread::spawn(move || {
+        if let Err(e) = follow(stream, reader, offset, cues, &follower_remote) {
+            debug!("Band connection failed: {}", e);
+        }
+    });
+
+    let mut next_part: Option<Part> = None;
+    let waiting = format!(
+        "Waiting for the conductor, {} to leave the band",
+        hotkeys.bindings().key(HotkeyAction::Interrupt)
+    );
+    hotkeys.clear();
+    println!("{}", waiting);
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Cue::Song(part)) => {
+                info!("Next song: {} - tracks {:?}", part.title, part.tracks);
+                next_part = Some(part);
+            }
+            Ok(Cue::Start(start)) => {
+                let Some(part) = next_part.take() else {
+                    continue;
+                };
+                let queued = part_song(conn, &part);
+                let mut settings = match PlayerSettings::new(part.midi, false) {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        error!("Failed to parse MIDI data: {}", e);
+                        continue;
+                    }
+                };
+                settings.tracks = Some(part.tracks);
+                settings.options = part.options;
+                settings.speed = part.speed;
+
+                remote.start_song(part.title.clone(), |_| {});
+                let started = SystemTime::now();
+                let played = play_at(
+                    settings,
+                    start,
+                    window,
+                    input_sleep_duration,
+                    multi,
+                    remote,
+                    hotkeys,
+                );
+                remote.end_song();
+                if let Some((end, dropped_notes)) = played {
+                    info!("{} ended: {:?}", part.title, end);
+                    let completed = end == PlayEnd::Finished;
+                    if let Err(e) =
+                        history::record(conn, &queued, started, completed, dropped_notes)
+                    {
+                        error!("Failed to save play history: {}", e);
+                    }
+                }
+                println!("{}", waiting);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                while let Some(action) = hotkeys.try_next() {
+                    if matches!(action, HotkeyAction::Interrupt | HotkeyAction::StopQueue) {
+                        info!("Left the band");
+                        let _ = connection.shutdown(Shutdown::Both);
+                        return Ok(());
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                info!("The conductor ended the band");
+                return Ok(());
+            }
+        }
+    }
+}
+
+// The part as a song for the history, the library's copy when there is one
+fn part_song(conn: &Connection, part: &Part) -> QueuedSong {
+    let song = library::find_by_hash(conn, &library::content_hash(&part.midi))
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| SongSource::file(PathBuf::from(&part.title)));
+    QueuedSong {
+        song,
+        profile: SongProfile {
+            tracks: Some(part.tracks.clone()),
+            speed: part.speed,
+            options: part.options,
+            ..Default::default()
+        },
+    }
+}
+
+// Turns the conductor's messages into cues for the player and commands for
+// the song playing
+fn follow(
+    stream: TcpStream,
+    mut reader: BufReader<TcpStream>,
+    mut offset: i64,
+    cues: Sender<Cue>,
+    remote: &Remote,
+) -> io::Result<()> {
+    let mut speed = 1.0;
+    while let Some(message) = read(&mut reader, MAX_CONDUCTOR_MESSAGE)? {
+        let cue = match message {
+            Message::Song {
+                title,
+                midi,
+                tracks,
+                shift,
+                policy,
+                min_note,
+                max_note,
+                speed: song_speed,
+            } => {
+                let Some(midi) = from_hex(&midi) else {
+                    warn!(
+                        "The conductor sent {} in a form this version can't read",
+                        title
+                    );
+                    continue;
+                };
+                // Clocks drift, so measure again before every song
+                (offset, _) = sync_clock(&stream, &mut reader)?;
+                speed = song_speed;
+                send(&stream, &Message::Ready)?;
+                Cue::Song(Part {
+                    title,
+                    midi,
+                    tracks,
+                    options: ArrangeOptions {
+                        shift,
+                        policy: RangePolicy::from_name(&policy),
+                        min_note,
+                        max_note,
+                    },
+                    speed,
+                })
+            }
+            Message::Start { at } => Cue::Start(instant_at(at - offset)),
+            Message::Pause => {
+                remote.send(Command::Pause);
+                continue;
+            }
+            Message::Resume { position_secs, at } => {
+                remote.send(Command::Seek(catch_up(position_secs, at - offset, speed)));
+                remote.send(Command::Resume);
+                continue;
+            }
+            Message::Seek { position_secs, at } => {
+                remote.send(Command::Seek(catch_up(position_secs, at - offset, speed)));
+                continue;
+            }
+            Message::Stop => {
+                remote.send(Command::Stop);
+                continue;
+            }
+            message => {
+                debug!("Unexpected message from the conductor: {:?}", message);
+                continue;
+            }
+        };
+        if cues.send(cue).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Creates the player ahead of time so only the sleep is left before `start`.
+// Returns how it ended and the notes dropped, None without a player.
+fn play_at<'a>(
+    settings: PlayerSettings<'a>,
+    start: Instant,
+    window: &'a Window,
+    input_sleep_duration: u64,
+    multi: &'a MultiProgress,
+    remote: &'a Remote,
+    hotkeys: &'a Hotkeys,
+) -> Option<(PlayEnd, usize)> {
+    let mut player = match WebfishingPlayer::new(
+        settings,
+        false,
+        input_sleep_duration,
+        window,
+        multi,
+        remote,
+        hotkeys,
+    ) {
+        Ok(player) => player,
+        Err(e) => {
+            error!("Error creating player: {}", e);
+            return None;
+        }
+    };
+    remote.clear_commands();
+    sleep(start.saturating_duration_since(Instant::now()));
+    let end = player.play();
+    Some((end, player.dropped_notes()))
+}
+
+// Measures how far the conductor's clock is ahead of ours using the quickest
+// of several round trips, returns the offset and that round trip
+fn sync_clock(mut writer: impl Write, reader: &mut impl BufRead) -> io::Result<(i64, i64)> {
+    let mut best = (0, i64::MAX);
+    for _ in 0..SYNC_ROUNDS {
+        send(&mut writer, &Message::Ping { sent: now_micros() })?;
+        loop {
+            match read(reader, MAX_CONDUCTOR_MESSAGE)? {
+                Some(Message::Pong { sent, conductor }) => {
+                    let received = now_micros();
+                    let round_trip = received - sent;
+                    if round_trip < best.1 {
+                        best = (conductor - (sent + received) / 2, round_trip);
+                    }
+                    break;
+                }
+                Some(message) => debug!("Ignoring {:?} during the clock sync", message),
+                None => return Err(ErrorKind::UnexpectedEof.into()),
+            }
+        }
+    }
+    Ok(best)
+}
+
+// Where the song is now if it was at `position_secs` at the local time `at`
+fn catch_up(position_secs: f64, at: i64, speed: f64) -> f64 {
+    position_secs + (now_micros() - at).max(0) as f64 / 1_000_000.0 * speed
+}
+
+fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as i64)
+}
+
+// The instant of a local clock time, or now if it has passed
+fn instant_at(micros: i64) -> Instant {
+    let delay = (micros - now_micros()).max(0);
+    Instant::now() + Duration::from_micros(delay as u64)
+}
+
+fn send(mut writer: impl Write, message: &Message) -> io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+// The next message, None once the other side hangs up. Lines longer than
+// `limit` bytes are an error so the other side can't run us out of memory.
+fn read(reader: &mut impl BufRead, limit: u64) -> io::Result<Option<Message>> {
+    loop {
+        let mut line = String::new();
+        let length = reader.by_ref().take(limit).read_line(&mut line)?;
+        if length == 0 {
+            return Ok(None);
+        }
+        if length as u64 == limit && !line.ends_with('\n') {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "band message is too long",
+            ));
+        }
+        match serde_json::from_str(&line) {
+            Ok(message) => return Ok(Some(message)),
+            Err(e) => debug!("Ignoring band message {:?}: {}", line.trim(), e),
+        }
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(messages: &[Message]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for message in messages {
+            send(&mut data, message).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::Hello {
+                name: "Bass".to_string(),
+                code: "0123456789abcdef".to_string(),
+            },
+            Message::Seek {
+                position_secs: 12.5,
+                at: 1_700_000_000_000_000,
+            },
+            Message::Stop,
+        ];
+        let data = lines(&messages);
+        let mut reader = &data[..];
+        for message in messages {
+            assert_eq!(
+                read(&mut reader, MAX_MEMBER_MESSAGE).unwrap(),
+                Some(message)
+            );
+        }
+        assert_eq!(read(&mut reader, MAX_MEMBER_MESSAGE).unwrap(), None);
+
+        let midi = [0x4d, 0x54, 0x68, 0x64, 0x00, 0xff];
+        assert_eq!(from_hex(&to_hex(&midi)), Some(midi.to_vec()));
+        assert_eq!(from_hex("4d5"), None);
+    }
+
+    #[test]
+    fn unreadable_lines_are_skipped() {
+        let mut data = b"not json\n{\"type\":\"unknown\"}\n".to_vec();
+        data.extend(lines(&[Message::Ready]));
+        assert_eq!(
+            read(&mut &data[..], MAX_MEMBER_MESSAGE).unwrap(),
+            Some(Message::Ready)
+        );
+    }
+
+    #[test]
+    fn long_lines_are_an_error() {
+        let data = vec![b'x'; MAX_MEMBER_MESSAGE as usize * 2];
+        let error = read(&mut &data[..], MAX_MEMBER_MESSAGE).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        // A message right at the limit still fits
+        let mut data = lines(&[Message::Ready]);
+        let limit = data.len() as u64;
+        data.extend(lines(&[Message::Stop]));
+        let mut reader = &data[..];
+        assert_eq!(read(&mut reader, limit).unwrap(), Some(Message::Ready));
+        assert_eq!(read(&mut reader, limit).unwrap(), Some(Message::Stop));
+    }
+
+    #[test]
+    fn clock_sync_trusts_the_quickest_round_trip() {
+        const OFFSET: i64 = 5_000_000;
+        let now = now_micros();
+        let mut replies = Vec::new();
+        for _ in 1..SYNC_ROUNDS {
+            // Slow round trips that would give a different offset
+            let sent = now - 1_000_000;
+            replies.push(Message::Pong {
+                sent,
+                conductor: sent + 900_000 + OFFSET,
+            });
+        }
+        replies.insert(3, Message::Ready);
+        let sent = now - 2000;
+        replies.push(Message::Pong {
+            sent,
+            conductor: sent + 1000 + OFFSET,
+        });
+        let replies = lines(&replies);
+
+        let mut pings = Vec::new();
+        let (offset, round_trip) = sync_clock(&mut pings, &mut &replies[..]).unwrap();
+        assert!((offset - OFFSET).abs() < 50_000, "offset {}", offset);
+        assert!(round_trip < 1_000_000, "round trip {}", round_trip);
+
+        let mut pings = &pings[..];
+        for _ in 0..SYNC_ROUNDS {
+            assert!(matches!(
+                read(&mut pings, MAX_MEMBER_MESSAGE).unwrap(),
+                Some(Message::Ping { .. })
+            ));
+        }
+        assert_eq!(read(&mut pings, MAX_MEMBER_MESSAGE).unwrap(), None);
+    }
+
+    #[test]
+    fn clock_sync_fails_when_the_conductor_hangs_up() {
+        let replies = lines(&[Message::Pong {
+            sent: now_micros(),
+            conductor: now_micros(),
+        }]);
+        let error = sync_clock(io::sink(), &mut &replies[..]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn catch_up_moves_on_by_the_time_passed() {
+        let two_seconds_ago = now_micros() - 2_000_000;
+        let position = catch_up(10.0, two_seconds_ago, 1.5);
+        assert!((position - 13.0).abs() < 0.1, "position {}", position);
+        // A time still to come doesn't move it back
+        assert_eq!(catch_up(10.0, now_micros() + 2_000_000, 1.0), 10.0);
+    }
+
+    #[test]
+    fn conductor_only_admits_the_band_code() {
+        let conductor = Conductor::listen(0).unwrap();
+        let address = (Ipv4Addr::LOCALHOST, conductor.port());
+        let hello = |name: &str, code: &str| {
+            let stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            send(
+                &stream,
+                &Message::Hello {
+                    name: name.to_string(),
+                    code: code.to_string(),
+                },
+            )
+            .unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            (stream, reader)
+        };
+
+        let (stream, mut reader) = hello("Stranger", "wrong");
+        assert!(sync_clock(&stream, &mut reader).is_err());
+
+        let (stream, mut reader) = hello("Bass", conductor.code());
+        let (offset, _) = sync_clock(&stream, &mut reader).unwrap();
+        // Both ends share this machine's clock
+        assert!(offset.abs() < 100_000, "offset {}", offset);
+        let names: Vec<String> = conductor
+            .members()
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect();
+        assert_eq!(names, ["Bass"]);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::{
+    analysis::track_keys,
+    arrangement::{optimal_shift, ArrangeOptions, Arrangement, MAX_NOTE, MIN_NOTE},
+    library,
+    profiles::join_tracks,
+    songs::{self, SongSource},
+    suggestion::{self, SuggestMode},
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::{info, warn};
+use midly::{Smf, Timing};
+use serde::Serialize;
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+#[derive(Clone, Copy)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+impl ReportFormat {
+    pub const ALL: [ReportFormat; 2] = [ReportFormat::Csv, ReportFormat::Json];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ReportFormat::Csv => "CSV (spreadsheets)",
+            ReportFormat::Json => "JSON",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Csv => "csv",
+            ReportFormat::Json => "json",
+        }
+    }
+}
+
+/// What the report says about one song, only the path, title and error when
+/// it couldn't be read
+#[derive(Serialize)]
+struct SongReport {
+    path: String,
+    tune: Option<usize>,
+    title: String,
+    error: Option<String>,
+    #[serde(flatten)]
+    analysis: Option<SongAnalysis>,
+}
+
+#[derive(Serialize)]
+struct SongAnalysis {
+    timing: String,
+    duration_secs: f64,
+    track_count: usize,
+    note_count: usize,
+    // With every track at the best shift
+    playability: f64,
+    suggested_tracks: Vec<usize>,
+    suggested_shift: i8,
+    suggested_playability: f64,
+}
+
+const COLUMNS: [&str; 12] = [
+    "path",
+    "tune",
+    "title",
+    "error",
+    "timing",
+    "duration_secs",
+    "track_count",
+    "note_count",
+    "playability",
+    "suggested_tracks",
+    "suggested_shift",
+    "suggested_playability",
+];
+
+impl SongReport {
+    fn failed(song: &SongSource, error: String) -> Self {
+        SongReport {
+            path: song.path.to_string_lossy().into_owned(),
+            tune: song.tune,
+            title: song.title.clone().unwrap_or_default(),
+            error: Some(error),
+            analysis: None,
+        }
+    }
+
+    // Values in the order of COLUMNS, None where there's nothing to report
+    fn values(&self) -> [Option<String>; 12] {
+        let analysis = self.analysis.as_ref();
+        let value = |value: fn(&SongAnalysis) -> String| analysis.map(value);
+        [
+            Some(self.path.clone()),
+            self.tune.map(|tune| tune.to_string()),
+            Some(self.title.clone()),
+            self.error.clone(),
+            value(|a| a.timing.clone()),
+            value(|a| format!("{:.1}", a.duration_secs)),
+            value(|a| a.track_count.to_string()),
+            value(|a| a.note_count.to_string()),
+            value(|a| format!("{:.1}", a.playability)),
+            value(|a| join_tracks(&a.suggested_tracks)),
+            value(|a| a.suggested_shift.to_string()),
+            value(|a| format!("{:.1}", a.suggested_playability)),
+        ]
+    }
+}
+
+fn analyze_song(song: &SongSource) -> Result<SongReport, Box<dyn Error>> {
+    let data = song.load()?;
+    let smf = Smf::parse(&data)?;
+    let entry = library::analyze_smf(song, &data, &smf);
+
+    let timing = match smf.header.timing {
+        Timing::Metrical(ppq) => format!("metrical {} ppq", ppq.as_int()),
+        // The player can't play these yet
+        Timing::Timecode(fps, subframe) => {
+            format!("timecode {} fps x {}", fps.as_f32(), subframe)
+        }
+    };
+
+    let suggested_tracks =
+        suggestion::suggest_tracks(&suggestion::score_tracks(&smf), SuggestMode::Band);
+    let keys: Vec<u8> = suggested_tracks
+        .iter()
+        .flat_map(|&track| track_keys(&smf, track))
+        .collect();
+    let suggested_shift = optimal_shift(&keys, MIN_NOTE, MAX_NOTE).0;
+    let arrangement = Arrangement::new(
+        &smf,
+        &suggested_tracks,
+        &ArrangeOptions {
+            shift: Some(suggested_shift),
+            ..Default::default()
+        },
+    );
+
+    Ok(SongReport {
+        path: song.path.to_string_lossy().into_owned(),
+        tune: song.tune,
+        title: entry.title,
+        error: None,
+        analysis: Some(SongAnalysis {
+            timing,
+            duration_secs: entry.duration_secs,
+            track_count: entry.track_count,
+            note_count: entry.note_count,
+            playability: entry.playability,
+            suggested_tracks,
+            suggested_shift,
+            suggested_playability: arrangement.playability(),
+        }),
+    })
+}
+
+// Every song in a file, a file that can't be read or panics the parser is
+// reported instead of stopping the whole run
+fn analyze_file(path: &Path) -> Vec<SongReport> {
+    let songs = match songs::read_songs_in_file(path) {
+        Ok(songs) if !songs.is_empty() => songs,
+        result => {
+            let error = result
+                .err()
+                .map_or_else(|| "no songs found".to_string(), |e| e.to_string());
+            return vec![SongReport::failed(
+                &SongSource::file(path.to_path_buf()),
+                error,
+            )];
+        }
+    };
+    songs
+        .iter()
+        .map(|song| {
+            match panic::catch_unwind(AssertUnwindSafe(|| {
+                analyze_song(song).map_err(|e| e.to_string())
+            })) {
+                Ok(Ok(report)) => report,
+                Ok(Err(e)) => SongReport::failed(song, e),
+                Err(_) => SongReport::failed(song, "the parser crashed".to_string()),
+            }
+        })
+        .collect()
+}
+
+/// Analyzes every song under `root` on all cores and writes the report to
+/// `path`, returns how many songs were reported and how many failed
+pub fn write_report(
+    root: &Path,
+    path: &Path,
+    format: ReportFormat,
+    multi: &MultiProgress,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    library::collect_song_files(root, &mut files);
+    files.sort();
+    info!("Analyzing {} files in {}", files.len(), root.display());
+
+    let pb = multi.add(ProgressBar::new(files.len() as u64));
+    pb.set_style(ProgressStyle::with_template(
+        "[{elapsed}] {wide_bar:.cyan/blue} {pos}/{len}",
+    )?);
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<(usize, Vec<SongReport>)>> = Mutex::new(Vec::new());
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|scope| {
+        for _ in 0..workers.min(files.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(index) else {
+                    break;
+                };
+                let reports = analyze_file(file);
+                for report in &reports {
+                    if let Some(error) = &report.error {
+                        warn!("Failed to analyze {}: {}", report.path, error);
+                    }
+                }
+                results.lock().unwrap().push((index, reports));
+                pb.inc(1);
+            });
+        }
+    });
+    pb.finish();
+    multi.remove(&pb);
+
+    // Back in file order, the workers finish in any order
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|&(index, _)| index);
+    let reports: Vec<SongReport> = results
+        .into_iter()
+        .flat_map(|(_, reports)| reports)
+        .collect();
+
+    match format {
+        ReportFormat::Csv => fs::write(path, to_csv(&reports))?,
+        ReportFormat::Json => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            serde_json::to_writer_pretty(&mut writer, &reports)?;
+            writer.flush()?;
+        }
+    }
+
+    let failed = reports
+        .iter()
+        .filter(|report| report.error.is_some())
+        .count();
+    Ok((reports.len(), failed))
+}
+
+fn to_csv(reports: &[SongReport]) -> String {
+    let mut text = COLUMNS.join(",");
+    text.push('\n');
+    for report in reports {
+        let row: Vec<String> = report
+            .values()
+            .into_iter()
+            .map(|value| csv_field(&value.unwrap_or_default()))
+            .collect();
+        text.push_str(&row.join(","));
+        text.push('\n');
+    }
+    text
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
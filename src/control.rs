@@ -0,0 +1,137 @@
+use crate::{
+    remote::{Command, Remote},
+    songs::SongSource,
+};
+use log::debug;
+use serde_json::{json, Value};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    sync::Arc,
+    thread,
+};
+
+#[cfg(unix)]
+const SOCKET_PATH: &str = "webfishing-midi.sock";
+#[cfg(not(unix))]
+const CONTROL_PORT: u16 = 7879;
+
+/// Where the control server listens. The socket file is removed when this is
+/// dropped.
+pub struct ControlServer {
+    pub address: String,
+}
+
+#[cfg(unix)]
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(SOCKET_PATH);
+    }
+}
+
+/// Accepts control connections on a background thread, a Unix socket where
+/// there are those and localhost TCP everywhere else
+#[cfg(unix)]
+pub fn start(remote: Arc<Remote>) -> io::Result<ControlServer> {
+    use std::{
+        fs,
+        os::unix::net::{UnixListener, UnixStream},
+        path::Path,
+    };
+
+    if Path::new(SOCKET_PATH).exists() {
+        if UnixStream::connect(SOCKET_PATH).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another instance is already running on {}", SOCKET_PATH),
+            ));
+        }
+        // Left behind by a crash, nothing answers on it
+        fs::remove_file(SOCKET_PATH)?;
+    }
+    let listener = UnixListener::bind(SOCKET_PATH)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let remote = Arc::clone(&remote);
+            thread::spawn(move || serve(stream, &remote));
+        }
+    });
+    Ok(ControlServer {
+        address: SOCKET_PATH.to_string(),
+    })
+}
+
+#[cfg(not(unix))]
+pub fn start(remote: Arc<Remote>) -> io::Result<ControlServer> {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, CONTROL_PORT))?;
+    let addr = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let remote = Arc::clone(&remote);
+            thread::spawn(move || serve(stream, &remote));
+        }
+    });
+    Ok(ControlServer {
+        address: addr.to_string(),
+    })
+}
+
+// Answers each line with one line of JSON until the client disconnects
+fn serve<S>(stream: S, remote: &Remote)
+where
+    for<'a> &'a S: Read + Write,
+{
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let reply = handle_line(line.trim(), remote);
+        if writeln!(writer, "{}", reply).is_err() {
+            break;
+        }
+    }
+    debug!("Control client disconnected");
+}
+
+fn handle_line(line: &str, remote: &Remote) -> Value {
+    let (command, argument) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(command, argument)| (command, argument.trim()));
+
+    match (command, argument) {
+        ("play" | "queue", path) if !path.is_empty() => {
+            let Some(song) = SongSource::from_key(path) else {
+                return json!({ "error": format!("no song found at {}", path) });
+            };
+            if command == "play" {
+                remote.play_now(song);
+            } else {
+                remote.enqueue(song);
+            }
+            json!({ "ok": true })
+        }
+        ("queue", _) => {
+            let status = remote.status();
+            json!({ "queue": status.queue, "position": status.queue_position })
+        }
+        ("status", _) => json!(remote.status()),
+        ("pause", _) => send(remote, Command::Pause),
+        ("resume", _) => send(remote, Command::Resume),
+        ("skip", _) => send(remote, Command::Skip),
+        ("stop", _) => send(remote, Command::Stop),
+        _ => json!({
+            "error": format!("unknown command {:?}", line),
+            "commands": ["play <path>", "queue [path]", "pause", "resume", "skip", "stop", "status"],
+        }),
+    }
+}
+
+fn send(remote: &Remote, command: Command) -> Value {
+    if remote.send_playing(command) {
+        json!({ "ok": true })
+    } else {
+        json!({ "error": "nothing playing" })
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{history, hotkeys, library, playlists, songs::SongSource};
+use log::{info, warn};
+use rusqlite::{params, Connection, Transaction};
+
+// Schema changes in the order they were made, the database's user_version is
+// the number of migrations already applied. Only ever append to this list.
+const MIGRATIONS: [fn(&Transaction) -> rusqlite::Result<()>; 4] = [
+    create_tables,
+    add_song_profiles,
+    key_profiles_by_content,
+    add_hotkeys,
+];
+
+/// Opens the database and brings its tables up to date
+pub fn open(path: &str) -> rusqlite::Result<Connection> {
+    let mut conn = Connection::open(path)?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+// Applies the migrations the database hasn't seen yet
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version;", [], |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        info!("Updating the database to version {}", i + 1);
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+// The tables from before migrations were tracked, also used for new databases
+fn create_tables(conn: &Transaction) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS track_selections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL UNIQUE,
+            tracks TEXT);",
+        (),
+    )?;
+    library::create_table(conn)?;
+    playlists::create_tables(conn)?;
+    history::create_table(conn)?;
+    Ok(())
+}
+
+// Track selections become part of a profile with every playback setting
+fn add_song_profiles(conn: &Transaction) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE song_profiles (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            song_key TEXT NOT NULL UNIQUE,
+            tracks TEXT,
+            loops INTEGER NOT NULL DEFAULT 1,
+            shift INTEGER,
+            speed REAL NOT NULL DEFAULT 1,
+            policy TEXT NOT NULL DEFAULT 'clamp',
+            min_note INTEGER NOT NULL DEFAULT 40,
+            max_note INTEGER NOT NULL DEFAULT 79);",
+        (),
+    )?;
+    conn.execute(
+        "INSERT INTO song_profiles (song_key, tracks) SELECT path, tracks FROM track_selections;",
+        (),
+    )?;
+    conn.execute("DROP TABLE track_selections;", ())?;
+
+    for column in [
+        "shift INTEGER",
+        "speed REAL NOT NULL DEFAULT 1",
+        "policy TEXT NOT NULL DEFAULT 'clamp'",
+        "min_note INTEGER NOT NULL DEFAULT 40",
+        "max_note INTEGER NOT NULL DEFAULT 79",
+    ] {
+        conn.execute(
+            &format!("ALTER TABLE playlist_songs ADD COLUMN {};", column),
+            (),
+        )?;
+    }
+    Ok(())
+}
+
+// Profiles follow a file's content when it's moved or renamed. Existing rows
+// get the hash of the file at their path, rows for files that are gone keep none.
+fn key_profiles_by_content(conn: &Transaction) -> rusqlite::Result<()> {
+    conn.execute(
+        "ALTER TABLE song_profiles ADD COLUMN content_hash TEXT;",
+        (),
+    )?;
+    let keys: Vec<String> = conn
+        .prepare("SELECT song_key FROM song_profiles;")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for key in keys {
+        let data = SongSource::from_key(&key).map(|song| song.load());
+        match data {
+            Some(Ok(data)) => {
+                conn.execute(
+                    "UPDATE song_profiles SET content_hash = ?1 WHERE song_key = ?2;",
+                    params![library::content_hash(&data), key],
+                )?;
+            }
+            Some(Err(e)) => warn!("Failed to read {} for its saved settings: {}", key, e),
+            None => {}
+        }
+    }
+    conn.execute(
+        "CREATE INDEX song_profiles_content_hash ON song_profiles (content_hash);",
+        (),
+    )?;
+    Ok(())
+}
+
+// Keys changed from their defaults, the rest keep the built in ones
+fn add_hotkeys(conn: &Transaction) -> rusqlite::Result<()> {
+    hotkeys::create_table(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        conn.prepare(&format!("PRAGMA table_info({});", table))
+            .unwrap()
+            .query_map([], |row| row.get(1))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn baseline_databases_are_migrated() {
+        let song = env::temp_dir().join(format!("webfishing-midi-db-{}.mid", process::id()));
+        fs::write(&song, b"MThd song").unwrap();
+        let song_key = song.to_string_lossy().into_owned();
+
+        // The only table from before migrations, and no user_version
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE track_selections (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                path TEXT NOT NULL UNIQUE,
+                tracks TEXT);",
+            (),
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO track_selections (path, tracks) VALUES (?1, '0,2'), ('gone.mid', '1');",
+            params![song_key],
+        )
+        .unwrap();
+
+        let migrated = migrate(&mut conn);
+        fs::remove_file(&song).unwrap();
+        migrated.unwrap();
+
+        let version: usize = conn
+            .query_row("PRAGMA user_version;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        let tables: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table';")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert!(!tables.contains(&"track_selections".to_string()));
+
+        assert_eq!(
+            columns(&conn, "song_profiles"),
+            [
+                "id",
+                "song_key",
+                "tracks",
+                "loops",
+                "shift",
+                "speed",
+                "policy",
+                "min_note",
+                "max_note",
+                "content_hash"
+            ]
+        );
+        let playlist_songs = columns(&conn, "playlist_songs");
+        for column in ["shift", "speed", "policy", "min_note", "max_note"] {
+            assert!(
+                playlist_songs.iter().any(|name| name == column),
+                "{}",
+                column
+            );
+        }
+
+        let profiles: Vec<(String, String, i64, f64, String, Option<String>)> = conn
+            .prepare(
+                "SELECT song_key, tracks, loops, speed, policy, content_hash
+                FROM song_profiles ORDER BY id;",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            profiles,
+            [
+                (
+                    song_key,
+                    "0,2".to_string(),
+                    1,
+                    1.0,
+                    "clamp".to_string(),
+                    Some(library::content_hash(b"MThd song"))
+                ),
+                (
+                    "gone.mid".to_string(),
+                    "1".to_string(),
+                    1,
+                    1.0,
+                    "clamp".to_string(),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn migrated_databases_are_left_alone() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        let version: usize = conn
+            .query_row("PRAGMA user_version;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+}
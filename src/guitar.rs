@@ -0,0 +1,210 @@
+use enigo::{
+    Button, Coordinate,
+    Direction::{Click, Press, Release},
+    Enigo, Key, Keyboard, Mouse, Settings,
+};
+use log::debug;
+use std::{
+    collections::HashMap,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use xcap::Window;
+
+use crate::arrangement::{Fretboard, GuitarPosition, STRING_COUNT};
+
+/// The keys that strum each string, from the low E to the high E
+pub const STRUM_KEYS: [char; STRING_COUNT] = ['q', 'w', 'e', 'r', 't', 'y'];
+
+/// Where the fretboard is drawn, in pixels of a 2560x1440 window. Windows of
+/// other sizes are scaled from these.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    // Offset from the left where the strings start
+    pub left: f32,
+    // Offset from the top where the frets start
+    pub top: f32,
+    // Distance centre to centre of the strings
+    pub string_spacing: f32,
+    // Distance centre to centre of the frets
+    pub fret_spacing: f32,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            left: 460.0,
+            top: 130.0,
+            string_spacing: 44.0,
+            fret_spacing: 82.0,
+        }
+    }
+}
+
+/// How long the inputs for a window's notes took
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GuitarStats {
+    pub notes: usize,
+    // Notes with no free string
+    pub dropped: usize,
+    pub total_input: Duration,
+    pub max_input: Duration,
+}
+
+impl GuitarStats {
+    pub fn average_input(&self) -> Duration {
+        self.total_input / self.notes.max(1) as u32
+    }
+
+    /// Adds the inputs of another play of the same window
+    pub fn merge(&mut self, other: &GuitarStats) {
+        self.notes += other.notes;
+        self.dropped += other.dropped;
+        self.total_input += other.total_input;
+        self.max_input = self.max_input.max(other.max_input);
+    }
+}
+
+/// Plays notes on the guitar of one game window by clicking its frets and
+/// pressing the strum keys
+pub struct Guitar<'a> {
+    enigo: Enigo,
+    window: &'a Window,
+    calibration: Calibration,
+    cur_string_positions: HashMap<i32, i32>,
+    fretboard: Fretboard,
+    input_sleep_duration: u64,
+    // Cleared when inputs went to another window since, key presses only
+    // reach the focused window
+    focused: bool,
+    stats: GuitarStats,
+}
+
+impl<'a> Guitar<'a> {
+    pub fn new(window: &'a Window, input_sleep_duration: u64) -> Self {
+        Guitar::with_calibration(window, input_sleep_duration, Calibration::default())
+    }
+
+    pub fn with_calibration(
+        window: &'a Window,
+        input_sleep_duration: u64,
+        calibration: Calibration,
+    ) -> Self {
+        Guitar {
+            enigo: Enigo::new(&Settings::default()).unwrap(),
+            window,
+            calibration,
+            // For each 6 strings initialize the cur pos as 0
+            cur_string_positions: (0..6).map(|string| (string, 0)).collect(),
+            fretboard: Fretboard::default(),
+            input_sleep_duration,
+            focused: true,
+            stats: GuitarStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> GuitarStats {
+        self.stats
+    }
+
+    /// Another window was played since this one, so its next note clicks
+    /// a fret even when the fret is already set
+    pub fn focus_lost(&mut self) {
+        self.focused = false;
+    }
+
+    pub fn input_sleep_duration(&self) -> u64 {
+        self.input_sleep_duration
+    }
+
+    /// Attempt to press space in-case the user's OS requires a permission pop-up for input
+    pub fn request_input_permission(&mut self) {
+        self.enigo.key(Key::Space, Click).unwrap();
+    }
+
+    /// Reset the guitar to all open string
+    pub fn reset(&mut self) {
+        self.set_fret(6, 0);
+    }
+
+    /// Notes played after this are a new chord and may reuse strings
+    pub fn next_chord(&mut self) {
+        self.fretboard.next_chord();
+    }
+
+    /// Plays a note already in range, returns where it was played or `None`
+    /// when no string is free for it
+    pub fn play_note(&mut self, note: u8) -> Option<GuitarPosition> {
+        let started = Instant::now();
+        // Use the find_best_string function to get the guitar position
+        let Some(position) = self.fretboard.find_best_string(note) else {
+            self.stats.dropped += 1;
+            return None;
+        };
+
+        // Set fret position
+        self.set_fret(position.string, position.fret);
+
+        // Strum the string
+        self.strum_string(position.string);
+
+        self.fretboard.mark_played(position.string);
+
+        let input = started.elapsed();
+        self.stats.notes += 1;
+        self.stats.total_input += input;
+        self.stats.max_input = self.stats.max_input.max(input);
+        Some(position)
+    }
+
+    fn set_fret(&mut self, string: i32, fret: i32) {
+        // Don't attempt to change to this position if it's already set
+        // It will just unset it
+        let already_set = self.cur_string_positions.get(&string).unwrap_or(&-1) == &fret;
+        if already_set && self.focused {
+            return;
+        }
+
+        let cur_string_pos = self.cur_string_positions.entry(string).or_default();
+        *cur_string_pos = fret;
+
+        let scale_x = self.window.width() as f32 / 2560.0;
+        let scale_y = self.window.height() as f32 / 1440.0;
+        let scaled_left = (self.calibration.left * scale_x) as i32;
+        let scaled_top = (self.calibration.top * scale_y) as i32;
+        let scaled_string = (self.calibration.string_spacing * scale_x) as i32;
+        let scaled_fret = (self.calibration.fret_spacing * scale_y) as i32;
+
+        let fret_x = self.window.x() + (scaled_left + (string * scaled_string));
+        let fret_y = self.window.y() + (scaled_top + (fret * scaled_fret));
+
+        debug!(
+            "x: {} y: {} | scale_x {:.3} scale_y {:.3}",
+            fret_x, fret_y, scale_x, scale_y
+        );
+
+        self.enigo
+            .move_mouse(fret_x, fret_y, Coordinate::Abs)
+            .unwrap();
+        if already_set {
+            // Focus the window with a click that unsets the fret, then set it again
+            self.enigo.button(Button::Left, Click).unwrap();
+        }
+        self.enigo.button(Button::Left, Click).unwrap();
+        self.focused = true;
+    }
+
+    fn strum_string(&mut self, string: i32) {
+        let Some(&key) = STRUM_KEYS.get(string as usize) else {
+            return;
+        };
+        let key = Key::Unicode(key);
+
+        self.enigo.key(key, Press).unwrap();
+        // NOTE: This sleep is needed for the game to read the input
+        // espesially when it is low FPS since it checks input
+        // once per frame
+        sleep(Duration::from_millis(self.input_sleep_duration));
+        self.enigo.key(key, Release).unwrap();
+    }
+}
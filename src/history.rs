@@ -0,0 +1,188 @@
+use crate::{picker::format_duration, profiles::join_tracks, queue::QueuedSong, songs::SongSource};
+use rusqlite::{params, Connection};
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tabled::{builder::Builder, settings::Style};
+
+// How many songs each stats table and picker shortcut shows
+const LIST_LENGTH: usize = 10;
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS play_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            song_key TEXT NOT NULL,
+            path TEXT NOT NULL,
+            tune INTEGER,
+            title TEXT,
+            tracks TEXT,
+            started_at INTEGER NOT NULL,
+            ended_at INTEGER NOT NULL,
+            completed INTEGER NOT NULL,
+            dropped_notes INTEGER NOT NULL);",
+        (),
+    )?;
+    Ok(())
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Logs one playback of a song
+pub fn record(
+    conn: &Connection,
+    queued: &QueuedSong,
+    started: SystemTime,
+    completed: bool,
+    dropped_notes: usize,
+) -> rusqlite::Result<()> {
+    let tracks = queued.profile.tracks.as_deref().map(join_tracks);
+    conn.execute(
+        "INSERT INTO play_history (song_key, path, tune, title, tracks, started_at, ended_at,
+            completed, dropped_notes)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9);",
+        params![
+            queued.song.key(),
+            queued.song.path.to_string_lossy(),
+            queued.song.tune.map(|tune| tune as i64),
+            queued.song.title,
+            tracks,
+            unix_time(started),
+            unix_time(SystemTime::now()),
+            completed,
+            dropped_notes as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+fn song_from_row(row: &rusqlite::Row) -> rusqlite::Result<SongSource> {
+    let path: String = row.get(0)?;
+    let tune: Option<i64> = row.get(1)?;
+    Ok(SongSource {
+        path: PathBuf::from(path),
+        tune: tune.map(|tune| tune as usize),
+        title: row.get(2)?,
+    })
+}
+
+// Only songs that are still in the library
+fn existing_songs(songs: rusqlite::Result<Vec<SongSource>>) -> rusqlite::Result<Vec<SongSource>> {
+    Ok(songs?
+        .into_iter()
+        .filter(|song| song.path.exists())
+        .collect())
+}
+
+/// The last songs played, most recent first
+pub fn recently_played(conn: &Connection) -> rusqlite::Result<Vec<SongSource>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, tune, title FROM play_history
+        GROUP BY song_key ORDER BY MAX(started_at) DESC LIMIT ?1;",
+    )?;
+    let songs = stmt
+        .query_map([LIST_LENGTH as i64], song_from_row)?
+        .collect();
+    existing_songs(songs)
+}
+
+/// The songs played to the end the most times
+pub fn favourites(conn: &Connection) -> rusqlite::Result<Vec<SongSource>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, tune, title FROM play_history WHERE completed = 1
+        GROUP BY song_key ORDER BY COUNT(*) DESC, MAX(started_at) DESC LIMIT ?1;",
+    )?;
+    let songs = stmt
+        .query_map([LIST_LENGTH as i64], song_from_row)?
+        .collect();
+    existing_songs(songs)
+}
+
+/// Renders the statistics view
+pub fn stats(conn: &Connection) -> rusqlite::Result<String> {
+    let (plays, total_secs): (i64, Option<i64>) = conn.query_row(
+        "SELECT COUNT(*), SUM(ended_at - started_at) FROM play_history;",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let mut text = format!(
+        "{} plays, {} played in total\n",
+        plays,
+        format_duration(total_secs.unwrap_or(0) as f64)
+    );
+
+    text.push_str(&stats_table(
+        conn,
+        "Most played",
+        ["Song", "Plays", "Completed", "Time played"],
+        "SELECT path, tune, title, COUNT(*), SUM(completed), SUM(ended_at - started_at)
+        FROM play_history GROUP BY song_key ORDER BY COUNT(*) DESC LIMIT ?1;",
+        |row| {
+            Ok([
+                row.get::<_, i64>(3)?.to_string(),
+                row.get::<_, i64>(4)?.to_string(),
+                format_duration(row.get::<_, i64>(5)? as f64),
+            ])
+        },
+    )?);
+    text.push_str(&stats_table(
+        conn,
+        "Recently played",
+        ["Song", "Started", "Completed", "Dropped notes"],
+        "SELECT path, tune, title, datetime(started_at, 'unixepoch', 'localtime'), completed,
+            dropped_notes
+        FROM play_history ORDER BY started_at DESC, id DESC LIMIT ?1;",
+        |row| {
+            Ok([
+                row.get::<_, String>(3)?,
+                if row.get(4)? { "yes" } else { "no" }.to_string(),
+                row.get::<_, i64>(5)?.to_string(),
+            ])
+        },
+    )?);
+    text.push_str(&stats_table(
+        conn,
+        "Often interrupted",
+        ["Song", "Plays", "Interrupted", "Rate"],
+        "SELECT path, tune, title, COUNT(*), COUNT(*) - SUM(completed)
+        FROM play_history GROUP BY song_key HAVING COUNT(*) - SUM(completed) > 0
+        ORDER BY (COUNT(*) - SUM(completed)) * 1.0 / COUNT(*) DESC, COUNT(*) DESC LIMIT ?1;",
+        |row| {
+            let plays: i64 = row.get(3)?;
+            let interrupted: i64 = row.get(4)?;
+            Ok([
+                plays.to_string(),
+                interrupted.to_string(),
+                format!("{:.0}%", interrupted as f64 * 100.0 / plays as f64),
+            ])
+        },
+    )?);
+    Ok(text)
+}
+
+// A titled table of songs, the query's first columns are path, tune and title
+fn stats_table(
+    conn: &Connection,
+    title: &str,
+    header: [&str; 4],
+    query: &str,
+    columns: impl Fn(&rusqlite::Row) -> rusqlite::Result<[String; 3]>,
+) -> rusqlite::Result<String> {
+    let mut stmt = conn.prepare(query)?;
+    let mut rows = stmt.query([LIST_LENGTH as i64])?;
+    let mut builder = Builder::new();
+    builder.push_record(header);
+    while let Some(row) = rows.next()? {
+        let [a, b, c] = columns(row)?;
+        builder.push_record([song_from_row(row)?.display_name(), a, b, c]);
+    }
+    Ok(format!(
+        "\n{}\n{}\n",
+        title,
+        builder.build().with(Style::psql())
+    ))
+}
@@ -0,0 +1,330 @@
+use crate::guitar::STRUM_KEYS;
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+use log::{info, warn};
+use rusqlite::{params, Connection};
+use std::{
+    collections::HashMap,
+    error::Error,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread::{self, sleep},
+    time::Duration,
+};
+use tabled::{builder::Builder, settings::Style};
+
+// How often the keyboard is read, short enough that a tap is never missed
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Something a key does while songs play
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HotkeyAction {
+    // Begin once the user has tabbed over to the game
+    Start,
+    // Stop the song and open the queue editor
+    Interrupt,
+    TogglePause,
+    Next,
+    Previous,
+    Restart,
+    TransposeUp,
+    TransposeDown,
+    // Stop the song and everything queued after it
+    StopQueue,
+}
+
+impl HotkeyAction {
+    pub const ALL: [HotkeyAction; 9] = [
+        HotkeyAction::Start,
+        HotkeyAction::Interrupt,
+        HotkeyAction::TogglePause,
+        HotkeyAction::Next,
+        HotkeyAction::Previous,
+        HotkeyAction::Restart,
+        HotkeyAction::TransposeUp,
+        HotkeyAction::TransposeDown,
+        HotkeyAction::StopQueue,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HotkeyAction::Start => "start playing",
+            HotkeyAction::Interrupt => "stop the song",
+            HotkeyAction::TogglePause => "pause/play",
+            HotkeyAction::Next => "next song",
+            HotkeyAction::Previous => "previous song",
+            HotkeyAction::Restart => "restart the song",
+            HotkeyAction::TransposeUp => "transpose up",
+            HotkeyAction::TransposeDown => "transpose down",
+            HotkeyAction::StopQueue => "stop the queue",
+        }
+    }
+
+    // How the action is saved in the database
+    fn name(self) -> &'static str {
+        match self {
+            HotkeyAction::Start => "start",
+            HotkeyAction::Interrupt => "interrupt",
+            HotkeyAction::TogglePause => "toggle_pause",
+            HotkeyAction::Next => "next",
+            HotkeyAction::Previous => "previous",
+            HotkeyAction::Restart => "restart",
+            HotkeyAction::TransposeUp => "transpose_up",
+            HotkeyAction::TransposeDown => "transpose_down",
+            HotkeyAction::StopQueue => "stop_queue",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        HotkeyAction::ALL
+            .into_iter()
+            .find(|action| action.name() == name)
+    }
+
+    fn default_key(self) -> Keycode {
+        match self {
+            HotkeyAction::Start => Keycode::Backspace,
+            HotkeyAction::Interrupt => Keycode::Escape,
+            HotkeyAction::TogglePause => Keycode::RShift,
+            HotkeyAction::Next => Keycode::F7,
+            HotkeyAction::Previous => Keycode::F6,
+            HotkeyAction::Restart => Keycode::F5,
+            HotkeyAction::TransposeUp => Keycode::PageUp,
+            HotkeyAction::TransposeDown => Keycode::PageDown,
+            HotkeyAction::StopQueue => Keycode::F8,
+        }
+    }
+}
+
+// Keys the player presses itself, the keyboard thread sees those presses too
+// so binding one would fire its action while a song plays
+fn pressed_by_player(key: Keycode) -> bool {
+    key == Keycode::Space
+        || STRUM_KEYS.iter().any(|strum| {
+            Keycode::from_str(&strum.to_ascii_uppercase().to_string())
+                .is_ok_and(|strum| strum == key)
+        })
+}
+
+/// The key for each action
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bindings {
+    keys: HashMap<HotkeyAction, Keycode>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            keys: HotkeyAction::ALL
+                .into_iter()
+                .map(|action| (action, action.default_key()))
+                .collect(),
+        }
+    }
+}
+
+impl Bindings {
+    pub fn key(&self, action: HotkeyAction) -> Keycode {
+        self.keys[&action]
+    }
+
+    fn action_for(&self, key: Keycode) -> Option<HotkeyAction> {
+        HotkeyAction::ALL
+            .into_iter()
+            .find(|&action| self.key(action) == key)
+    }
+
+    /// The keys for `actions` as one line, e.g. "Escape: stop the song, RShift: pause/play"
+    pub fn help(&self, actions: &[HotkeyAction]) -> String {
+        actions
+            .iter()
+            .map(|&action| format!("{}: {}", self.key(action), action.label()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS hotkeys (
+            action TEXT PRIMARY KEY,
+            key TEXT NOT NULL);",
+        (),
+    )?;
+    Ok(())
+}
+
+/// The saved bindings, with the default key for any action that wasn't changed
+pub fn load_bindings(conn: &Connection) -> rusqlite::Result<Bindings> {
+    let mut bindings = Bindings::default();
+    let mut stmt = conn.prepare("SELECT action, key FROM hotkeys;")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (action, key) = row?;
+        match (HotkeyAction::from_name(&action), Keycode::from_str(&key)) {
+            (Some(action), Ok(key)) if !pressed_by_player(key) => {
+                bindings.keys.insert(action, key);
+            }
+            _ => warn!("Ignoring the saved hotkey {} for {}", key, action),
+        }
+    }
+    Ok(bindings)
+}
+
+fn save_binding(conn: &Connection, action: HotkeyAction, key: Keycode) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO hotkeys (action, key) VALUES (?1, ?2)
+        ON CONFLICT (action) DO UPDATE SET key = excluded.key;",
+        params![action.name(), key.to_string()],
+    )?;
+    Ok(())
+}
+
+/// Reads the keyboard on its own thread and reports every press of a bound
+/// key once, however long it's held
+pub struct Hotkeys {
+    bindings: Arc<Mutex<Bindings>>,
+    receiver: Receiver<HotkeyAction>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Hotkeys {
+    pub fn start(bindings: Bindings) -> Self {
+        let bindings = Arc::new(Mutex::new(bindings));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_bindings = Arc::clone(&bindings);
+        let thread_stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            let device_state = DeviceState::new();
+            // Keys already down when this starts weren't pressed for us
+            let mut held = device_state.get_keys();
+            while !thread_stop.load(Ordering::Relaxed) {
+                sleep(POLL_INTERVAL);
+                let keys = device_state.get_keys();
+                let pressed: Vec<HotkeyAction> = {
+                    let bindings = thread_bindings.lock().unwrap();
+                    keys.iter()
+                        .filter(|key| !held.contains(key))
+                        .filter_map(|&key| bindings.action_for(key))
+                        .collect()
+                };
+                for action in pressed {
+                    if sender.send(action).is_err() {
+                        return;
+                    }
+                }
+                held = keys;
+            }
+        });
+
+        Hotkeys {
+            bindings,
+            receiver,
+            stop,
+        }
+    }
+
+    pub fn bindings(&self) -> Bindings {
+        self.bindings.lock().unwrap().clone()
+    }
+
+    fn rebind(&self, bindings: Bindings) {
+        *self.bindings.lock().unwrap() = bindings;
+    }
+
+    /// Forgets the keys pressed so far, like typing in the menus
+    pub fn clear(&self) {
+        while self.receiver.try_recv().is_ok() {}
+    }
+
+    /// The next key pressed since the last call, if there's one
+    pub fn try_next(&self) -> Option<HotkeyAction> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Blocks until one of `actions` is pressed, `None` when the keyboard
+    /// can't be read
+    pub fn wait_for(&self, actions: &[HotkeyAction]) -> Option<HotkeyAction> {
+        self.receiver.iter().find(|action| actions.contains(action))
+    }
+}
+
+impl Drop for Hotkeys {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Lists the hotkeys and lets the user change them
+pub fn manage_hotkeys(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    hotkeys: &Hotkeys,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        let bindings = hotkeys.bindings();
+        let mut builder = Builder::new();
+        builder.push_record(["Action", "Key"]);
+        for action in HotkeyAction::ALL {
+            builder.push_record([action.label().to_string(), bindings.key(action).to_string()]);
+        }
+        println!("{}", builder.build().with(Style::psql()));
+
+        let mut items: Vec<String> = HotkeyAction::ALL
+            .iter()
+            .map(|&action| format!("Change {} ({})", action.label(), bindings.key(action)))
+            .collect();
+        items.push("Reset to the defaults".to_string());
+        items.push("Back".to_string());
+        let choice = Select::with_theme(theme)
+            .with_prompt("Hotkeys")
+            .items(&items)
+            .default(0)
+            .interact()?;
+
+        let Some(&action) = HotkeyAction::ALL.get(choice) else {
+            if choice == HotkeyAction::ALL.len() {
+                conn.execute("DELETE FROM hotkeys;", ())?;
+                hotkeys.rebind(Bindings::default());
+                info!("Hotkeys reset to the defaults");
+                continue;
+            }
+            return Ok(());
+        };
+
+        let key: String = Input::with_theme(theme)
+            .with_prompt(format!(
+                "Key to {} (e.g. F9, PageUp, RShift, Key5, Numpad0)",
+                action.label()
+            ))
+            .with_initial_text(bindings.key(action).to_string())
+            .validate_with(|input: &String| -> Result<(), String> {
+                let key = Keycode::from_str(input.trim())
+                    .map_err(|_| format!("{} isn't a key name", input.trim()))?;
+                if pressed_by_player(key) {
+                    return Err(format!("{} is pressed by the player to play notes", key));
+                }
+                match bindings.action_for(key) {
+                    Some(other) if other != action => {
+                        Err(format!("{} is already used to {}", key, other.label()))
+                    }
+                    _ => Ok(()),
+                }
+            })
+            .interact_text()?;
+        let key = Keycode::from_str(key.trim())?;
+
+        save_binding(conn, action, key)?;
+        let mut bindings = bindings;
+        bindings.keys.insert(action, key);
+        hotkeys.rebind(bindings);
+    }
+}
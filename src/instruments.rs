@@ -1,3 +1,5 @@
+use midly::{MidiMessage, Smf, TrackEventKind};
+
 // https://en.wikipedia.org/wiki/General_MIDI#Program_change_events
 // https://github.com/ryohey/signal/blob/main/app/src/components/TrackList/InstrumentName.tsx
 pub const INSTRUMENTS: [&str; 128] = [
@@ -161,3 +163,21 @@ pub const INSTRUMENTS: [&str; 128] = [
     "Applause",
     "Gunshot",
 ];
+
+/// The instrument a track's first program change picks, drums on channel 10
+pub fn track_instrument(smf: &Smf, track: usize) -> &'static str {
+    smf.tracks[track]
+        .iter()
+        .find_map(|event| match event.kind {
+            TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::ProgramChange { program },
+            } => Some(if channel == 9 {
+                "Standard Drum Kit"
+            } else {
+                INSTRUMENTS[program.as_int() as usize]
+            }),
+            _ => None,
+        })
+        .unwrap_or("No instrument set")
+}
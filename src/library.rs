@@ -0,0 +1,397 @@
+use crate::{
+    arrangement::{ArrangeOptions, Arrangement},
+    songs::{self, SongSource},
+};
+use indicatif::ProgressBar;
+use log::{debug, info, warn};
+use midly::{MetaMessage, MidiMessage, Smf, TrackEventKind};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    str,
+    time::UNIX_EPOCH,
+};
+
+/// What the index knows about a song, parsed once when the file changes
+#[derive(Clone, Debug)]
+pub struct LibraryEntry {
+    pub song: SongSource,
+    pub content_hash: String,
+    pub title: String,
+    pub duration_secs: f64,
+    pub track_count: usize,
+    pub channel_count: usize,
+    pub note_count: usize,
+    pub min_note: Option<u8>,
+    pub max_note: Option<u8>,
+    pub tempo_bpm: f64,
+    // Track names and text events, for searching
+    pub search_text: String,
+    // Percentage of notes played as written at the best shift with every track selected
+    pub playability: f64,
+}
+
+#[derive(Default)]
+pub struct IndexSummary {
+    pub indexed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub failed: usize,
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS library (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            key TEXT NOT NULL UNIQUE,
+            path TEXT NOT NULL,
+            tune INTEGER,
+            modified INTEGER NOT NULL,
+            size INTEGER NOT NULL,
+            content_hash TEXT,
+            title TEXT,
+            duration_secs REAL,
+            track_count INTEGER,
+            channel_count INTEGER,
+            note_count INTEGER,
+            min_note INTEGER,
+            max_note INTEGER,
+            tempo_bpm REAL,
+            playability REAL,
+            search_text TEXT,
+            error TEXT);",
+        (),
+    )?;
+    // Rows indexed before a column existed have to be parsed again
+    if add_column(conn, "library", "search_text", "TEXT")? {
+        conn.execute("UPDATE library SET modified = 0;", ())?;
+    }
+    Ok(())
+}
+
+/// Adds a column to tables created by older versions, returns whether it was missing
+pub fn add_column(
+    conn: &Connection,
+    table: &str,
+    name: &str,
+    definition: &str,
+) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1);")?;
+    let columns = stmt
+        .query_map([table], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if columns.iter().any(|column| column == name) {
+        return Ok(false);
+    }
+    conn.execute(
+        &format!("ALTER TABLE {} ADD COLUMN {} {};", table, name, definition),
+        (),
+    )?;
+    Ok(true)
+}
+
+/// Parses a song and collects its metadata
+pub fn analyze(song: &SongSource) -> Result<LibraryEntry, Box<dyn Error>> {
+    let data = song.load()?;
+    let smf = Smf::parse(&data)?;
+    Ok(analyze_smf(song, &data, &smf))
+}
+
+/// The library entry for a song already loaded as `data` and parsed as `smf`
+pub fn analyze_smf(song: &SongSource, data: &[u8], smf: &Smf) -> LibraryEntry {
+    let mut channels = HashSet::new();
+    let mut tempo = None;
+    let mut title = None;
+    let mut search_text = Vec::new();
+    for (i, track) in smf.tracks.iter().enumerate() {
+        for event in track {
+            match event.kind {
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn { vel, .. },
+                } if vel.as_int() > 0 => {
+                    channels.insert(channel.as_int());
+                }
+                TrackEventKind::Meta(MetaMessage::Tempo(micros)) => {
+                    tempo.get_or_insert(micros.as_int());
+                }
+                TrackEventKind::Meta(MetaMessage::TrackName(text)) => {
+                    if let Some(text) = meta_text(text) {
+                        // The first track's name is usually the song title
+                        if i == 0 && title.is_none() {
+                            title = Some(text.clone());
+                        }
+                        search_text.push(text);
+                    }
+                }
+                TrackEventKind::Meta(MetaMessage::Text(text)) => {
+                    search_text.extend(meta_text(text));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let tracks: Vec<usize> = (0..smf.tracks.len()).collect();
+    let arrangement = Arrangement::new(smf, &tracks, &ArrangeOptions::default());
+
+    LibraryEntry {
+        song: song.clone(),
+        content_hash: content_hash(data),
+        title: title
+            .or_else(|| song.title.clone())
+            .unwrap_or_else(|| song.file_stem()),
+        duration_secs: arrangement.length_micros as f64 / 1_000_000.0,
+        track_count: smf.tracks.len(),
+        channel_count: channels.len(),
+        note_count: arrangement.notes.len(),
+        min_note: arrangement.notes.iter().map(|n| n.key).min(),
+        max_note: arrangement.notes.iter().map(|n| n.key).max(),
+        tempo_bpm: 60_000_000.0 / tempo.unwrap_or(500_000).max(1) as f64,
+        search_text: search_text.join(" | "),
+        playability: arrangement.playability(),
+    }
+}
+
+fn meta_text(bytes: &[u8]) -> Option<String> {
+    str::from_utf8(bytes)
+        .ok()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+pub fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Scans `root` recursively and re-parses only the files that changed since the
+/// last scan
+pub fn index_library(
+    conn: &Connection,
+    root: &Path,
+    progress: &ProgressBar,
+) -> rusqlite::Result<IndexSummary> {
+    let mut summary = IndexSummary::default();
+    let mut files = Vec::new();
+    collect_song_files(root, &mut files);
+    progress.set_length(files.len() as u64);
+
+    let mut known: HashMap<String, (i64, i64)> = HashMap::new();
+    {
+        let mut stmt = conn.prepare("SELECT DISTINCT path, modified, size FROM library;")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?;
+        for row in rows {
+            let (path, stamp) = row?;
+            known.insert(path, stamp);
+        }
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    let mut seen = HashSet::new();
+    for file in &files {
+        progress.inc(1);
+        let path = file.to_string_lossy().into_owned();
+        seen.insert(path.clone());
+        let stamp = file_stamp(file);
+        if known.get(&path) == Some(&stamp) {
+            summary.unchanged += 1;
+            continue;
+        }
+
+        debug!("Indexing {}", path);
+        tx.execute("DELETE FROM library WHERE path = ?1;", [&path])?;
+        let songs = match songs::read_songs_in_file(file) {
+            Ok(songs) if !songs.is_empty() => songs,
+            result => {
+                let error = result
+                    .err()
+                    .map_or_else(|| "no songs found".to_string(), |e| e.to_string());
+                summary.failed += 1;
+                insert_error(&tx, &SongSource::file(file.clone()), stamp, &error)?;
+                continue;
+            }
+        };
+        for song in songs {
+            match analyze(&song) {
+                Ok(entry) => {
+                    insert_entry(&tx, &entry, stamp)?;
+                    summary.indexed += 1;
+                }
+                Err(e) => {
+                    warn!("Failed to index {}: {}", song.display_name(), e);
+                    insert_error(&tx, &song, stamp, &e.to_string())?;
+                    summary.failed += 1;
+                }
+            }
+        }
+    }
+
+    for path in known.keys().filter(|path| !seen.contains(*path)) {
+        tx.execute("DELETE FROM library WHERE path = ?1;", [path])?;
+        summary.removed += 1;
+    }
+    tx.commit()?;
+
+    info!(
+        "Library: {} indexed, {} unchanged, {} removed, {} failed",
+        summary.indexed, summary.unchanged, summary.removed, summary.failed
+    );
+    Ok(summary)
+}
+
+/// Every file under `dir` the player can read
+pub fn collect_song_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_song_files(&path, files);
+        } else if songs::is_song_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+// (modified time, size) to tell whether a file changed since it was indexed
+fn file_stamp(path: &Path) -> (i64, i64) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return (0, 0);
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs() as i64);
+    (modified, metadata.len() as i64)
+}
+
+fn insert_entry(
+    conn: &Connection,
+    entry: &LibraryEntry,
+    stamp: (i64, i64),
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO library (key, path, tune, modified, size, content_hash, title,
+            duration_secs, track_count, channel_count, note_count, min_note, max_note,
+            tempo_bpm, playability, search_text)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16);",
+        params![
+            entry.song.key(),
+            entry.song.path.to_string_lossy(),
+            entry.song.tune.map(|tune| tune as i64),
+            stamp.0,
+            stamp.1,
+            entry.content_hash,
+            entry.title,
+            entry.duration_secs,
+            entry.track_count as i64,
+            entry.channel_count as i64,
+            entry.note_count as i64,
+            entry.min_note,
+            entry.max_note,
+            entry.tempo_bpm,
+            entry.playability,
+            entry.search_text,
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_error(
+    conn: &Connection,
+    song: &SongSource,
+    stamp: (i64, i64),
+    error: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO library (key, path, tune, modified, size, error)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+        params![
+            song.key(),
+            song.path.to_string_lossy(),
+            song.tune.map(|tune| tune as i64),
+            stamp.0,
+            stamp.1,
+            error,
+        ],
+    )?;
+    Ok(())
+}
+
+const ENTRY_COLUMNS: &str = "key, path, tune, content_hash, title, duration_secs, track_count,
+    channel_count, note_count, min_note, max_note, tempo_bpm, playability, search_text";
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<(String, LibraryEntry)> {
+    let key: String = row.get(0)?;
+    let path: String = row.get(1)?;
+    let tune: Option<i64> = row.get(2)?;
+    let title: String = row.get(4)?;
+    Ok((
+        key,
+        LibraryEntry {
+            song: SongSource {
+                path: PathBuf::from(path),
+                tune: tune.map(|tune| tune as usize),
+                title: tune.map(|_| title.clone()),
+            },
+            content_hash: row.get(3)?,
+            title,
+            duration_secs: row.get(5)?,
+            track_count: row.get::<_, i64>(6)? as usize,
+            channel_count: row.get::<_, i64>(7)? as usize,
+            note_count: row.get::<_, i64>(8)? as usize,
+            min_note: row.get(9)?,
+            max_note: row.get(10)?,
+            tempo_bpm: row.get(11)?,
+            playability: row.get(12)?,
+            search_text: row.get::<_, Option<String>>(13)?.unwrap_or_default(),
+        },
+    ))
+}
+
+/// Every successfully indexed song by its key
+pub fn load_entries(conn: &Connection) -> rusqlite::Result<HashMap<String, LibraryEntry>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM library WHERE error IS NULL;",
+        ENTRY_COLUMNS
+    ))?;
+    let rows = stmt.query_map([], entry_from_row)?;
+    rows.collect()
+}
+
+/// An indexed song with this content, for songs that arrive as data
+pub fn find_by_hash(conn: &Connection, content_hash: &str) -> rusqlite::Result<Option<SongSource>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM library WHERE content_hash = ?1 AND error IS NULL LIMIT 1;",
+            ENTRY_COLUMNS
+        ),
+        [content_hash],
+        entry_from_row,
+    )
+    .optional()
+    .map(|entry| entry.map(|(_, entry)| entry.song))
+}
+
+pub fn get_entry(conn: &Connection, key: &str) -> rusqlite::Result<Option<LibraryEntry>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM library WHERE key = ?1 AND error IS NULL;",
+            ENTRY_COLUMNS
+        ),
+        [key],
+        entry_from_row,
+    )
+    .optional()
+    .map(|entry| entry.map(|(_, entry)| entry))
+}
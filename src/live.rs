@@ -0,0 +1,194 @@
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+use log::{debug, info, warn};
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use std::{
+    error::Error,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+use xcap::Window;
+
+use crate::{
+    arrangement::ArrangeOptions,
+    guitar::Guitar,
+    hotkeys::{HotkeyAction, Hotkeys},
+};
+
+const CLIENT_NAME: &str = "webfishing-midi";
+// Notes arriving closer together than this are played as one chord
+const CHORD_WINDOW: Duration = Duration::from_millis(30);
+// How often the keyboard is checked while no notes come in
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_TRANSPOSE: i8 = 24;
+
+struct LiveNote {
+    key: u8,
+    received: Instant,
+}
+
+/// Plays notes from a MIDI keyboard or another program on the game guitar as
+/// they come in, until the stop key is pressed
+pub fn play_live(
+    theme: &ColorfulTheme,
+    window: &Window,
+    input_sleep_duration: u64,
+    hotkeys: &Hotkeys,
+) -> Result<(), Box<dyn Error>> {
+    let mut midi_in = MidiInput::new(CLIENT_NAME)?;
+    // Only notes are played, skip SysEx, clock and active sensing
+    midi_in.ignore(Ignore::All);
+
+    let ports = midi_in.ports();
+    let mut items: Vec<String> = ports
+        .iter()
+        .map(|port| {
+            midi_in
+                .port_name(port)
+                .unwrap_or_else(|_| "Unknown port".to_string())
+        })
+        .collect();
+    if cfg!(unix) {
+        items.push("Create a virtual port for other programs to play into".to_string());
+    }
+    if items.is_empty() {
+        warn!("No MIDI input ports found, connect a device and try again");
+        return Ok(());
+    }
+    let port = Select::with_theme(theme)
+        .with_prompt("Which MIDI input should be played?")
+        .items(&items)
+        .default(0)
+        .interact()?;
+
+    let mut channels = vec!["All channels".to_string()];
+    channels.extend((1..=16).map(|channel| format!("Channel {}", channel)));
+    let channel = Select::with_theme(theme)
+        .with_prompt("Which channel should be played?")
+        .items(&channels)
+        .default(0)
+        .interact()?;
+    // MIDI channels are 0 based on the wire
+    let channel = channel.checked_sub(1).map(|channel| channel as u8);
+
+    let mut transpose: i8 = Input::with_theme(theme)
+        .with_prompt("Transpose by how many semitones?")
+        .default(0)
+        .validate_with(|semitones: &i8| -> Result<(), String> {
+            if semitones.abs() <= MAX_TRANSPOSE {
+                Ok(())
+            } else {
+                Err(format!("Must be between -{0} and {0}", MAX_TRANSPOSE))
+            }
+        })
+        .interact_text()?;
+
+    // The callback runs on the MIDI thread, notes are played on this one
+    let (sender, receiver) = mpsc::channel();
+    let callback = move |_: u64, message: &[u8], _: &mut ()| {
+        if let [status, key, vel] = *message {
+            let is_note_on = status & 0xF0 == 0x90 && vel > 0;
+            if is_note_on && channel.is_none_or(|channel| status & 0x0F == channel) {
+                let _ = sender.send(LiveNote {
+                    key,
+                    received: Instant::now(),
+                });
+            }
+        }
+    };
+    let _connection = match ports.get(port) {
+        Some(port) => midi_in.connect(port, "webfishing-midi-input", callback, ())?,
+        None => connect_virtual(midi_in, callback)?,
+    };
+
+    let mut guitar = Guitar::new(window, input_sleep_duration);
+    let bindings = hotkeys.bindings();
+    hotkeys.clear();
+    guitar.request_input_permission();
+    println!(
+        "Tab over to the game and press {} to start playing",
+        bindings.key(HotkeyAction::Start)
+    );
+    match hotkeys.wait_for(&[HotkeyAction::Start, HotkeyAction::Interrupt]) {
+        Some(HotkeyAction::Start) => {}
+        _ => return Ok(()),
+    }
+    guitar.reset();
+    // Notes played while waiting would come out all at once
+    while receiver.try_recv().is_ok() {}
+
+    println!(
+        "{}",
+        bindings.help(&[
+            HotkeyAction::Interrupt,
+            HotkeyAction::TransposeUp,
+            HotkeyAction::TransposeDown,
+        ])
+    );
+    let options = ArrangeOptions::default();
+    let mut last_received: Option<Instant> = None;
+    'live: loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(note) => {
+                if last_received.is_none_or(|last| note.received - last > CHORD_WINDOW) {
+                    guitar.next_chord();
+                }
+                last_received = Some(note.received);
+
+                match options.fit_note(note.key, transpose) {
+                    Some(key) => match guitar.play_note(key) {
+                        Some(position) => debug!(
+                            "Playing note {} on string {} fret {}",
+                            key,
+                            position.string + 1,
+                            position.fret
+                        ),
+                        None => warn!("No suitable string found for note {}", key),
+                    },
+                    None => debug!("Skipping out of range note {}", note.key),
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                warn!("The MIDI input was disconnected");
+                break;
+            }
+        }
+
+        while let Some(action) = hotkeys.try_next() {
+            let step = match action {
+                HotkeyAction::Interrupt | HotkeyAction::StopQueue => break 'live,
+                HotkeyAction::TransposeUp => 1,
+                HotkeyAction::TransposeDown => -1,
+                _ => continue,
+            };
+            transpose = (transpose + step).clamp(-MAX_TRANSPOSE, MAX_TRANSPOSE);
+            info!("Transposing by {:+} semitones", transpose);
+        }
+    }
+
+    info!("Stopped playing live");
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect_virtual(
+    midi_in: MidiInput,
+    callback: impl FnMut(u64, &[u8], &mut ()) + Send + 'static,
+) -> Result<MidiInputConnection<()>, Box<dyn Error>> {
+    use midir::os::unix::VirtualInput;
+
+    let connection = midi_in.create_virtual(CLIENT_NAME, callback, ())?;
+    info!(
+        "Created the virtual MIDI port {}, connect your program to it",
+        CLIENT_NAME
+    );
+    Ok(connection)
+}
+
+#[cfg(not(unix))]
+fn connect_virtual(
+    _: MidiInput,
+    _: impl FnMut(u64, &[u8], &mut ()) + Send + 'static,
+) -> Result<MidiInputConnection<()>, Box<dyn Error>> {
+    Err("virtual MIDI ports aren't supported on this system".into())
+}
@@ -1,46 +1,107 @@
+mod abc;
+mod analysis;
+mod arrangement;
+mod band;
+mod batch;
+mod control;
+mod db;
+mod guitar;
+mod history;
+mod hotkeys;
 mod instruments;
+mod library;
+#[cfg(feature = "live-midi")]
+mod live;
+mod midi_export;
+mod musicxml;
+mod picker;
+mod playlists;
+mod preview;
+mod preview_select;
+mod profiles;
+mod queue;
+mod remote;
+mod screen;
+mod server;
+mod songs;
+mod splitter;
+mod suggestion;
+mod tablature;
+mod watcher;
 mod webfishing_player;
+use arrangement::Arrangement;
+use batch::ReportFormat;
 use core::str;
-use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect, Input, MultiSelect};
-use indicatif::MultiProgress;
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, MultiSelect, Select};
+use guitar::{Calibration, Guitar, GuitarStats};
+use hotkeys::Hotkeys;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use indicatif_log_bridge::LogWrapper;
 use instruments::INSTRUMENTS;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use midi_export::ExportLayout;
 use midly::{MetaMessage, MidiMessage, Smf, TrackEventKind};
-use rusqlite::{params, Connection};
+use picker::Picker;
+use queue::{EditMode, Queue, QueuedSong};
+use remote::{Command, Event, Remote};
+use rusqlite::Connection;
+use screen::ScreenLogger;
 use simple_logger::SimpleLogger;
+use songs::SongSource;
+use splitter::{BandSplit, PartFormat};
 use std::{
+    env,
+    error::Error,
     fs,
     io::stdin,
+    net::SocketAddr,
     path::{Path, PathBuf},
     process::exit,
+    sync::Arc,
+    thread::sleep,
+    time::{Duration, SystemTime},
 };
+use suggestion::SuggestMode;
 use tabled::{builder::Builder, settings::Style};
-use webfishing_player::{PlayerSettings, WebfishingPlayer};
+use webfishing_player::{PlayEnd, PlayerSettings, Routing, WebfishingPlayer};
 use xcap::Window;
 
 #[cfg(feature = "auto-update")]
 use self_update::{cargo_crate_version, update::ReleaseUpdate, version::bump_is_greater};
-#[cfg(feature = "auto-update")]
-use std::env;
 
+const DB_PATH: &str = "webfishing-midi.db";
 const MIDI_DIR: &str = "./midi";
+const EXPORT_DIR: &str = "./exports";
+// Frame rate assumed in headless mode unless --fps is given
+const DEFAULT_MIN_FRAMERATE: u64 = 40;
+// Inputs are timed in whole milliseconds so faster frame rates can't be kept up with
+const MAX_FRAMERATE: u64 = 1000;
 const WINDOW_NAMES: [&str; 3] = ["steam_app_3146520", "Fish! (On the WEB!)", "Godot_Engine"];
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let logger = SimpleLogger::new()
-        .with_level(if cfg!(debug_assertions) {
-            log::LevelFilter::Debug
-        } else {
-            log::LevelFilter::Info
-        })
-        .without_timestamps();
+    let level = if cfg!(debug_assertions) {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Info
+    };
+    let logger = SimpleLogger::new().with_level(level).without_timestamps();
     let multi = MultiProgress::new();
-    LogWrapper::new(multi.clone(), logger).try_init()?;
+    // Logging goes through the screen so it can show the lines while it's open
+    log::set_boxed_logger(Box::new(ScreenLogger::new(LogWrapper::new(
+        multi.clone(),
+        logger,
+    ))))?;
+    log::set_max_level(level);
     let theme = ColorfulTheme::default();
 
+    // Headless mode plays songs sent to the control socket without any menus
+    let args: Vec<String> = env::args().collect();
+    let headless = args.iter().any(|arg| arg == "--headless");
+    // --plain keeps the progress bar and log lines instead of the full-screen view
+    screen::set_enabled(!headless && !args.iter().any(|arg| arg == "--plain"));
+
     #[cfg(feature = "auto-update")]
-    {
+    if !headless {
         let cur_ver = cargo_crate_version!();
         let opt_updater = check_update(cur_ver).unwrap_or_else(|e| {
             error!("Update check failed: {}", e);
@@ -66,23 +127,241 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    let conn = Connection::open("webfishing-midi.db")?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS track_selections (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            path TEXT NOT NULL UNIQUE,
-            tracks TEXT);",
-        (),
-    )?;
+    let conn = db::open(DB_PATH)?;
+    info!("Indexing songs in {}", MIDI_DIR);
+    let pb = multi.add(ProgressBar::new(0));
+    pb.set_style(ProgressStyle::with_template(
+        "[{elapsed}] {wide_bar:.cyan/blue} {pos}/{len}",
+    )?);
+    if let Err(e) = library::index_library(&conn, Path::new(MIDI_DIR), &pb) {
+        error!("Failed to index the library: {}", e);
+    }
+    pb.finish_and_clear();
+    multi.remove(&pb);
+    let bindings = hotkeys::load_bindings(&conn).unwrap_or_else(|e| {
+        error!("Failed to load the hotkeys: {}", e);
+        Default::default()
+    });
+    let hotkeys = Hotkeys::start(bindings);
 
-    let window = WINDOW_NAMES
-        .iter()
-        .find_map(|name| get_window(name))
-        .ok_or_else(|| {
-            error!("Could not find game window");
-            pause_and_exit(-1);
-        })
-        .unwrap();
+    if headless {
+        let min_framerate = args
+            .iter()
+            .position(|arg| arg == "--fps")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|fps| fps.parse().ok())
+            .filter(|fps| (1..=MAX_FRAMERATE).contains(fps))
+            .unwrap_or(DEFAULT_MIN_FRAMERATE);
+        return run_headless(&conn, &multi, &hotkeys, min_framerate);
+    }
+
+    // The game window and minimum frame rate are only needed once the user wants to play
+    let mut game: Option<(Window, u64)> = None;
+    let mut picker = Picker::new();
+    let remote = Arc::new(Remote::default());
+    let mut remote_server: Option<SocketAddr> = None;
+
+    loop {
+        let action = Select::with_theme(&theme)
+            .with_prompt("What would you like to do?")
+            .items(&MenuAction::ALL.map(MenuAction::label))
+            .default(0)
+            .interact()?;
+
+        match MenuAction::ALL[action] {
+            MenuAction::Play => {
+                if game.is_none() {
+                    game = find_game(&theme)?;
+                }
+                if let Some((window, min_framerate)) = &game {
+                    play_songs(
+                        &theme,
+                        &conn,
+                        window,
+                        input_sleep(*min_framerate),
+                        &multi,
+                        &mut picker,
+                        &remote,
+                        &hotkeys,
+                    )?;
+                }
+            }
+            MenuAction::Playlists => {
+                let Some(mut queue) = playlists::manage_playlists(&theme, &conn, &mut picker)?
+                else {
+                    continue;
+                };
+                if game.is_none() {
+                    game = find_game(&theme)?;
+                }
+                if let Some((window, min_framerate)) = &game {
+                    play_queue(
+                        &theme,
+                        &conn,
+                        &mut picker,
+                        &mut queue,
+                        window,
+                        input_sleep(*min_framerate),
+                        &multi,
+                        &remote,
+                        &hotkeys,
+                    )?;
+                }
+            }
+            #[cfg(feature = "live-midi")]
+            MenuAction::LiveMidi => {
+                if game.is_none() {
+                    game = find_game(&theme)?;
+                }
+                if let Some((window, min_framerate)) = &game {
+                    if let Err(e) =
+                        live::play_live(&theme, window, input_sleep(*min_framerate), &hotkeys)
+                    {
+                        error!("Live MIDI input failed: {}", e);
+                    }
+                }
+            }
+            #[cfg(not(feature = "live-midi"))]
+            MenuAction::LiveMidi => {
+                error!("This build can't read MIDI input, build it with --features live-midi")
+            }
+            MenuAction::MultiWindow => {
+                play_multi_window(&theme, &conn, &mut picker, &multi, &remote, &hotkeys)?;
+            }
+            MenuAction::Band => {
+                if game.is_none() {
+                    game = find_game(&theme)?;
+                }
+                if let Some((window, min_framerate)) = &game {
+                    play_in_band(
+                        &theme,
+                        &conn,
+                        &mut picker,
+                        window,
+                        input_sleep(*min_framerate),
+                        &multi,
+                        &remote,
+                        &hotkeys,
+                    )?;
+                }
+            }
+            MenuAction::Analyze => {
+                let min_framerate = match &game {
+                    Some((_, min_framerate)) => *min_framerate,
+                    None => ask_min_framerate(&theme)?,
+                };
+                analyze_song(&theme, &conn, &mut picker, min_framerate)?;
+            }
+            MenuAction::LibraryReport => {
+                library_report(&theme, &multi)?;
+            }
+            MenuAction::RenderPreview => {
+                render_preview(&theme, &conn, &mut picker)?;
+            }
+            MenuAction::ExportMidi => {
+                export_midi(&theme, &conn, &mut picker)?;
+            }
+            MenuAction::ExportTab => {
+                export_tab(&theme, &conn, &mut picker)?;
+            }
+            MenuAction::SplitParts => {
+                split_parts(&theme, &conn, &mut picker)?;
+            }
+            MenuAction::RemoteControl => match remote_server {
+                Some(addr) => info!("The remote control is already running on {}", addr),
+                None => remote_server = start_remote_server(&theme, &remote)?,
+            },
+            MenuAction::Hotkeys => hotkeys::manage_hotkeys(&theme, &conn, &hotkeys)?,
+            MenuAction::Stats => println!("{}", history::stats(&conn)?),
+            MenuAction::Exit => break,
+        }
+    }
+
+    Ok(())
+}
+
+// How a song is shared between game windows
+#[derive(Clone, Copy)]
+enum SplitMode {
+    Tracks,
+    Chords,
+}
+
+impl SplitMode {
+    const ALL: [SplitMode; 2] = [SplitMode::Tracks, SplitMode::Chords];
+
+    fn label(self) -> &'static str {
+        match self {
+            SplitMode::Tracks => "Choose the tracks each window plays",
+            SplitMode::Chords => "Deal the notes out to the windows in turn",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum MenuAction {
+    Play,
+    Playlists,
+    LiveMidi,
+    MultiWindow,
+    Band,
+    Analyze,
+    LibraryReport,
+    RenderPreview,
+    ExportMidi,
+    ExportTab,
+    SplitParts,
+    RemoteControl,
+    Hotkeys,
+    Stats,
+    Exit,
+}
+
+impl MenuAction {
+    const ALL: [MenuAction; 15] = [
+        MenuAction::Play,
+        MenuAction::Playlists,
+        MenuAction::LiveMidi,
+        MenuAction::MultiWindow,
+        MenuAction::Band,
+        MenuAction::Analyze,
+        MenuAction::LibraryReport,
+        MenuAction::RenderPreview,
+        MenuAction::ExportMidi,
+        MenuAction::ExportTab,
+        MenuAction::SplitParts,
+        MenuAction::RemoteControl,
+        MenuAction::Hotkeys,
+        MenuAction::Stats,
+        MenuAction::Exit,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            MenuAction::Play => "Play songs",
+            MenuAction::Playlists => "Playlists",
+            MenuAction::LiveMidi => "Play live from a MIDI keyboard",
+            MenuAction::MultiWindow => "Play on several game windows",
+            MenuAction::Band => "Play in a band over the network",
+            MenuAction::Analyze => "Analyze playability",
+            MenuAction::LibraryReport => "Analyze the whole library (CSV/JSON)",
+            MenuAction::RenderPreview => "Render audio preview (WAV)",
+            MenuAction::ExportMidi => "Export arrangement to MIDI",
+            MenuAction::ExportTab => "Export guitar tab",
+            MenuAction::SplitParts => "Split a song into band parts",
+            MenuAction::RemoteControl => "Start the remote control server",
+            MenuAction::Hotkeys => "Change the hotkeys",
+            MenuAction::Stats => "Play statistics",
+            MenuAction::Exit => "Exit",
+        }
+    }
+}
+
+fn find_window() -> Option<Window> {
+    let Some(window) = WINDOW_NAMES.iter().find_map(|name| get_window(name)) else {
+        error!("Could not find game window");
+        return None;
+    };
 
     info!(
         "Found window: {} {},{} {}x{}",
@@ -92,306 +371,1044 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         window.width(),
         window.height()
     );
+    Some(window)
+}
 
-    let min_framerate: u64 = Input::with_theme(&theme)
+fn find_windows() -> Vec<Window> {
+    match Window::all() {
+        Ok(windows) => windows
+            .into_iter()
+            .filter(|window| WINDOW_NAMES.contains(&window.app_name()))
+            .collect(),
+        Err(e) => {
+            error!("Failed to list windows: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn find_game(theme: &ColorfulTheme) -> Result<Option<(Window, u64)>, dialoguer::Error> {
+    let Some(window) = find_window() else {
+        return Ok(None);
+    };
+
+    Ok(Some((window, ask_min_framerate(theme)?)))
+}
+
+// The ideal delay between inputs in milliseconds, one frame at the minimum
+// frame rate
+fn input_sleep(min_framerate: u64) -> u64 {
+    1000 / min_framerate
+}
+
+fn ask_min_framerate(theme: &ColorfulTheme) -> Result<u64, dialoguer::Error> {
+    Input::with_theme(theme)
         .with_prompt("\nEnter your minimum FPS.\nHigher is better, but may skip notes. Default:")
         .default(40)
+        .validate_with(|fps: &u64| -> Result<(), String> {
+            if (1..=MAX_FRAMERATE).contains(fps) {
+                Ok(())
+            } else {
+                Err(format!("FPS must be between 1 and {}", MAX_FRAMERATE))
+            }
+        })
+        .interact_text()
+}
+
+fn ask_calibration(theme: &ColorfulTheme) -> Result<Calibration, dialoguer::Error> {
+    let default = Calibration::default();
+    let calibration: String = Input::with_theme(theme)
+        .with_prompt(
+            "Fretboard position as left, top, string spacing, fret spacing (pixels at 2560x1440)",
+        )
+        .default(format!(
+            "{}, {}, {}, {}",
+            default.left, default.top, default.string_spacing, default.fret_spacing
+        ))
+        .validate_with(|input: &String| -> Result<(), &str> {
+            parse_calibration(input)
+                .map(|_| ())
+                .ok_or("Expected four numbers separated by commas")
+        })
         .interact_text()?;
+    Ok(parse_calibration(&calibration).unwrap_or(default))
+}
 
-    // Calculate the ideal delay in milliseconds
-    let input_sleep_duration: u64 = (1000 / min_framerate) as u64;
+fn parse_calibration(input: &str) -> Option<Calibration> {
+    let values: Vec<f32> = input
+        .split(',')
+        .map(|value| value.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [left, top, string_spacing, fret_spacing] => Some(Calibration {
+            left,
+            top,
+            string_spacing,
+            fret_spacing,
+        }),
+        _ => None,
+    }
+}
 
-    loop {
-        let mut song_queue: Vec<PlayerSettings> = Vec::new();
-        let mut default_selection = 0;
+// Asks for a song, the tracks to play from it and its other settings
+fn select_song<'a>(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+) -> Result<Option<(QueuedSong, PlayerSettings<'a>)>, dialoguer::Error> {
+    let song = picker.select(theme, conn)?;
 
-        // Selection loop for adding songs to the queue
-        loop {
-            let (midi_file_path, selection) = get_midi_selection(&theme, default_selection);
-            default_selection = selection;
+    info!("Selected: {}", song.display_name());
 
-            info!("Selected: {}", midi_file_path.display());
+    let midi_data = match song.load() {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Failed to read song: {}", e);
+            return Ok(None);
+        }
+    };
 
-            let midi_data = match fs::read(&midi_file_path) {
-                Ok(data) => data,
-                Err(e) => {
-                    error!("Failed to read MIDI file: {}", e);
-                    continue;
-                }
-            };
+    let content_hash = library::content_hash(&midi_data);
+    let mut settings = match PlayerSettings::new(midi_data, false) {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("Failed to parse MIDI data: {}", e);
+            return Ok(None);
+        }
+    };
 
-            // Ask if the user wants to loop the song
-            let loop_midi = Confirm::with_theme(&theme)
-                .with_prompt("Loop? (Hold ESC to stop)")
-                .default(false)
-                .interact()?;
+    let mut profile = match profiles::get_profile(conn, &song, &content_hash) {
+        Ok(profile) => profile.unwrap_or_default(),
+        Err(err) => {
+            error!("Failed to get song settings from database: {}", err);
+            pause_and_exit(-1);
+        }
+    };
+    let chosen_tracks = get_tracks_selection(&settings.smf, profile.tracks.as_deref(), theme)?;
+    profile.tracks = Some(chosen_tracks);
+    let profile = profiles::review_profile(theme, conn, picker, &song, &content_hash, profile)?;
+    profile.apply(&mut settings);
 
-            // Add the selected song to the queue
-            let mut settings = match PlayerSettings::new(midi_data, loop_midi) {
-                Ok(settings) => settings,
-                Err(e) => {
-                    error!("Failed to parse MIDI data: {}", e);
-                    continue;
-                }
-            };
+    Ok(Some((QueuedSong { song, profile }, settings)))
+}
 
-            let chosen_tracks =
-                get_tracks_selection(&midi_file_path, &settings.smf, &theme, &conn)?;
-            settings.tracks = Some(chosen_tracks);
+#[allow(clippy::too_many_arguments)]
+fn play_songs(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    window: &Window,
+    input_sleep_duration: u64,
+    multi: &MultiProgress,
+    picker: &mut Picker,
+    remote: &Remote,
+    hotkeys: &Hotkeys,
+) -> Result<(), dialoguer::Error> {
+    let mut queue = Queue::default();
+    loop {
+        queue.add_enqueued(conn, remote);
+        if !queue::edit_queue(theme, conn, picker, &mut queue, EditMode::Queue)? {
+            return Ok(());
+        }
+        play_queue(
+            theme,
+            conn,
+            picker,
+            &mut queue,
+            window,
+            input_sleep_duration,
+            multi,
+            remote,
+            hotkeys,
+        )?;
+        // Keep the songs around so the queue can be played again or changed
+        queue.position = 0;
+    }
+}
 
-            song_queue.push(settings);
+// Plays the queue from its position, stopping a song with escape opens the queue
+// editor before moving on
+#[allow(clippy::too_many_arguments)]
+fn play_queue(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+    queue: &mut Queue,
+    window: &Window,
+    input_sleep_duration: u64,
+    multi: &MultiProgress,
+    remote: &Remote,
+    hotkeys: &Hotkeys,
+) -> Result<(), dialoguer::Error> {
+    remote.clear_commands();
+    let mut wait_for_user = true;
+    // Songs in a row that couldn't be played, a whole pass of them means a
+    // repeating queue would never play anything
+    let mut unplayable = 0;
+    loop {
+        queue.add_enqueued(conn, remote);
+        if unplayable >= queue.songs.len().max(1) {
+            error!("None of the songs in the queue could be played");
+            end_queue(remote);
+            return Ok(());
+        }
+        if queue.position >= queue.songs.len() {
+            if queue.repeat && !queue.songs.is_empty() {
+                queue.position = 0;
+            } else {
+                end_queue(remote);
+                return Ok(());
+            }
+        }
 
-            if loop_midi {
-                break; // Exit the selection loop
+        let index = queue.advance();
+        let Some(end) = play_song(
+            conn,
+            queue,
+            index,
+            &mut wait_for_user,
+            window,
+            input_sleep_duration,
+            multi,
+            remote,
+            hotkeys,
+        ) else {
+            unplayable += 1;
+            continue;
+        };
+        unplayable = 0;
+        match end {
+            PlayEnd::Finished => {}
+            PlayEnd::Skipped => info!("Song skipped"),
+            PlayEnd::Previous => {
+                info!("Back to the previous song");
+                queue.previous();
+            }
+            PlayEnd::Interrupted => {
+                info!("Song interrupted");
+                if !queue::edit_queue(theme, conn, picker, queue, EditMode::Queue)? {
+                    end_queue(remote);
+                    return Ok(());
+                }
+                // The user is back in the terminal so wait for them to tab over again
+                wait_for_user = true;
             }
+            PlayEnd::Stopped => {
+                info!("Queue stopped");
+                end_queue(remote);
+                return Ok(());
+            }
+        }
+    }
+}
 
-            // Ask if the user wants to add another song
-            let add_another_song = Confirm::with_theme(&theme)
-                .with_prompt("Would you like to add another song to the queue?")
-                .default(false)
-                .interact()?;
+// Plays a song of the queue as many times as its loop count, records every
+// play and keeps remote clients up to date. `None` when the song couldn't be
+// played at all.
+#[allow(clippy::too_many_arguments)]
+fn play_song(
+    conn: &Connection,
+    queue: &Queue,
+    index: usize,
+    wait_for_user: &mut bool,
+    window: &Window,
+    input_sleep_duration: u64,
+    multi: &MultiProgress,
+    remote: &Remote,
+    hotkeys: &Hotkeys,
+) -> Option<PlayEnd> {
+    let queued = &queue.songs[index];
+    let name = queued.song.display_name();
+    let names = queue.names();
+    remote.start_song(name.clone(), |status| {
+        status.queue = names;
+        status.queue_position = Some(index);
+    });
+    remote.emit(Event::SongStarted { song: name.clone() });
+    info!("Playing {}", name);
+
+    // Stays `None` when the song can't be loaded
+    let mut end = None;
+    // A loop count of 0 loops inside the player until stopped
+    for _ in 0..queued.profile.loops.max(1) {
+        let Some(settings) = queued.settings() else {
+            break;
+        };
 
-            if !add_another_song {
-                break; // Exit the selection loop
+        let mut player = match WebfishingPlayer::new(
+            settings,
+            *wait_for_user,
+            input_sleep_duration,
+            window,
+            multi,
+            remote,
+            hotkeys,
+        ) {
+            Ok(player) => player,
+            Err(e) => {
+                error!("Error creating player: {}", e);
+                break;
             }
+        };
+        *wait_for_user = false;
+
+        let started = SystemTime::now();
+        let played = player.play();
+        end = Some(played);
+        let completed = played == PlayEnd::Finished;
+        if let Err(e) = history::record(conn, queued, started, completed, player.dropped_notes()) {
+            error!("Failed to save play history: {}", e);
+        }
+        if !completed {
+            break;
         }
+    }
+    // Nothing plays until the next song starts, so commands aren't accepted
+    remote.end_song();
+    remote.emit(Event::SongEnded {
+        song: name,
+        completed: end == Some(PlayEnd::Finished),
+    });
+    end
+}
 
-        // Play all songs in the queue
-        for (index, settings) in song_queue.into_iter().enumerate() {
-            let is_first_song = index == 0;
+// Plays whatever is sent to the control socket until the process is killed
+fn run_headless(
+    conn: &Connection,
+    multi: &MultiProgress,
+    hotkeys: &Hotkeys,
+    min_framerate: u64,
+) -> Result<(), Box<dyn Error>> {
+    let window = find_window().ok_or("the game needs to be running in headless mode")?;
+    let input_sleep_duration = input_sleep(min_framerate);
+    let remote = Arc::new(Remote::default());
+    let control = control::start(Arc::clone(&remote))?;
+    info!("Waiting for commands on {}", control.address);
 
-            let mut player = match WebfishingPlayer::new(
-                settings,
-                is_first_song,
-                input_sleep_duration,
-                &window,
-                &multi,
-            ) {
-                Ok(player) => player,
-                Err(e) => {
-                    error!("Error creating player: {}", e);
-                    continue;
+    let mut queue = Queue::default();
+    // Scripts focus the game themselves so there's no backspace to wait for
+    let mut wait_for_user = false;
+    loop {
+        queue.add_enqueued(conn, &remote);
+        if queue.position >= queue.songs.len() {
+            // Nothing is playing, so only a stop needs handling. The others
+            // were already answered with an error.
+            while let Some(command) = remote.next_command() {
+                if command == Command::Stop {
+                    queue = Queue::default();
+                    remote.update_status(|status| status.queue.clear());
                 }
-            };
+            }
+            sleep(Duration::from_millis(100));
+            continue;
+        }
 
-            player.play();
+        let index = queue.advance();
+        // Songs that can't be played are skipped
+        let end = play_song(
+            conn,
+            &queue,
+            index,
+            &mut wait_for_user,
+            &window,
+            input_sleep_duration,
+            multi,
+            &remote,
+            hotkeys,
+        )
+        .unwrap_or(PlayEnd::Skipped);
+        if end == PlayEnd::Previous {
+            queue.previous();
         }
+        if matches!(end, PlayEnd::Stopped | PlayEnd::Interrupted) {
+            info!("Queue stopped");
+            queue = Queue::default();
+            remote.update_status(|status| status.queue.clear());
+        }
+        if queue.position >= queue.songs.len() {
+            end_queue(&remote);
+        }
+    }
+}
 
-        // Ask if the user wants to play another song
-        let confirmation = Confirm::with_theme(&theme)
-            .with_prompt("Do you want to play another song?")
-            .default(true)
-            .interact()?;
-        if !confirmation {
+fn end_queue(remote: &Remote) {
+    remote.update_status(|status| {
+        status.song = None;
+        status.queue_position = None;
+    });
+    remote.emit(Event::QueueEnded);
+}
+
+// Plays one song on several game windows at once, each with its own frame rate
+// and fretboard position
+fn play_multi_window(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+    multi: &MultiProgress,
+    remote: &Remote,
+    hotkeys: &Hotkeys,
+) -> Result<(), dialoguer::Error> {
+    let windows = find_windows();
+    if windows.is_empty() {
+        error!("Could not find game window");
+        return Ok(());
+    }
+    let labels: Vec<String> = windows
+        .iter()
+        .enumerate()
+        .map(|(i, window)| {
+            format!(
+                "Window {}: {} {},{} {}x{}",
+                i + 1,
+                window.title(),
+                window.x(),
+                window.y(),
+                window.width(),
+                window.height()
+            )
+        })
+        .collect();
+    let chosen = MultiSelect::with_theme(theme)
+        .with_prompt("Which game windows should play?")
+        .items(&labels)
+        .defaults(&vec![true; windows.len()])
+        .interact()?;
+    if chosen.is_empty() {
+        return Ok(());
+    }
+
+    let mut setups = Vec::new();
+    for &i in &chosen {
+        println!("{}", labels[i]);
+        let input_sleep_duration = input_sleep(ask_min_framerate(theme)?);
+        let calibration = ask_calibration(theme)?;
+        setups.push((&windows[i], input_sleep_duration, calibration));
+    }
+
+    let Some((queued, mut settings)) = select_song(theme, conn, picker)? else {
+        return Ok(());
+    };
+    let split = Select::with_theme(theme)
+        .with_prompt("How should the windows share the song?")
+        .items(&SplitMode::ALL.map(SplitMode::label))
+        .default(0)
+        .interact()?;
+    let routing = match SplitMode::ALL[split] {
+        SplitMode::Chords => Routing::Split,
+        SplitMode::Tracks => {
+            let players: Vec<String> = chosen.iter().map(|i| format!("window {}", i + 1)).collect();
+            let parts = assign_parts(
+                theme,
+                &settings.smf,
+                settings.tracks.as_deref().unwrap_or(&[]),
+                &players,
+            )?;
+            let mut tracks: Vec<usize> = parts.iter().flatten().copied().collect();
+            tracks.sort_unstable();
+            tracks.dedup();
+            settings.tracks = Some(tracks);
+            Routing::Tracks(parts)
+        }
+    };
+
+    let name = queued.song.display_name();
+    info!("Playing {} on {} windows", name, chosen.len());
+    remote.start_song(name.clone(), |_| {});
+    let tracks = settings.tracks.clone();
+    let mut first = Some(settings);
+    let mut stats = vec![GuitarStats::default(); chosen.len()];
+    let mut wait_for_user = true;
+    // A loop count of 0 loops inside the player until stopped
+    for _ in 0..queued.profile.loops.max(1) {
+        // Later loops load the song again, with the tracks the windows share
+        let Some(mut settings) = first.take().or_else(|| queued.settings()) else {
+            break;
+        };
+        settings.tracks = tracks.clone();
+        let guitars = setups
+            .iter()
+            .map(|&(window, input_sleep_duration, calibration)| {
+                Guitar::with_calibration(window, input_sleep_duration, calibration)
+            })
+            .collect();
+        let mut player = match WebfishingPlayer::with_guitars(
+            settings,
+            wait_for_user,
+            guitars,
+            routing.clone(),
+            multi,
+            remote,
+            hotkeys,
+        ) {
+            Ok(player) => player,
+            Err(e) => {
+                error!("Error creating player: {}", e);
+                break;
+            }
+        };
+        wait_for_user = false;
+
+        let started = SystemTime::now();
+        let end = player.play();
+        info!("{} ended: {:?}", name, end);
+        for (total, window) in stats.iter_mut().zip(player.guitar_stats()) {
+            total.merge(&window);
+        }
+        let completed = end == PlayEnd::Finished;
+        if let Err(e) = history::record(conn, &queued, started, completed, player.dropped_notes()) {
+            error!("Failed to save play history: {}", e);
+        }
+        if !completed {
             break;
         }
     }
+    remote.end_song();
 
+    let mut builder = Builder::new();
+    builder.push_record([
+        "Window",
+        "Notes",
+        "No free string",
+        "Average input",
+        "Slowest input",
+    ]);
+    for (i, stats) in chosen.iter().zip(stats) {
+        builder.push_record([
+            (i + 1).to_string(),
+            stats.notes.to_string(),
+            stats.dropped.to_string(),
+            format!("{}ms", stats.average_input().as_millis()),
+            format!("{}ms", stats.max_input.as_millis()),
+        ]);
+    }
+    println!("{}", builder.build().with(Style::psql()));
     Ok(())
 }
 
-#[cfg(feature = "auto-update")]
-fn check_update(
-    cur_ver: &str,
-) -> Result<Option<Box<dyn ReleaseUpdate>>, self_update::errors::Error> {
-    let updater = self_update::backends::github::Update::configure()
-        .repo_owner("yobson1")
-        .verifying_keys([*include_bytes!("../pub.key")])
-        .repo_name("webfishing-midi")
-        .bin_name("webfishing-midi")
-        .show_download_progress(true)
-        .show_output(cfg!(debug_assertions))
-        .no_confirm(true)
-        .current_version(cur_ver)
-        .build()?;
+// Conducts a band or joins one as a member
+#[allow(clippy::too_many_arguments)]
+fn play_in_band(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+    window: &Window,
+    input_sleep_duration: u64,
+    multi: &MultiProgress,
+    remote: &Arc<Remote>,
+    hotkeys: &Hotkeys,
+) -> Result<(), dialoguer::Error> {
+    let role = Select::with_theme(theme)
+        .with_prompt("How do you want to play?")
+        .items(&band::Role::ALL.map(band::Role::label))
+        .default(0)
+        .interact()?;
 
-    let latest = updater.get_latest_release()?;
-    if bump_is_greater(cur_ver, &latest.version)? {
-        Ok(Some(updater))
-    } else {
-        Ok(None)
+    match band::Role::ALL[role] {
+        band::Role::Conductor => conduct_band(
+            theme,
+            conn,
+            picker,
+            window,
+            input_sleep_duration,
+            multi,
+            remote,
+            hotkeys,
+        ),
+        band::Role::Member => {
+            let address: String = Input::with_theme(theme)
+                .with_prompt("Conductor address")
+                .default(format!("127.0.0.1:{}", band::DEFAULT_PORT))
+                .interact_text()?;
+            let code: String = Input::with_theme(theme)
+                .with_prompt("Band code (the conductor has it)")
+                .interact_text()?;
+            let name: String = Input::with_theme(theme)
+                .with_prompt("Your name in the band")
+                .default("Member".to_string())
+                .interact_text()?;
+            if let Err(e) = band::join(
+                conn,
+                &address,
+                &code,
+                &name,
+                window,
+                input_sleep_duration,
+                multi,
+                remote,
+                hotkeys,
+            ) {
+                error!("Lost the band: {}", e);
+            }
+            Ok(())
+        }
     }
 }
 
-fn get_window(name: &str) -> Option<Window> {
-    let windows = Window::all().unwrap();
-    windows.into_iter().find(|w| w.app_name() == name)
-}
-
-fn get_midi_selection(theme: &ColorfulTheme, default_selection: usize) -> (PathBuf, usize) {
-    let mut current_dir = PathBuf::from(MIDI_DIR);
+#[allow(clippy::too_many_arguments)]
+fn conduct_band(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+    window: &Window,
+    input_sleep_duration: u64,
+    multi: &MultiProgress,
+    remote: &Remote,
+    hotkeys: &Hotkeys,
+) -> Result<(), dialoguer::Error> {
+    let port: u16 = Input::with_theme(theme)
+        .with_prompt("Port")
+        .default(band::DEFAULT_PORT)
+        .interact_text()?;
+    let conductor = match band::Conductor::listen(port) {
+        Ok(conductor) => conductor,
+        Err(e) => {
+            error!("Failed to start conducting: {}", e);
+            return Ok(());
+        }
+    };
+    info!(
+        "Conducting on port {} with band code {}, members join with this computer's IP, that port and the code",
+        conductor.port(),
+        conductor.code()
+    );
 
     loop {
-        let (midi_files, folder_names) = collect_midi_files(&current_dir);
-
-        let mut items: Vec<String> = Vec::new();
-
-        // Add an option to go to the parent directory
-        if current_dir != PathBuf::from(MIDI_DIR) {
-            items.push("..".to_string());
-        } else {
-            // Replace parent option with refresh in ./midi
-            items.push("[Refresh]".to_string());
+        let members = conductor.members();
+        let names: Vec<&str> = members.iter().map(|(_, name)| name.as_str()).collect();
+        let action = Select::with_theme(theme)
+            .with_prompt(format!(
+                "{} member(s) in the band: {}",
+                members.len(),
+                names.join(", ")
+            ))
+            .items(&band::ConductorAction::ALL.map(band::ConductorAction::label))
+            .default(0)
+            .interact()?;
+        match band::ConductorAction::ALL[action] {
+            band::ConductorAction::ChooseSong => {}
+            band::ConductorAction::Refresh => continue,
+            band::ConductorAction::Stop => return Ok(()),
         }
 
-        // Add folder names
-        items.extend(folder_names.iter().map(|name| format!("[Folder] {}", name)));
+        let Some((mut queued, mut settings)) = select_song(theme, conn, picker)? else {
+            continue;
+        };
+        let midi_data = match queued.song.load() {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to read song: {}", e);
+                continue;
+            }
+        };
+
+        let mut players = vec!["you".to_string()];
+        players.extend(names.iter().map(|name| name.to_string()));
+        let parts = assign_parts(
+            theme,
+            &settings.smf,
+            settings.tracks.as_deref().unwrap_or(&[]),
+            &players,
+        )?;
+        let assignments: Vec<(u64, Vec<usize>)> = members
+            .iter()
+            .map(|(id, _)| *id)
+            .zip(parts[1..].iter().cloned())
+            .collect();
 
-        // Add MIDI file names
-        items.extend(
-            midi_files
-                .iter()
-                .map(|path| path.file_name().unwrap().to_str().unwrap().to_string()),
+        let title = queued.song.display_name();
+        let ready = conductor.prepare(
+            &title,
+            &midi_data,
+            &assignments,
+            &settings.options,
+            settings.speed,
         );
+        info!("{} of {} members are ready", ready, members.len());
 
-        let selection = FuzzySelect::with_theme(theme)
-            .with_prompt("Select a midi file or folder to navigate")
-            .items(&items)
-            .default(default_selection)
-            .interact()
-            .unwrap();
-
-        if selection == 0 && current_dir == PathBuf::from(MIDI_DIR) {
-            // Refresh list
-            current_dir = current_dir;
-        } else if selection == 0 && current_dir.parent().is_some() {
-            // Navigate to the parent folder
-            current_dir = current_dir.parent().unwrap().to_path_buf();
-        } else if selection < folder_names.len() + 1 {
-            // Navigate into the selected folder
-            let selected_folder = &folder_names[selection - 1]; // Adjust index for folder selection
-            current_dir = current_dir.join(selected_folder); // Update current_dir to the selected folder
-        } else {
-            // Select a MIDI file
-            let midi_file_index = selection - folder_names.len() - 1; // Adjust index for MIDI file selection
-            return (midi_files[midi_file_index].clone(), midi_file_index);
+        settings.tracks = Some(parts[0].clone());
+        // The history has the conductor's own part
+        queued.profile.tracks = Some(parts[0].clone());
+        println!("Tab over to the game, {} starts in a few seconds", title);
+        let start = conductor.count_in();
+        remote.start_song(title.clone(), |_| {});
+        let started = SystemTime::now();
+        let played = conductor.play(
+            settings,
+            start,
+            window,
+            input_sleep_duration,
+            multi,
+            remote,
+            hotkeys,
+        );
+        remote.end_song();
+        if let Some((end, dropped_notes)) = played {
+            info!("{} ended: {:?}", title, end);
+            let completed = end == PlayEnd::Finished;
+            if let Err(e) = history::record(conn, &queued, started, completed, dropped_notes) {
+                error!("Failed to save play history: {}", e);
+            }
         }
     }
 }
 
-fn collect_midi_files(dir: &Path) -> (Vec<PathBuf>, Vec<String>) {
-    let mut midi_files = Vec::new();
-    let mut folder_names = Vec::new();
+// Asks which tracks each player gets, starting from the selected tracks dealt
+// out in turn
+fn assign_parts(
+    theme: &ColorfulTheme,
+    smf: &Smf,
+    selected: &[usize],
+    players: &[String],
+) -> Result<Vec<Vec<usize>>, dialoguer::Error> {
+    let labels: Vec<String> = (0..smf.tracks.len())
+        .map(|track| match analysis::track_name(smf, track) {
+            Some(name) if !name.is_empty() => format!("{}: {}", track, name),
+            _ => format!("{}: Unknown", track),
+        })
+        .collect();
 
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.filter_map(Result::ok) {
-            let path = entry.path();
-            if path.is_dir() {
-                // Collect folder names
-                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                    folder_names.push(name.to_string());
-                }
-            } else if path.extension().and_then(|s| s.to_str()) == Some("mid") {
-                // Collect MIDI files
-                midi_files.push(path);
+    players
+        .iter()
+        .enumerate()
+        .map(|(player_index, player)| {
+            let defaults: Vec<bool> = (0..smf.tracks.len())
+                .map(|track| {
+                    selected
+                        .iter()
+                        .position(|&selected| selected == track)
+                        .is_some_and(|position| position % players.len() == player_index)
+                })
+                .collect();
+            MultiSelect::with_theme(theme)
+                .with_prompt(format!("Which tracks does {} play?", player))
+                .items(&labels)
+                .defaults(&defaults)
+                .interact()
+        })
+        .collect()
+}
+
+fn start_remote_server(
+    theme: &ColorfulTheme,
+    remote: &Arc<Remote>,
+) -> Result<Option<SocketAddr>, dialoguer::Error> {
+    let lan = Confirm::with_theme(theme)
+        .with_prompt("Allow phones and other computers on your network to connect?")
+        .default(false)
+        .interact()?;
+    let port: u16 = Input::with_theme(theme)
+        .with_prompt("Port")
+        .default(server::DEFAULT_PORT)
+        .interact_text()?;
+
+    match server::start(Arc::clone(remote), DB_PATH, port, lan) {
+        Ok((addr, token)) => {
+            if let Some(token) = token {
+                info!(
+                    "Remote control running, open http://<this computer's IP>:{}/?token={} on your phone",
+                    addr.port(),
+                    token
+                );
+            } else {
+                info!("Remote control running at http://{}", addr);
             }
+            Ok(Some(addr))
+        }
+        Err(e) => {
+            error!("Failed to start the remote control server: {}", e);
+            Ok(None)
         }
-    } else {
-        error!("You need to place MIDI files in {}.", dir.display());
     }
+}
+
+fn analyze_song(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+    min_framerate: u64,
+) -> Result<(), dialoguer::Error> {
+    let Some((_, settings)) = select_song(theme, conn, picker)? else {
+        return Ok(());
+    };
 
-    (midi_files, folder_names)
+    println!(
+        "{}",
+        analysis::report(
+            &settings.smf,
+            settings.tracks.as_deref().unwrap_or(&[]),
+            &settings.options,
+            settings.speed,
+            min_framerate,
+        )
+    );
+    Ok(())
 }
 
-fn get_tracks_from_db(
-    midi_path: &str,
+fn library_report(
+    theme: &ColorfulTheme,
+    multi: &MultiProgress,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = Select::with_theme(theme)
+        .with_prompt("Which format should the report be in?")
+        .items(&ReportFormat::ALL.map(ReportFormat::label))
+        .default(0)
+        .interact()?;
+    let format = ReportFormat::ALL[format];
+
+    fs::create_dir_all(EXPORT_DIR)?;
+    let path = Path::new(EXPORT_DIR).join(format!("library-report.{}", format.extension()));
+    let (songs, failed) = batch::write_report(Path::new(MIDI_DIR), &path, format, multi)?;
+    info!(
+        "Wrote a report of {} songs ({} failed) to {}",
+        songs,
+        failed,
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn render_preview(
+    theme: &ColorfulTheme,
     conn: &Connection,
-) -> Result<Option<Vec<usize>>, rusqlite::Error> {
-    let mut stmt = conn.prepare("SELECT tracks FROM track_selections WHERE path = ?1;")?;
-    let result = stmt.query_row([midi_path], |row| {
-        let track_ids_str: String = row.get(0)?;
-        let track_ids = track_ids_str
-            .split(',')
-            .filter_map(|id| id.parse::<usize>().ok())
-            .collect();
-        Ok(track_ids)
-    });
+    picker: &mut Picker,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((QueuedSong { song, .. }, settings)) = select_song(theme, conn, picker)? else {
+        return Ok(());
+    };
+
+    let compare = Confirm::with_theme(theme)
+        .with_prompt("Also render the original notes for comparison?")
+        .default(false)
+        .interact()?;
+
+    let arrangement = Arrangement::new(
+        &settings.smf,
+        settings.tracks.as_deref().unwrap_or(&[]),
+        &settings.options,
+    );
+    info!(
+        "Shift {} | {} notes | {} clamped | {} dropped (no free string)",
+        arrangement.shift,
+        arrangement.notes.len(),
+        arrangement.clamped_notes(),
+        arrangement.dropped_notes()
+    );
+
+    let path = export_path(&song, "wav")?;
+    preview::write_wav(&path, &preview::render_arrangement(&arrangement))?;
+    info!("Wrote guitar preview to {}", path.display());
 
-    match result {
-        Ok(track_ids) => Ok(Some(track_ids)),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-        Err(err) => Err(err),
+    if compare {
+        let path = export_path(&song, "original.wav")?;
+        preview::write_wav(&path, &preview::render_original(&arrangement))?;
+        info!("Wrote original notes to {}", path.display());
     }
+
+    Ok(())
 }
 
-fn insert_tracks_to_db(
-    midi_path: &str,
-    tracks: &[usize],
+fn export_midi(
+    theme: &ColorfulTheme,
     conn: &Connection,
-) -> Result<(), rusqlite::Error> {
-    let track_ids_str = tracks
-        .iter()
-        .map(|id| id.to_string())
-        .collect::<Vec<String>>()
-        .join(","); // Convert track_ids to a comma-separated string
+    picker: &mut Picker,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((QueuedSong { song, .. }, settings)) = select_song(theme, conn, picker)? else {
+        return Ok(());
+    };
 
-    debug!("Inserting tracks: {}", track_ids_str);
+    let layout = Select::with_theme(theme)
+        .with_prompt("How should the notes be split into tracks?")
+        .items(&ExportLayout::ALL.map(ExportLayout::label))
+        .default(0)
+        .interact()?;
 
-    conn.execute(
-        "INSERT INTO track_selections (path, tracks)
-        VALUES (?1, ?2)
-        ON CONFLICT(path) DO UPDATE SET tracks = excluded.tracks;",
-        params![midi_path, track_ids_str],
+    let arrangement = Arrangement::new(
+        &settings.smf,
+        settings.tracks.as_deref().unwrap_or(&[]),
+        &settings.options,
+    );
+    let path = export_path(&song, "guitar.mid")?;
+    midi_export::export_arrangement(
+        &settings.smf,
+        &arrangement,
+        ExportLayout::ALL[layout],
+        &path,
     )?;
+    info!(
+        "Wrote {} notes to {}",
+        arrangement.notes.len() - arrangement.dropped_notes(),
+        path.display()
+    );
 
     Ok(())
 }
 
-fn get_tracks_selection(
-    midi_path: &PathBuf,
-    smf: &Smf,
+fn export_tab(
     theme: &ColorfulTheme,
     conn: &Connection,
-) -> Result<Vec<usize>, dialoguer::Error> {
-    let midi_path = match midi_path.to_str() {
-        Some(midi_path) => midi_path,
-        None => {
-            error!("Could not convert path to string");
-            pause_and_exit(-1);
-        }
+    picker: &mut Picker,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((QueuedSong { song, .. }, settings)) = select_song(theme, conn, picker)? else {
+        return Ok(());
     };
 
-    // If the midi_path is already in the DB then get the tracks from the DB
-    let saved_tracks = match get_tracks_from_db(midi_path, conn) {
-        Ok(tracks) => tracks,
-        Err(err) => {
-            error!("Failed to get tracks from database: {}", err);
-            pause_and_exit(-1);
-        }
+    let save_to_file = Select::with_theme(theme)
+        .with_prompt("Where should the tab go?")
+        .items(&["Print to terminal", "Save to file"])
+        .default(0)
+        .interact()?
+        == 1;
+
+    let arrangement = Arrangement::new(
+        &settings.smf,
+        settings.tracks.as_deref().unwrap_or(&[]),
+        &settings.options,
+    );
+    let tab = tablature::render_tab(&settings.smf, &arrangement);
+
+    if save_to_file {
+        let path = export_path(&song, "tab.txt")?;
+        fs::write(&path, tab)?;
+        info!("Wrote tab to {}", path.display());
+    } else {
+        println!("{}", tab);
+    }
+
+    Ok(())
+}
+
+// Shares the song's tracks out between band parts, shows the preservation
+// report and saves each part
+fn split_parts(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((QueuedSong { song, .. }, settings)) = select_song(theme, conn, picker)? else {
+        return Ok(());
     };
 
+    let players: usize = Input::with_theme(theme)
+        .with_prompt("How many players?")
+        .default(2)
+        .validate_with(|players: &usize| -> Result<(), String> {
+            if (splitter::MIN_PLAYERS..=splitter::MAX_PLAYERS).contains(players) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Must be between {} and {}",
+                    splitter::MIN_PLAYERS,
+                    splitter::MAX_PLAYERS
+                ))
+            }
+        })
+        .interact_text()?;
+
+    let split = BandSplit::new(
+        &settings.smf,
+        settings.tracks.as_deref().unwrap_or(&[]),
+        &settings.options,
+        players,
+    );
+    println!("{}", split.report());
+
+    let format = Select::with_theme(theme)
+        .with_prompt("How should the parts be saved?")
+        .items(&PartFormat::ALL.map(PartFormat::label))
+        .default(0)
+        .interact()?;
+    let format = PartFormat::ALL[format];
+
+    for (i, part) in split.parts.iter().enumerate() {
+        let name = split.part_name(i);
+        let path = export_path(&song, &format!("{}.{}", name, format.extension()))?;
+        match format {
+            PartFormat::Midi => midi_export::export_arrangement(
+                &settings.smf,
+                &part.arrangement,
+                ExportLayout::PerSourceTrack,
+                &path,
+            )?,
+            PartFormat::Timeline => splitter::write_timeline(&part.arrangement, &name, &path)?,
+        }
+        info!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
+
+// ./exports/<song name>.<extension>
+fn export_path(song: &SongSource, extension: &str) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(EXPORT_DIR)?;
+    Ok(Path::new(EXPORT_DIR).join(format!("{}.{}", song.file_stem(), extension)))
+}
+
+#[cfg(feature = "auto-update")]
+fn check_update(
+    cur_ver: &str,
+) -> Result<Option<Box<dyn ReleaseUpdate>>, self_update::errors::Error> {
+    let updater = self_update::backends::github::Update::configure()
+        .repo_owner("yobson1")
+        .verifying_keys([*include_bytes!("../pub.key")])
+        .repo_name("webfishing-midi")
+        .bin_name("webfishing-midi")
+        .show_download_progress(true)
+        .show_output(cfg!(debug_assertions))
+        .no_confirm(true)
+        .current_version(cur_ver)
+        .build()?;
+
+    let latest = updater.get_latest_release()?;
+    if bump_is_greater(cur_ver, &latest.version)? {
+        Ok(Some(updater))
+    } else {
+        Ok(None)
+    }
+}
+
+fn get_window(name: &str) -> Option<Window> {
+    let windows = Window::all().unwrap();
+    windows.into_iter().find(|w| w.app_name() == name)
+}
+
+fn get_tracks_selection(
+    smf: &Smf,
+    saved_tracks: Option<&[usize]>,
+    theme: &ColorfulTheme,
+) -> Result<Vec<usize>, dialoguer::Error> {
     // progams[track] = instrument
     let mut programs = vec![-1; smf.tracks.len()];
     // Get the "program"/instrument of each channel
     for (i, track) in smf.tracks.iter().enumerate() {
         for event in track {
-            match event.kind {
-                TrackEventKind::Midi { channel, message } => match message {
-                    MidiMessage::ProgramChange { program } => {
-                        debug!(
-                            "Program change: {} - {} channel {} track {}",
-                            program,
-                            INSTRUMENTS[program.as_int() as usize],
-                            channel,
-                            i
-                        );
-                        if channel == 9 {
-                            programs[i] = -128;
-                        } else {
-                            programs[i] = program.as_int() as i8;
-                        }
-                        break;
-                    }
-                    _ => {}
-                },
-                _ => continue,
+            if let TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::ProgramChange { program },
+            } = event.kind
+            {
+                debug!(
+                    "Program change: {} - {} channel {} track {}",
+                    program,
+                    INSTRUMENTS[program.as_int() as usize],
+                    channel,
+                    i
+                );
+                if channel == 9 {
+                    programs[i] = -128;
+                } else {
+                    programs[i] = program.as_int() as i8;
+                }
+                break;
             }
         }
     }
 
+    let scores = suggestion::score_tracks(smf);
+
     // Ask the user which tracks to play
     let mut builder = Builder::new();
-    builder.push_record(["Track #", "Track Name", "Program", "Instrument"]);
+    builder.push_record(["Track #", "Track Name", "Program", "Instrument", "Score"]);
     for (i, track) in smf.tracks.iter().enumerate() {
         let mut track_name = None;
         let mut instrument_name = None;
@@ -427,40 +1444,44 @@ fn get_tracks_selection(
             track_name.unwrap_or("Unknown"),
             program_name,
             instrument_name.unwrap_or("Unknown"),
+            &scores[i].map_or("-".to_string(), |score| format!("{:.0}", score)),
         ]);
     }
     let table = builder.build().with(Style::psql()).to_string();
     let tracks_tbl = table.split("\n").collect::<Vec<_>>();
     let tracks = &tracks_tbl[2..];
 
-    let defaults = if let Some(saved_tracks) = saved_tracks {
-        let mut defaults = vec![false; tracks.len()];
-        for track_index in saved_tracks {
-            if track_index < defaults.len() {
-                defaults[track_index] = true;
-            }
+    let preselected = match saved_tracks {
+        Some(saved_tracks) => saved_tracks.to_vec(),
+        None => {
+            let mode = Select::with_theme(theme)
+                .with_prompt("Which tracks should be suggested?")
+                .items(&SuggestMode::ALL.map(SuggestMode::label))
+                .default(0)
+                .interact()?;
+            suggestion::suggest_tracks(&scores, SuggestMode::ALL[mode])
         }
-        defaults
-    } else {
-        vec![true; tracks.len()]
     };
+    let mut defaults = vec![false; tracks.len()];
+    for track_index in preselected {
+        match defaults.get_mut(track_index) {
+            Some(default) => *default = true,
+            None => warn!(
+                "Saved track {} isn't in this file any more, it only has {} tracks",
+                track_index,
+                tracks.len()
+            ),
+        }
+    }
 
     let chosen_tracks = MultiSelect::with_theme(theme)
         .with_prompt(
             format!("Which tracks to play? (use arrow keys and space to select, enter to confirm)\n  {}\n  {}", tracks_tbl[0], tracks_tbl[1]),
         )
-        .items(&tracks)
+        .items(tracks)
         .defaults(&defaults)
         .interact()?;
 
-    match insert_tracks_to_db(midi_path, &chosen_tracks, conn) {
-        Ok(_) => {}
-        Err(err) => {
-            error!("Failed to insert tracks to database: {}", err);
-            pause_and_exit(-1);
-        }
-    };
-
     Ok(chosen_tracks)
 }
 
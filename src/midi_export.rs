@@ -0,0 +1,203 @@
+use crate::arrangement::{ArrangedNote, Arrangement, STRING_COUNT};
+use midly::{
+    num::u28, Format, Header, MetaMessage, MidiMessage, Smf, Track, TrackEvent, TrackEventKind,
+};
+use std::{collections::HashMap, io, path::Path, str};
+
+// Acoustic Guitar (nylon)
+const GUITAR_PROGRAM: u8 = 24;
+// Channel 10 is reserved for drums
+const DRUM_CHANNEL: u8 = 9;
+pub const STRING_NAMES: [&str; STRING_COUNT] = ["Low E", "A", "D", "G", "B", "High E"];
+
+#[derive(Clone, Copy)]
+pub enum ExportLayout {
+    PerString,
+    PerSourceTrack,
+}
+
+impl ExportLayout {
+    pub const ALL: [ExportLayout; 2] = [ExportLayout::PerString, ExportLayout::PerSourceTrack];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ExportLayout::PerString => "One track per guitar string",
+            ExportLayout::PerSourceTrack => "One track per source track",
+        }
+    }
+}
+
+/// Writes the notes the guitar will actually play as a Standard MIDI File,
+/// keeping the tempo and time signature of the source
+pub fn export_arrangement(
+    smf: &Smf,
+    arrangement: &Arrangement,
+    layout: ExportLayout,
+    path: &Path,
+) -> io::Result<()> {
+    let played = arrangement.notes.iter().filter(|n| n.position.is_some());
+
+    // (track name, notes)
+    let parts: Vec<(String, Vec<&ArrangedNote>)> = match layout {
+        ExportLayout::PerString => STRING_NAMES
+            .iter()
+            .enumerate()
+            .map(|(string, name)| {
+                let notes = played
+                    .clone()
+                    .filter(|n| n.position.is_some_and(|p| p.string == string as i32))
+                    .collect();
+                (format!("String {} ({})", string + 1, name), notes)
+            })
+            .collect(),
+        ExportLayout::PerSourceTrack => {
+            let mut by_track: Vec<usize> = played.clone().map(|n| n.track).collect();
+            by_track.sort_unstable();
+            by_track.dedup();
+            by_track
+                .into_iter()
+                .map(|track| {
+                    let notes = played.clone().filter(|n| n.track == track).collect();
+                    (source_track_name(smf, track), notes)
+                })
+                .collect()
+        }
+    };
+
+    let mut tracks = vec![conductor_track(smf)];
+    let mut channels = (0..16u8).filter(|&c| c != DRUM_CHANNEL).cycle();
+    for (name, notes) in &parts {
+        let channel = channels.next().unwrap();
+        let slot = |note: &ArrangedNote| match layout {
+            ExportLayout::PerString => note.position.map_or(0, |p| p.string as u8),
+            ExportLayout::PerSourceTrack => note.note,
+        };
+        tracks.push(note_track(name, notes, channel, slot));
+    }
+
+    Smf {
+        header: Header::new(Format::Parallel, smf.header.timing),
+        tracks,
+    }
+    .save(path)
+}
+
+pub fn source_track_name(smf: &Smf, track: usize) -> String {
+    smf.tracks[track]
+        .iter()
+        .find_map(|event| match event.kind {
+            TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+                str::from_utf8(name).ok().map(str::to_string)
+            }
+            _ => None,
+        })
+        .unwrap_or_else(|| format!("Track {}", track))
+}
+
+// Tempo, time and key signatures from every track of the source
+fn conductor_track<'a>(smf: &Smf<'a>) -> Track<'a> {
+    let mut events = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Meta(
+                MetaMessage::Tempo(_)
+                | MetaMessage::TimeSignature(..)
+                | MetaMessage::KeySignature(..),
+            ) = event.kind
+            {
+                events.push((tick, event.kind));
+            }
+        }
+    }
+    events.sort_by_key(|&(tick, _)| tick);
+    to_track(events)
+}
+
+fn note_track<'a>(
+    name: &'a str,
+    notes: &[&ArrangedNote],
+    channel: u8,
+    slot: impl Fn(&ArrangedNote) -> u8,
+) -> Track<'a> {
+    // A new note on the same string (or the same key) cuts the previous one off
+    let mut ends: Vec<u64> = notes.iter().map(|n| n.end_tick.max(n.tick + 1)).collect();
+    let mut last_in_slot: HashMap<u8, usize> = HashMap::new();
+    for (i, note) in notes.iter().enumerate() {
+        if let Some(prev) = last_in_slot.insert(slot(note), i) {
+            ends[prev] = ends[prev].min(note.tick);
+        }
+    }
+
+    // (tick, is note on, event) so note offs sort before note ons on the same tick
+    let mut events = vec![
+        (
+            0,
+            false,
+            TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())),
+        ),
+        (
+            0,
+            false,
+            TrackEventKind::Midi {
+                channel: channel.into(),
+                message: MidiMessage::ProgramChange {
+                    program: GUITAR_PROGRAM.into(),
+                },
+            },
+        ),
+    ];
+    for (note, &end) in notes.iter().zip(&ends) {
+        if end <= note.tick {
+            continue; // Same key struck twice at once, the first one is enough
+        }
+        events.push((
+            note.tick,
+            true,
+            TrackEventKind::Midi {
+                channel: channel.into(),
+                message: MidiMessage::NoteOn {
+                    key: note.note.into(),
+                    vel: note.velocity.into(),
+                },
+            },
+        ));
+        events.push((
+            end,
+            false,
+            TrackEventKind::Midi {
+                channel: channel.into(),
+                message: MidiMessage::NoteOff {
+                    key: note.note.into(),
+                    vel: 0.into(),
+                },
+            },
+        ));
+    }
+    events.sort_by_key(|&(tick, is_on, _)| (tick, is_on));
+    to_track(
+        events
+            .into_iter()
+            .map(|(tick, _, kind)| (tick, kind))
+            .collect(),
+    )
+}
+
+// Converts absolute ticks to deltas and terminates the track
+pub fn to_track(events: Vec<(u64, TrackEventKind)>) -> Track {
+    let mut last_tick = 0;
+    let mut track: Track = events
+        .into_iter()
+        .map(|(tick, kind)| {
+            let delta = u28::new((tick - last_tick).min(u28::max_value().as_int() as u64) as u32);
+            last_tick = tick;
+            TrackEvent { delta, kind }
+        })
+        .collect();
+    track.push(TrackEvent {
+        delta: 0.into(),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
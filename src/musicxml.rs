@@ -0,0 +1,760 @@
+use crate::{midi_export::to_track, songs};
+use midly::{num::u24, Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use quick_xml::{events::Event, Reader};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+const TICKS_PER_QUARTER: u64 = 480;
+const VELOCITY: u8 = 80;
+// Channel 10 is reserved for drums
+const DRUM_CHANNEL: u8 = 9;
+// Most times a repeated section is played, however many the file asks for
+const MAX_REPEAT_TIMES: u32 = 16;
+// Longest duration read, the largest gap a MIDI file can hold between events
+const MAX_DURATION_TICKS: u64 = 0x0FFF_FFFF;
+// Semitones above C for C D E F G A B
+const STEP_SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Reads a .musicxml/.xml file or a compressed .mxl archive as Standard MIDI
+/// File bytes with one track per part
+pub fn load(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let xml = if songs::extension(path).as_deref() == Some("mxl") {
+        read_compressed(path)?
+    } else {
+        fs::read_to_string(path)?
+    };
+    let root = parse_xml(&xml)?;
+    if root.name != "score-partwise" {
+        return Err(invalid_data(&format!(
+            "unsupported MusicXML root <{}>, only score-partwise is supported",
+            root.name
+        )));
+    }
+    score_to_smf(&root)
+}
+
+fn invalid_data(message: &str) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+// The score is the file named in META-INF/container.xml
+fn read_compressed(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+    let mut root_path = None;
+    if let Ok(mut container) = archive.by_name("META-INF/container.xml") {
+        let mut text = String::new();
+        container.read_to_string(&mut text)?;
+        root_path = parse_xml(&text)?
+            .descendants("rootfile")
+            .into_iter()
+            .find_map(|rootfile| rootfile.attr("full-path").map(str::to_string));
+    }
+    let root_path = match root_path {
+        Some(root_path) => root_path,
+        None => archive
+            .file_names()
+            .find(|name| {
+                !name.starts_with("META-INF")
+                    && (name.ends_with(".xml") || name.ends_with(".musicxml"))
+            })
+            .map(str::to_string)
+            .ok_or_else(|| invalid_data("no score found in archive"))?,
+    };
+
+    let mut text = String::new();
+    archive.by_name(&root_path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+// A minimal element tree, MusicXML is small enough to hold in memory
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.child(name).is_some()
+    }
+
+    fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.trim())
+    }
+
+    fn child_number<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.child_text(name)?.parse().ok()
+    }
+
+    fn descendants<'a>(&'a self, name: &str) -> Vec<&'a Element> {
+        let mut found = Vec::new();
+        for child in &self.children {
+            if child.name == name {
+                found.push(child);
+            }
+            found.extend(child.descendants(name));
+        }
+        found
+    }
+}
+
+fn parse_xml(xml: &str) -> Result<Element, Box<dyn Error>> {
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<Element> = Vec::new();
+    let new_element = |start: &quick_xml::events::BytesStart| Element {
+        name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+        attributes: start
+            .attributes()
+            .filter_map(Result::ok)
+            .map(|attr| {
+                (
+                    String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+                    attr.unescape_value()
+                        .map(|v| v.into_owned())
+                        .unwrap_or_default(),
+                )
+            })
+            .collect(),
+        children: Vec::new(),
+        text: String::new(),
+    };
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => stack.push(new_element(&start)),
+            Event::Empty(start) => {
+                let element = new_element(&start);
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            Event::Text(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&text.unescape().unwrap_or_default());
+                }
+            }
+            Event::End(_) => {
+                let element = stack.pop().ok_or_else(|| invalid_data("unbalanced XML"))?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            Event::Eof => return Err(invalid_data("unexpected end of XML")),
+            _ => {}
+        }
+    }
+}
+
+struct Measure {
+    // Ticks from the start of the measure to its end
+    length: u64,
+    // (start, end, key) relative to the measure start
+    notes: Vec<(u64, u64, u8)>,
+    // Notes tied into the next measure, by key
+    tied_out: Vec<u8>,
+    // Notes that continue a tie from the previous measure, by index in `notes`
+    tied_in: Vec<usize>,
+    tempos: Vec<(u64, u32)>,
+    time_signature: Option<(u8, u8)>,
+    key_signature: Option<(i8, bool)>,
+    forward_repeat: bool,
+    // How many times the section is played when the measure ends with a repeat
+    backward_repeat: Option<u32>,
+    endings: Vec<u32>,
+}
+
+struct Part {
+    name: String,
+    program: u8,
+    drums: bool,
+    measures: Vec<Measure>,
+}
+
+fn score_to_smf(root: &Element) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Part names and instruments from the part list
+    let mut part_info: HashMap<&str, (String, u8, bool)> = HashMap::new();
+    if let Some(part_list) = root.child("part-list") {
+        for score_part in part_list.children("score-part") {
+            let id = score_part.attr("id").unwrap_or_default();
+            let name = score_part
+                .child_text("part-name")
+                .filter(|name| !name.is_empty())
+                .unwrap_or(id)
+                .to_string();
+            let instrument = score_part.child("midi-instrument");
+            let program = instrument
+                .and_then(|i| i.child_number::<u8>("midi-program"))
+                .map_or(0, |p| p.clamp(1, 128) - 1);
+            let drums = instrument.and_then(|i| i.child_number::<u8>("midi-channel")) == Some(10);
+            part_info.insert(id, (name, program, drums));
+        }
+    }
+
+    let parts: Vec<Part> = root
+        .children("part")
+        .map(|part| {
+            let id = part.attr("id").unwrap_or_default();
+            let (name, program, drums) = part_info
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| (id.to_string(), 0, false));
+            Part {
+                name,
+                program,
+                drums,
+                measures: read_measures(part),
+            }
+        })
+        .collect();
+    let Some(first_part) = parts.first() else {
+        return Err(invalid_data("score has no parts"));
+    };
+
+    // Every part follows the repeats and voltas of the first one
+    let order = play_order(&first_part.measures);
+    let mut conductor = BTreeMap::new();
+    let mut tracks_notes = Vec::new();
+    for (part_index, part) in parts.iter().enumerate() {
+        let mut notes: Vec<(u64, u64, u8)> = Vec::new();
+        let mut open_ties: HashMap<u8, usize> = HashMap::new();
+        let mut measure_start = 0;
+        for &index in &order {
+            let Some(measure) = part.measures.get(index) else {
+                continue;
+            };
+            if part_index == 0 {
+                if let Some((numerator, denominator)) = measure.time_signature {
+                    conductor.insert(
+                        (measure_start, 0),
+                        TrackEventKind::Meta(MetaMessage::TimeSignature(
+                            numerator,
+                            denominator.trailing_zeros() as u8,
+                            24,
+                            8,
+                        )),
+                    );
+                }
+                if let Some((fifths, minor)) = measure.key_signature {
+                    conductor.insert(
+                        (measure_start, 1),
+                        TrackEventKind::Meta(MetaMessage::KeySignature(fifths, minor)),
+                    );
+                }
+                for &(offset, tempo) in &measure.tempos {
+                    conductor.insert(
+                        (measure_start + offset, 2),
+                        TrackEventKind::Meta(MetaMessage::Tempo(u24::new(tempo))),
+                    );
+                }
+            }
+
+            let mut tied_ends = HashMap::new();
+            for (i, &(start, end, key)) in measure.notes.iter().enumerate() {
+                let (start, end) = (measure_start + start, measure_start + end);
+                match open_ties.get(&key) {
+                    Some(&open) if measure.tied_in.contains(&i) => {
+                        notes[open].1 = end;
+                        tied_ends.insert(key, open);
+                    }
+                    _ => {
+                        notes.push((start, end, key));
+                        tied_ends.insert(key, notes.len() - 1);
+                    }
+                }
+            }
+            open_ties = measure
+                .tied_out
+                .iter()
+                .filter_map(|key| Some((*key, *tied_ends.get(key)?)))
+                .collect();
+            measure_start += measure.length;
+        }
+        tracks_notes.push(notes);
+    }
+
+    let mut tracks = vec![to_track(
+        conductor
+            .into_iter()
+            .map(|((tick, _), kind)| (tick, kind))
+            .collect(),
+    )];
+    let mut channels = (0..16u8).filter(|&c| c != DRUM_CHANNEL).cycle();
+    for (part, notes) in parts.iter().zip(&tracks_notes) {
+        let channel = if part.drums {
+            DRUM_CHANNEL
+        } else {
+            channels.next().unwrap()
+        };
+        let mut events = vec![
+            (
+                0,
+                false,
+                TrackEventKind::Meta(MetaMessage::TrackName(part.name.as_bytes())),
+            ),
+            (
+                0,
+                false,
+                TrackEventKind::Midi {
+                    channel: channel.into(),
+                    message: MidiMessage::ProgramChange {
+                        program: part.program.into(),
+                    },
+                },
+            ),
+        ];
+        for &(start, end, key) in notes {
+            events.push((
+                start,
+                true,
+                TrackEventKind::Midi {
+                    channel: channel.into(),
+                    message: MidiMessage::NoteOn {
+                        key: key.into(),
+                        vel: VELOCITY.into(),
+                    },
+                },
+            ));
+            events.push((
+                end,
+                false,
+                TrackEventKind::Midi {
+                    channel: channel.into(),
+                    message: MidiMessage::NoteOff {
+                        key: key.into(),
+                        vel: 0.into(),
+                    },
+                },
+            ));
+        }
+        // Note offs first so repeated notes aren't cut short
+        events.sort_by_key(|&(tick, is_on, _)| (tick, is_on));
+        tracks.push(to_track(
+            events
+                .into_iter()
+                .map(|(tick, _, kind)| (tick, kind))
+                .collect(),
+        ));
+    }
+
+    let smf = Smf {
+        header: Header::new(
+            Format::Parallel,
+            Timing::Metrical((TICKS_PER_QUARTER as u16).into()),
+        ),
+        tracks,
+    };
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_measures(part: &Element) -> Vec<Measure> {
+    let mut divisions = 1u64;
+    let mut in_ending: Vec<u32> = Vec::new();
+    let mut measures = Vec::new();
+
+    for element in part.children("measure") {
+        let mut measure = Measure {
+            length: 0,
+            notes: Vec::new(),
+            tied_out: Vec::new(),
+            tied_in: Vec::new(),
+            tempos: Vec::new(),
+            time_signature: None,
+            key_signature: None,
+            forward_repeat: false,
+            backward_repeat: None,
+            endings: in_ending.clone(),
+        };
+        let mut cursor = 0u64;
+        let mut last_start = 0u64;
+        let to_ticks = |duration: u64, divisions: u64| {
+            (duration.saturating_mul(TICKS_PER_QUARTER) / divisions).min(MAX_DURATION_TICKS)
+        };
+
+        for child in &element.children {
+            match child.name.as_str() {
+                "attributes" => {
+                    if let Some(value) = child.child_number::<u64>("divisions") {
+                        divisions = value.max(1);
+                    }
+                    if let Some(time) = child.child("time") {
+                        let beats = time
+                            .child_text("beats")
+                            .map(|b| b.split('+').filter_map(|n| n.parse::<u32>().ok()).sum())
+                            .unwrap_or(4u32);
+                        let beat_type = time.child_number::<u8>("beat-type").unwrap_or(4);
+                        if beat_type.is_power_of_two() {
+                            measure.time_signature = Some((beats.min(255) as u8, beat_type));
+                        }
+                    }
+                    if let Some(key) = child.child("key") {
+                        let fifths = key.child_number::<i8>("fifths").unwrap_or(0);
+                        let minor = key.child_text("mode") == Some("minor");
+                        measure.key_signature = Some((fifths.clamp(-7, 7), minor));
+                    }
+                }
+                "note" => {
+                    // Grace and cue notes take no time in the written rhythm
+                    if child.has("grace") || child.has("cue") {
+                        continue;
+                    }
+                    let duration = to_ticks(child.child_number("duration").unwrap_or(0), divisions);
+                    let start = if child.has("chord") {
+                        last_start
+                    } else {
+                        cursor
+                    };
+                    let end = start + duration;
+
+                    if let Some(key) = child.child("pitch").and_then(pitch_to_key) {
+                        let ties: Vec<&str> = child
+                            .children("tie")
+                            .filter_map(|tie| tie.attr("type"))
+                            .collect();
+                        if ties.contains(&"stop") {
+                            // Continues a note from earlier in this measure or the last one
+                            let earlier =
+                                measure.notes.iter().rposition(|&(_, note_end, note_key)| {
+                                    note_key == key && note_end == start
+                                });
+                            match earlier {
+                                Some(index) => measure.notes[index].1 = end,
+                                None => {
+                                    measure.tied_in.push(measure.notes.len());
+                                    measure.notes.push((start, end, key));
+                                }
+                            }
+                        } else {
+                            measure.notes.push((start, end, key));
+                        }
+                        measure.tied_out.retain(|&k| k != key);
+                        if ties.contains(&"start") {
+                            measure.tied_out.push(key);
+                        }
+                    }
+
+                    if !child.has("chord") {
+                        last_start = cursor;
+                        cursor += duration;
+                    }
+                }
+                "backup" => {
+                    let duration = to_ticks(child.child_number("duration").unwrap_or(0), divisions);
+                    cursor = cursor.saturating_sub(duration);
+                }
+                "forward" => {
+                    cursor += to_ticks(child.child_number("duration").unwrap_or(0), divisions);
+                }
+                "direction" | "sound" => {
+                    let sound = if child.name == "sound" {
+                        Some(child)
+                    } else {
+                        child.child("sound")
+                    };
+                    let tempo = sound
+                        .and_then(|sound| sound.attr("tempo"))
+                        .and_then(|tempo| tempo.parse::<f64>().ok())
+                        .filter(|&bpm| bpm > 0.0);
+                    if let Some(bpm) = tempo {
+                        let offset =
+                            to_ticks(child.child_number::<u64>("offset").unwrap_or(0), divisions);
+                        measure
+                            .tempos
+                            .push((cursor + offset, (60_000_000.0 / bpm) as u32));
+                    }
+                }
+                "barline" => {
+                    if let Some(repeat) = child.child("repeat") {
+                        match repeat.attr("direction") {
+                            Some("forward") => measure.forward_repeat = true,
+                            Some("backward") => {
+                                let times = repeat
+                                    .attr("times")
+                                    .and_then(|t| t.parse().ok())
+                                    .unwrap_or(2)
+                                    .clamp(1, MAX_REPEAT_TIMES);
+                                measure.backward_repeat = Some(times);
+                            }
+                            _ => {}
+                        }
+                    }
+                    if let Some(ending) = child.child("ending") {
+                        let numbers = ending
+                            .attr("number")
+                            .unwrap_or("1")
+                            .split([',', ' '])
+                            .filter_map(|n| n.trim().parse().ok())
+                            .collect();
+                        match ending.attr("type") {
+                            Some("start") => {
+                                measure.endings = numbers;
+                                in_ending = measure.endings.clone();
+                            }
+                            Some("stop") | Some("discontinue") => in_ending.clear(),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+            measure.length = measure.length.max(cursor);
+        }
+        measures.push(measure);
+    }
+    measures
+}
+
+fn pitch_to_key(pitch: &Element) -> Option<u8> {
+    let step = pitch.child_text("step")?.chars().next()?;
+    let step = "CDEFGAB".find(step.to_ascii_uppercase())?;
+    let octave: i32 = pitch.child_number("octave")?;
+    let alter = pitch
+        .child_number::<f32>("alter")
+        .map_or(0, |alter| alter.round() as i32);
+    let key = (octave + 1) * 12 + STEP_SEMITONES[step] + alter;
+    (0..=127).contains(&key).then_some(key as u8)
+}
+
+// Measure indices in the order they are played, following repeats and voltas
+fn play_order(measures: &[Measure]) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut repeat_start = 0;
+    let mut pass = 1;
+    let mut jumped = false;
+    let mut i = 0;
+
+    while i < measures.len() {
+        let measure = &measures[i];
+        if measure.forward_repeat && !jumped {
+            repeat_start = i;
+            pass = 1;
+        }
+        jumped = false;
+
+        if !measure.endings.is_empty() && !measure.endings.contains(&pass) {
+            i += 1;
+            continue;
+        }
+        // Leaving the voltas, later repeats start counting again
+        if measure.endings.is_empty() && i > 0 && !measures[i - 1].endings.is_empty() {
+            if pass > 1 && !measure.forward_repeat {
+                repeat_start = i;
+            }
+            pass = 1;
+        }
+
+        order.push(i);
+        if let Some(times) = measure.backward_repeat {
+            if pass < times {
+                pass += 1;
+                i = repeat_start;
+                jumped = true;
+                continue;
+            }
+            pass = 1;
+            repeat_start = i + 1;
+        }
+        i += 1;
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUARTER: u64 = TICKS_PER_QUARTER;
+
+    fn note(step: char, octave: u8, duration: u64, extra: &str) -> String {
+        format!(
+            "<note>{}<pitch><step>{}</step><octave>{}</octave></pitch><duration>{}</duration></note>",
+            extra, step, octave, duration
+        )
+    }
+
+    // (start, end, key) of every note of a one part score
+    fn notes(measures: &[String]) -> Vec<(u64, u64, u8)> {
+        let measures: String = measures
+            .iter()
+            .map(|measure| format!("<measure>{}</measure>", measure))
+            .collect();
+        let xml = format!(
+            "<score-partwise><part-list><score-part id=\"P1\"><part-name>Piano</part-name></score-part></part-list><part id=\"P1\">{}</part></score-partwise>",
+            measures
+        );
+        let bytes = score_to_smf(&parse_xml(&xml).unwrap()).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+
+        let mut notes = Vec::new();
+        let mut open: HashMap<u8, u64> = HashMap::new();
+        let mut tick = 0;
+        for event in &smf.tracks[1] {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { key, .. },
+                    ..
+                } => {
+                    open.insert(key.as_int(), tick);
+                }
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOff { key, .. },
+                    ..
+                } => {
+                    let start = open.remove(&key.as_int()).unwrap();
+                    notes.push((start, tick, key.as_int()));
+                }
+                _ => {}
+            }
+        }
+        notes.sort_unstable();
+        notes
+    }
+
+    fn divisions(divisions: u64) -> String {
+        format!(
+            "<attributes><divisions>{}</divisions></attributes>",
+            divisions
+        )
+    }
+
+    #[test]
+    fn divisions_set_the_length_of_a_quarter() {
+        let measure = divisions(4) + &note('C', 4, 4, "") + &note('D', 4, 2, "");
+        assert_eq!(
+            notes(&[measure]),
+            [(0, QUARTER, 60), (QUARTER, QUARTER * 3 / 2, 62)]
+        );
+        let measure = divisions(1) + &note('C', 4, 2, "");
+        assert_eq!(notes(&[measure]), [(0, QUARTER * 2, 60)]);
+    }
+
+    #[test]
+    fn backup_and_forward_move_the_cursor() {
+        let measure = divisions(1)
+            + &note('C', 4, 2, "")
+            + "<backup><duration>2</duration></backup>"
+            + "<forward><duration>1</duration></forward>"
+            + &note('E', 4, 1, "");
+        assert_eq!(
+            notes(&[measure]),
+            [(0, QUARTER * 2, 60), (QUARTER, QUARTER * 2, 64)]
+        );
+    }
+
+    #[test]
+    fn chord_notes_start_with_the_note_before() {
+        let measure = divisions(1)
+            + &note('C', 4, 1, "")
+            + &note('E', 4, 1, "<chord/>")
+            + &note('G', 4, 1, "<chord/>")
+            + &note('C', 5, 1, "");
+        assert_eq!(
+            notes(&[measure]),
+            [
+                (0, QUARTER, 60),
+                (0, QUARTER, 64),
+                (0, QUARTER, 67),
+                (QUARTER, QUARTER * 2, 72)
+            ]
+        );
+    }
+
+    #[test]
+    fn ties_join_notes() {
+        let measure = divisions(1)
+            + &note('C', 4, 1, "<tie type=\"start\"/>")
+            + &note('C', 4, 1, "<tie type=\"stop\"/>");
+        assert_eq!(notes(&[measure]), [(0, QUARTER * 2, 60)]);
+
+        // Across the bar line
+        let first = divisions(1) + &note('C', 4, 4, "<tie type=\"start\"/>");
+        let second = note('C', 4, 2, "<tie type=\"stop\"/>") + &note('D', 4, 2, "");
+        assert_eq!(
+            notes(&[first, second]),
+            [(0, QUARTER * 6, 60), (QUARTER * 6, QUARTER * 8, 62)]
+        );
+    }
+
+    #[test]
+    fn repeats_and_endings() {
+        let forward = "<barline><repeat direction=\"forward\"/></barline>";
+        let backward = "<barline><repeat direction=\"backward\"/></barline>";
+        let measures = [
+            divisions(1) + forward + &note('C', 4, 1, ""),
+            note('D', 4, 1, "") + backward,
+            note('E', 4, 1, ""),
+        ];
+        let keys: Vec<u8> = notes(&measures)
+            .into_iter()
+            .map(|(_, _, key)| key)
+            .collect();
+        assert_eq!(keys, [60, 62, 60, 62, 64]);
+
+        let measures = [
+            divisions(1) + &note('C', 4, 1, ""),
+            "<barline><ending number=\"1\" type=\"start\"/></barline>".to_string()
+                + &note('D', 4, 1, "")
+                + "<barline><ending number=\"1\" type=\"stop\"/><repeat direction=\"backward\"/></barline>",
+            "<barline><ending number=\"2\" type=\"start\"/></barline>".to_string()
+                + &note('E', 4, 1, "")
+                + "<barline><ending number=\"2\" type=\"discontinue\"/></barline>",
+            note('F', 4, 1, ""),
+        ];
+        let keys: Vec<u8> = notes(&measures)
+            .into_iter()
+            .map(|(_, _, key)| key)
+            .collect();
+        assert_eq!(keys, [60, 62, 60, 64, 65]);
+    }
+
+    #[test]
+    fn repeat_times_are_limited() {
+        let measure = divisions(1)
+            + &note('C', 4, 1, "")
+            + "<barline><repeat direction=\"backward\" times=\"4000000000\"/></barline>";
+        assert_eq!(notes(&[measure]).len(), MAX_REPEAT_TIMES as usize);
+
+        let measure = divisions(1)
+            + &note('C', 4, 1, "")
+            + "<barline><repeat direction=\"backward\" times=\"0\"/></barline>";
+        assert_eq!(notes(&[measure]).len(), 1);
+    }
+
+    #[test]
+    fn huge_durations_are_limited() {
+        let measure = divisions(1)
+            + &note('C', 4, u64::MAX, "")
+            + &note('D', 4, u64::MAX, "")
+            + "<forward><duration>18446744073709551615</duration></forward>"
+            + &note('E', 4, 1, "");
+        let notes = notes(&[measure]);
+        assert_eq!(notes.len(), 3);
+        assert_eq!(notes[0], (0, MAX_DURATION_TICKS, 60));
+    }
+}
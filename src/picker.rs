@@ -0,0 +1,602 @@
+use crate::{
+    analysis,
+    arrangement::{note_name, ArrangeOptions, Arrangement},
+    history,
+    instruments::track_instrument,
+    library::{self, LibraryEntry},
+    preview_select::{self, Selection},
+    profiles, screen,
+    songs::{self, SongSource},
+    tablature::time_signatures,
+    watcher::LibraryWatcher,
+    DB_PATH, MIDI_DIR,
+};
+use dialoguer::{theme::ColorfulTheme, FuzzySelect, Input};
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use indicatif::ProgressBar;
+use log::{error, info, warn};
+use midly::{MetaMessage, Smf, TrackEventKind};
+use rusqlite::Connection;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::SystemTime,
+};
+use tabled::{builder::Builder, settings::Style};
+
+// Longest name shown in the song table before it gets cut off
+const MAX_NAME_WIDTH: usize = 40;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    Name,
+    Duration,
+    Notes,
+    Playability,
+    Tempo,
+}
+
+impl SortBy {
+    const ALL: [SortBy; 5] = [
+        SortBy::Name,
+        SortBy::Duration,
+        SortBy::Notes,
+        SortBy::Playability,
+        SortBy::Tempo,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            SortBy::Name => "Name",
+            SortBy::Duration => "Length",
+            SortBy::Notes => "Notes",
+            SortBy::Playability => "Playable",
+            SortBy::Tempo => "BPM",
+        }
+    }
+
+    fn next(self) -> SortBy {
+        let index = SortBy::ALL
+            .iter()
+            .position(|&sort| sort == self)
+            .unwrap_or(0);
+        SortBy::ALL[(index + 1) % SortBy::ALL.len()]
+    }
+
+    fn compare(self, a: Option<&LibraryEntry>, b: Option<&LibraryEntry>) -> Ordering {
+        // Songs that aren't indexed go last
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) => (a, b),
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => return Ordering::Equal,
+        };
+        match self {
+            SortBy::Name => Ordering::Equal,
+            SortBy::Duration => a.duration_secs.total_cmp(&b.duration_secs),
+            SortBy::Notes => a.note_count.cmp(&b.note_count),
+            // Most playable first
+            SortBy::Playability => b.playability.total_cmp(&a.playability),
+            SortBy::Tempo => a.tempo_bpm.total_cmp(&b.tempo_bpm),
+        }
+    }
+}
+
+enum Item {
+    Recent,
+    Favourites,
+    Refresh,
+    Parent,
+    Sort,
+    Search,
+    NewSearch,
+    Browse,
+    Folder(String),
+    Song(SongSource),
+}
+
+/// The song browser, remembers the last song, folder, search and sort order
+/// between uses
+pub struct Picker {
+    current_dir: PathBuf,
+    // Searching the whole library instead of browsing folders when set
+    query: Option<String>,
+    last_song: Option<SongSource>,
+    sort: SortBy,
+    previews: Previews,
+    // Keeps the index up to date, "[Refresh]" is offered instead when the
+    // folder can't be watched
+    watcher: Option<LibraryWatcher>,
+}
+
+impl Picker {
+    pub fn new() -> Self {
+        Picker {
+            current_dir: PathBuf::from(MIDI_DIR),
+            query: None,
+            last_song: None,
+            sort: SortBy::Name,
+            previews: Previews::new(),
+            watcher: LibraryWatcher::start(Path::new(MIDI_DIR))
+                .map_err(|e| warn!("Not watching {} for new songs: {}", MIDI_DIR, e))
+                .ok(),
+        }
+    }
+
+    // Indexes the songs added, changed or removed since the last look
+    fn refresh(&mut self, conn: &Connection) {
+        self.previews.clear();
+        // Only what changed is parsed, quick enough to go without a bar
+        if let Err(e) = library::index_library(conn, Path::new(MIDI_DIR), &ProgressBar::hidden()) {
+            error!("Failed to index the library: {}", e);
+        }
+        // The folder being browsed may be gone
+        if !self.current_dir.is_dir() {
+            self.current_dir = PathBuf::from(MIDI_DIR);
+        }
+    }
+
+    fn library_changed(&self) -> bool {
+        self.watcher.as_ref().is_some_and(LibraryWatcher::changed)
+    }
+
+    pub fn select(
+        &mut self,
+        theme: &ColorfulTheme,
+        conn: &Connection,
+    ) -> Result<SongSource, dialoguer::Error> {
+        // Highlight and filter to keep when the list is shown again after a change
+        let mut highlighted: Option<SongSource> = None;
+        let mut filter = String::new();
+        loop {
+            if self.library_changed() {
+                self.refresh(conn);
+            }
+            let entries = library::load_entries(conn).unwrap_or_else(|e| {
+                error!("Failed to load the library index: {}", e);
+                HashMap::new()
+            });
+
+            let mut items = Vec::new();
+            let (prompt, songs) = match &self.query {
+                Some(query) => {
+                    items.extend([Item::NewSearch, Item::Browse]);
+                    let songs = search(&entries, query);
+                    (format!("{} results for \"{}\"", songs.len(), query), songs)
+                }
+                None => {
+                    let (mut midi_files, folder_names) = collect_midi_files(&self.current_dir);
+                    midi_files.sort_by(|a, b| {
+                        self.sort
+                            .compare(entries.get(&a.key()), entries.get(&b.key()))
+                            .then_with(|| a.display_name().cmp(&b.display_name()))
+                    });
+
+                    items.extend([Item::Recent, Item::Favourites]);
+                    if self.current_dir != Path::new(MIDI_DIR) {
+                        items.push(Item::Parent);
+                    } else if self.watcher.is_none() {
+                        items.push(Item::Refresh);
+                    }
+                    items.extend([Item::Search, Item::Sort]);
+                    items.extend(folder_names.into_iter().map(Item::Folder));
+                    (
+                        "Select a midi file or folder to navigate".to_string(),
+                        midi_files,
+                    )
+                }
+            };
+
+            let (header, rows) = song_table(&songs, &entries);
+            let mut labels: Vec<String> = items
+                .iter()
+                .map(|item| match item {
+                    Item::Recent => "[Recently played]".to_string(),
+                    Item::Favourites => "[Favourites]".to_string(),
+                    Item::Refresh => "[Refresh]".to_string(),
+                    Item::Parent => "..".to_string(),
+                    Item::Sort => format!("[Sort: {}]", self.sort.label()),
+                    Item::Search => "[Search all songs]".to_string(),
+                    Item::NewSearch => "[New search]".to_string(),
+                    Item::Browse => "[Browse folders]".to_string(),
+                    Item::Folder(name) => format!("[Folder] {}", name),
+                    Item::Song(_) => String::new(),
+                })
+                .collect();
+            labels.extend(rows);
+            items.extend(songs.into_iter().map(Item::Song));
+
+            let keep = highlighted.take().or_else(|| self.last_song.clone());
+            let default_selection = items
+                .iter()
+                .position(|item| matches!(item, Item::Song(song) if Some(song) == keep.as_ref()))
+                .unwrap_or(0);
+
+            let selection = if screen::enabled() {
+                let previews = &mut self.previews;
+                let watcher = &self.watcher;
+                let selection = preview_select::select(
+                    &format!("{}{}", prompt, header),
+                    &labels,
+                    default_selection,
+                    &std::mem::take(&mut filter),
+                    |i| match &items[i] {
+                        Item::Song(song) => previews.get(song),
+                        _ => Some(Vec::new()),
+                    },
+                    || watcher.as_ref().is_some_and(LibraryWatcher::changed),
+                )?;
+                match selection {
+                    Selection::Picked(selection) => selection,
+                    Selection::Changed {
+                        highlighted: index,
+                        query,
+                    } => {
+                        if let Some(Item::Song(song)) = items.get(index) {
+                            highlighted = Some(song.clone());
+                        }
+                        filter = query;
+                        self.refresh(conn);
+                        continue;
+                    }
+                }
+            } else {
+                // Can't be interrupted, changes are picked up before the next prompt
+                FuzzySelect::with_theme(theme)
+                    .with_prompt(format!("{}{}", prompt, header))
+                    .items(&labels)
+                    .default(default_selection)
+                    .interact()?
+            };
+
+            match items.swap_remove(selection) {
+                Item::Recent => {
+                    let songs = history::recently_played(conn);
+                    if let Some(song) = pick_shortcut(theme, &entries, "Recently played", songs)? {
+                        self.last_song = Some(song.clone());
+                        return Ok(song);
+                    }
+                }
+                Item::Favourites => {
+                    let songs = history::favourites(conn);
+                    if let Some(song) = pick_shortcut(theme, &entries, "Favourites", songs)? {
+                        self.last_song = Some(song.clone());
+                        return Ok(song);
+                    }
+                }
+                Item::Refresh => self.refresh(conn),
+                Item::Parent => {
+                    if let Some(parent) = self.current_dir.parent() {
+                        self.current_dir = parent.to_path_buf();
+                    }
+                }
+                Item::Sort => self.sort = self.sort.next(),
+                Item::Search | Item::NewSearch => {
+                    let query: String = Input::with_theme(theme)
+                        .with_prompt("Search all songs (file name, title, track names)")
+                        .allow_empty(true)
+                        .interact_text()?;
+                    self.query = Some(query);
+                }
+                Item::Browse => self.query = None,
+                Item::Folder(name) => self.current_dir.push(name),
+                Item::Song(song) => {
+                    self.last_song = Some(song.clone());
+                    return Ok(song);
+                }
+            }
+        }
+    }
+}
+
+// Picks from a short list of songs from the play history
+fn pick_shortcut(
+    theme: &ColorfulTheme,
+    entries: &HashMap<String, LibraryEntry>,
+    prompt: &str,
+    songs: rusqlite::Result<Vec<SongSource>>,
+) -> Result<Option<SongSource>, dialoguer::Error> {
+    let songs = songs.unwrap_or_else(|e| {
+        error!("Failed to read the play history: {}", e);
+        Vec::new()
+    });
+    if songs.is_empty() {
+        info!("Nothing here yet, play some songs first");
+        return Ok(None);
+    }
+
+    let (header, rows) = song_table(&songs, entries);
+    let mut labels = vec!["[Back]".to_string()];
+    labels.extend(rows);
+    let selection = FuzzySelect::with_theme(theme)
+        .with_prompt(format!("{}{}", prompt, header))
+        .items(&labels)
+        .default(1)
+        .interact()?;
+
+    Ok(selection.checked_sub(1).map(|index| songs[index].clone()))
+}
+
+// Every indexed song that fuzzy matches the query, best matches first
+fn search(entries: &HashMap<String, LibraryEntry>, query: &str) -> Vec<SongSource> {
+    let matcher = SkimMatcherV2::default();
+    let mut results: Vec<(i64, &LibraryEntry)> = entries
+        .values()
+        .filter_map(|entry| {
+            [
+                relative_name(&entry.song),
+                entry.title.clone(),
+                entry.search_text.clone(),
+            ]
+            .iter()
+            .filter_map(|text| matcher.fuzzy_match(text, query))
+            .max()
+            .map(|score| (score, entry))
+        })
+        .collect();
+    results.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then_with(|| relative_name(&a.song).cmp(&relative_name(&b.song)))
+    });
+    results
+        .into_iter()
+        .map(|(_, entry)| entry.song.clone())
+        .collect()
+}
+
+// A song's key with the modification time and size of its file, so an edited
+// file gets a new preview
+type PreviewKey = (String, Option<SystemTime>, u64);
+
+// Preview lines worked out on a background thread the first time a song is
+// highlighted, so a large file doesn't hold up scrolling
+struct Previews {
+    done: HashMap<PreviewKey, Vec<String>>,
+    // The last song sent to the thread
+    requested: Option<PreviewKey>,
+    requests: Sender<(PreviewKey, SongSource)>,
+    results: Receiver<(PreviewKey, Vec<String>)>,
+}
+
+impl Previews {
+    fn new() -> Self {
+        let (requests, incoming) = mpsc::channel::<(PreviewKey, SongSource)>();
+        let (outgoing, results) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(mut request) = incoming.recv() {
+                // Songs scrolled past before their turn aren't worth working out
+                while let Ok(newer) = incoming.try_recv() {
+                    request = newer;
+                }
+                let (key, song) = request;
+                let lines = song_preview(&song)
+                    .unwrap_or_else(|e| vec![format!("Can't preview this song: {}", e)]);
+                if outgoing.send((key, lines)).is_err() {
+                    break;
+                }
+            }
+        });
+        Previews {
+            done: HashMap::new(),
+            requested: None,
+            requests,
+            results,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.done.clear();
+        self.requested = None;
+    }
+
+    /// The preview of `song`, None while it's being worked out
+    fn get(&mut self, song: &SongSource) -> Option<Vec<String>> {
+        self.done.extend(self.results.try_iter());
+        let metadata = fs::metadata(&song.path).ok();
+        let key = (
+            song.key(),
+            metadata
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok()),
+            metadata.map_or(0, |metadata| metadata.len()),
+        );
+        if let Some(lines) = self.done.get(&key) {
+            return Some(lines.clone());
+        }
+        if self.requested.as_ref() != Some(&key) {
+            self.requested = Some(key.clone());
+            // The thread only stops with the picker
+            let _ = self.requests.send((key, song.clone()));
+        }
+        None
+    }
+}
+
+// What the preview pane shows for a song: its length, tempo, time signature,
+// how much of it fits the guitar, the saved settings and its tracks
+fn song_preview(song: &SongSource) -> Result<Vec<String>, Box<dyn Error>> {
+    // The picker's connection stays on the UI thread
+    let conn = Connection::open(DB_PATH)?;
+    let data = song.load()?;
+    let smf = Smf::parse(&data)?;
+    let all_tracks: Vec<usize> = (0..smf.tracks.len()).collect();
+    let arrangement = Arrangement::new(&smf, &all_tracks, &ArrangeOptions::default());
+
+    let tempos: Vec<u32> = smf
+        .tracks
+        .iter()
+        .flatten()
+        .filter_map(|event| match event.kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => Some(tempo.as_int()),
+            _ => None,
+        })
+        .collect();
+    let bpm = 60_000_000.0 / tempos.first().copied().unwrap_or(500_000).max(1) as f64;
+    let tempo = match tempos.len() {
+        0 | 1 => format!("Tempo: {:.0} BPM", bpm),
+        changes => format!("Tempo: {:.0} BPM, {} tempo changes", bpm, changes - 1),
+    };
+    let signatures = time_signatures(&smf);
+    let signature = match signatures.values().next() {
+        Some((numerator, denominator)) if signatures.len() > 1 => format!(
+            "Time signature: {}/{}, changes {} times",
+            numerator,
+            denominator,
+            signatures.len() - 1
+        ),
+        Some((numerator, denominator)) => format!("Time signature: {}/{}", numerator, denominator),
+        None => "Time signature: 4/4 (not set)".to_string(),
+    };
+
+    let mut lines = vec![
+        song.display_name(),
+        String::new(),
+        format!(
+            "Length: {}",
+            format_duration(arrangement.length_micros as f64 / 1_000_000.0)
+        ),
+        tempo,
+        signature,
+        format!(
+            "Playable: {:.0}% of every track at shift {:+}",
+            arrangement.playability(),
+            arrangement.shift
+        ),
+    ];
+    match profiles::get_profile(&conn, song, &library::content_hash(&data))? {
+        Some(profile) => {
+            let tracks = profile.tracks.clone().unwrap_or(all_tracks);
+            let saved = Arrangement::new(&smf, &tracks, &profile.options);
+            lines.push(format!("Saved: {}", profile.summary()));
+            lines.push(format!(
+                "Playable as saved: {:.0}% at shift {:+}",
+                saved.playability(),
+                saved.shift
+            ));
+        }
+        None => lines.push("Saved: no track selection yet".to_string()),
+    }
+
+    lines.push(String::new());
+    lines.push(format!("Tracks ({}):", smf.tracks.len()));
+    for track in 0..smf.tracks.len() {
+        let name = analysis::track_name(&smf, track)
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "Unnamed".to_string());
+        lines.push(format!(
+            "{:>2} {} - {}, {} notes",
+            track,
+            name,
+            track_instrument(&smf, track),
+            analysis::track_keys(&smf, track).len()
+        ));
+    }
+    Ok(lines)
+}
+
+// The song's name with the folders it's in below the MIDI directory
+fn relative_name(song: &SongSource) -> String {
+    let folder = song
+        .path
+        .parent()
+        .and_then(|parent| parent.strip_prefix(MIDI_DIR).ok())
+        .unwrap_or(Path::new(""));
+    folder
+        .join(song.display_name())
+        .to_string_lossy()
+        .into_owned()
+}
+
+// The header lines for the prompt and a table row for each song
+fn song_table(
+    songs: &[SongSource],
+    entries: &HashMap<String, LibraryEntry>,
+) -> (String, Vec<String>) {
+    if songs.is_empty() {
+        return (String::new(), Vec::new());
+    }
+
+    let mut builder = Builder::new();
+    builder.push_record([
+        "Name", "Title", "Length", "Tracks", "Channels", "Notes", "Range", "BPM", "Playable",
+    ]);
+    for song in songs {
+        let name = truncate(&relative_name(song));
+        match entries.get(&song.key()) {
+            Some(entry) => builder.push_record([
+                name,
+                truncate(&entry.title),
+                format_duration(entry.duration_secs),
+                entry.track_count.to_string(),
+                entry.channel_count.to_string(),
+                entry.note_count.to_string(),
+                match (entry.min_note, entry.max_note) {
+                    (Some(min), Some(max)) => format!("{}-{}", note_name(min), note_name(max)),
+                    _ => "-".to_string(),
+                },
+                format!("{:.0}", entry.tempo_bpm),
+                format!("{:.0}%", entry.playability),
+            ]),
+            None => {
+                let mut record = vec!["-".to_string(); 9];
+                record[0] = name;
+                builder.push_record(record);
+            }
+        }
+    }
+
+    let table = builder.build().with(Style::psql()).to_string();
+    let mut lines = table.split('\n').map(str::to_string);
+    let header = format!(
+        "\n  {}\n  {}",
+        lines.next().unwrap_or_default(),
+        lines.next().unwrap_or_default()
+    );
+    (header, lines.collect())
+}
+
+fn truncate(text: &str) -> String {
+    if text.chars().count() > MAX_NAME_WIDTH {
+        let mut text: String = text.chars().take(MAX_NAME_WIDTH - 3).collect();
+        text.push_str("...");
+        text
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+fn collect_midi_files(dir: &Path) -> (Vec<SongSource>, Vec<String>) {
+    let mut midi_files = Vec::new();
+    let mut folder_names = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if path.is_dir() {
+                // Collect folder names
+                if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                    folder_names.push(name.to_string());
+                }
+            } else {
+                // Collect MIDI files and the tunes of ABC files
+                midi_files.extend(songs::songs_in_file(&path));
+            }
+        }
+    } else {
+        error!("You need to place MIDI files in {}.", dir.display());
+    }
+    folder_names.sort();
+
+    (midi_files, folder_names)
+}
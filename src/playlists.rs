@@ -0,0 +1,300 @@
+use crate::{
+    library::add_column,
+    picker::Picker,
+    profiles::{join_tracks, profile_from_row},
+    queue::{self, EditMode, Queue, QueuedSong},
+    songs::SongSource,
+};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
+use log::{info, warn};
+use rusqlite::{params, Connection};
+use std::{error::Error, path::PathBuf};
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS playlists (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE);",
+        (),
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS playlist_songs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            playlist_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            path TEXT NOT NULL,
+            tune INTEGER,
+            title TEXT,
+            tracks TEXT,
+            loop_midi INTEGER NOT NULL,
+            loops INTEGER NOT NULL DEFAULT 1);",
+        (),
+    )?;
+    // Songs saved before loop counts could only loop forever or play once
+    if add_column(
+        conn,
+        "playlist_songs",
+        "loops",
+        "INTEGER NOT NULL DEFAULT 1",
+    )? {
+        conn.execute(
+            "UPDATE playlist_songs SET loops = 0 WHERE loop_midi = 1;",
+            (),
+        )?;
+    }
+    Ok(())
+}
+
+struct Playlist {
+    id: i64,
+    name: String,
+    song_count: usize,
+}
+
+fn get_playlists(conn: &Connection) -> rusqlite::Result<Vec<Playlist>> {
+    let mut stmt = conn.prepare(
+        "SELECT playlists.id, playlists.name, COUNT(playlist_songs.id)
+        FROM playlists LEFT JOIN playlist_songs ON playlist_songs.playlist_id = playlists.id
+        GROUP BY playlists.id ORDER BY playlists.name;",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Playlist {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            song_count: row.get::<_, i64>(2)? as usize,
+        })
+    })?;
+    rows.collect()
+}
+
+fn get_songs(conn: &Connection, playlist_id: i64) -> rusqlite::Result<Vec<QueuedSong>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, tune, title, tracks, loops, shift, speed, policy, min_note, max_note
+        FROM playlist_songs
+        WHERE playlist_id = ?1 ORDER BY position;",
+    )?;
+    let rows = stmt.query_map([playlist_id], |row| {
+        let path: String = row.get(0)?;
+        let tune: Option<i64> = row.get(1)?;
+        Ok(QueuedSong {
+            song: SongSource {
+                path: PathBuf::from(path),
+                tune: tune.map(|tune| tune as usize),
+                title: row.get(2)?,
+            },
+            profile: profile_from_row(row, 3)?,
+        })
+    })?;
+    rows.collect()
+}
+
+fn save_songs(conn: &Connection, playlist_id: i64, songs: &[QueuedSong]) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM playlist_songs WHERE playlist_id = ?1;",
+        [playlist_id],
+    )?;
+    for (position, queued) in songs.iter().enumerate() {
+        let profile = &queued.profile;
+        tx.execute(
+            "INSERT INTO playlist_songs (playlist_id, position, path, tune, title, tracks,
+                loop_midi, loops, shift, speed, policy, min_note, max_note)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13);",
+            params![
+                playlist_id,
+                position as i64,
+                queued.song.path.to_string_lossy(),
+                queued.song.tune.map(|tune| tune as i64),
+                queued.song.title,
+                profile.tracks.as_deref().map(join_tracks),
+                profile.loops == 0,
+                profile.loops,
+                profile.options.shift,
+                profile.speed,
+                profile.options.policy.name(),
+                profile.options.min_note,
+                profile.options.max_note,
+            ],
+        )?;
+    }
+    tx.commit()
+}
+
+fn create_playlist(conn: &Connection, name: &str) -> rusqlite::Result<i64> {
+    conn.execute("INSERT INTO playlists (name) VALUES (?1);", [name])?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn delete_playlist(conn: &Connection, playlist_id: i64) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM playlist_songs WHERE playlist_id = ?1;",
+        [playlist_id],
+    )?;
+    tx.execute("DELETE FROM playlists WHERE id = ?1;", [playlist_id])?;
+    tx.commit()
+}
+
+#[derive(Clone, Copy)]
+enum PlaylistAction {
+    Play,
+    Edit,
+    Rename,
+    Duplicate,
+    Delete,
+    Back,
+}
+
+impl PlaylistAction {
+    const ALL: [PlaylistAction; 6] = [
+        PlaylistAction::Play,
+        PlaylistAction::Edit,
+        PlaylistAction::Rename,
+        PlaylistAction::Duplicate,
+        PlaylistAction::Delete,
+        PlaylistAction::Back,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            PlaylistAction::Play => "Play",
+            PlaylistAction::Edit => "Edit songs",
+            PlaylistAction::Rename => "Rename",
+            PlaylistAction::Duplicate => "Duplicate",
+            PlaylistAction::Delete => "Delete",
+            PlaylistAction::Back => "Back",
+        }
+    }
+}
+
+/// The playlist menu, returns the queue to play if the user starts a playlist
+pub fn manage_playlists(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+) -> Result<Option<Queue>, Box<dyn Error>> {
+    loop {
+        let playlists = get_playlists(conn)?;
+        let mut items = vec!["[New playlist]".to_string(), "[Back]".to_string()];
+        items.extend(
+            playlists
+                .iter()
+                .map(|playlist| format!("{} ({} songs)", playlist.name, playlist.song_count)),
+        );
+
+        let selection = Select::with_theme(theme)
+            .with_prompt("Playlists")
+            .items(&items)
+            .default(0)
+            .interact()?;
+
+        let playlist = match selection {
+            0 => {
+                let Some(name) = ask_name(theme, conn, None, None)? else {
+                    continue;
+                };
+                let id = create_playlist(conn, &name)?;
+                let mut queue = Queue::default();
+                queue::edit_queue(theme, conn, picker, &mut queue, EditMode::Playlist)?;
+                save_songs(conn, id, &queue.songs)?;
+                info!("Created playlist {} with {} songs", name, queue.songs.len());
+                continue;
+            }
+            1 => return Ok(None),
+            _ => &playlists[selection - 2],
+        };
+
+        let action = Select::with_theme(theme)
+            .with_prompt(&playlist.name)
+            .items(&PlaylistAction::ALL.map(PlaylistAction::label))
+            .default(0)
+            .interact()?;
+
+        match PlaylistAction::ALL[action] {
+            PlaylistAction::Play => {
+                let mut songs = get_songs(conn, playlist.id)?;
+                if songs.is_empty() {
+                    warn!("{} has no songs", playlist.name);
+                    continue;
+                }
+                let shuffle = Confirm::with_theme(theme)
+                    .with_prompt("Shuffle?")
+                    .default(false)
+                    .interact()?;
+                let repeat = Confirm::with_theme(theme)
+                    .with_prompt("Repeat all?")
+                    .default(false)
+                    .interact()?;
+                if shuffle {
+                    fastrand::shuffle(&mut songs);
+                }
+                return Ok(Some(Queue {
+                    songs,
+                    position: 0,
+                    repeat,
+                }));
+            }
+            PlaylistAction::Edit => {
+                let mut queue = Queue {
+                    songs: get_songs(conn, playlist.id)?,
+                    ..Default::default()
+                };
+                queue::edit_queue(theme, conn, picker, &mut queue, EditMode::Playlist)?;
+                save_songs(conn, playlist.id, &queue.songs)?;
+            }
+            PlaylistAction::Rename => {
+                if let Some(name) = ask_name(theme, conn, Some(&playlist.name), Some(playlist.id))?
+                {
+                    conn.execute(
+                        "UPDATE playlists SET name = ?1 WHERE id = ?2;",
+                        params![name, playlist.id],
+                    )?;
+                }
+            }
+            PlaylistAction::Duplicate => {
+                let copy_name = format!("{} copy", playlist.name);
+                if let Some(name) = ask_name(theme, conn, Some(&copy_name), None)? {
+                    let id = create_playlist(conn, &name)?;
+                    save_songs(conn, id, &get_songs(conn, playlist.id)?)?;
+                }
+            }
+            PlaylistAction::Delete => {
+                let confirmed = Confirm::with_theme(theme)
+                    .with_prompt(format!("Delete {}?", playlist.name))
+                    .default(false)
+                    .interact()?;
+                if confirmed {
+                    delete_playlist(conn, playlist.id)?;
+                }
+            }
+            PlaylistAction::Back => {}
+        }
+    }
+}
+
+// Asks for a playlist name, None if it's empty or taken by another playlist
+// than `renaming`
+fn ask_name(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    default: Option<&str>,
+    renaming: Option<i64>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut input = Input::<String>::with_theme(theme).with_prompt("Playlist name");
+    if let Some(default) = default {
+        input = input.default(default.to_string());
+    }
+    let name = input.interact_text()?.trim().to_string();
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    let taken = get_playlists(conn)?
+        .iter()
+        .any(|playlist| playlist.name == name && Some(playlist.id) != renaming);
+    if taken {
+        warn!("A playlist called {} already exists", name);
+        return Ok(None);
+    }
+    Ok(Some(name))
+}
@@ -0,0 +1,166 @@
+use crate::arrangement::Arrangement;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::path::Path;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+// Let the last notes ring out before the file ends
+const TAIL_SECONDS: u64 = 2;
+// Voices are dropped after this long, a plucked string is inaudible by then
+const MAX_VOICE_SECONDS: u64 = 6;
+// Feedback gain of the string while held and after the note is released
+const SUSTAIN_DECAY: f32 = 0.996;
+const RELEASE_DECAY: f32 = 0.9;
+
+struct Pluck {
+    start: u64,
+    release: Option<u64>,
+    note: u8,
+    amplitude: f32,
+    // Guitar string the note is played on, a new pluck cuts off the previous one
+    string: Option<i32>,
+}
+
+// Karplus-Strong plucked string
+struct Voice {
+    buffer: Vec<f32>,
+    index: usize,
+    start: u64,
+    release: Option<u64>,
+    string: Option<i32>,
+}
+
+impl Voice {
+    fn new(pluck: &Pluck, seed: &mut u32) -> Self {
+        let freq = 440.0 * 2f32.powf((pluck.note as f32 - 69.0) / 12.0);
+        let len = (SAMPLE_RATE as f32 / freq).round().max(2.0) as usize;
+        let mut buffer: Vec<f32> = (0..len).map(|_| next_noise(seed)).collect();
+        // Remove the DC offset of the noise burst so the string settles at zero
+        let mean = buffer.iter().sum::<f32>() / len as f32;
+        for sample in &mut buffer {
+            *sample = (*sample - mean) * pluck.amplitude;
+        }
+
+        Voice {
+            buffer,
+            index: 0,
+            start: pluck.start,
+            release: pluck.release,
+            string: pluck.string,
+        }
+    }
+
+    fn next_sample(&mut self, now: u64) -> f32 {
+        let decay = match self.release {
+            Some(release) if now >= release => RELEASE_DECAY,
+            _ => SUSTAIN_DECAY,
+        };
+        let len = self.buffer.len();
+        let next_index = (self.index + 1) % len;
+        let current = self.buffer[self.index];
+        self.buffer[self.index] = (current + self.buffer[next_index]) * 0.5 * decay;
+        self.index = next_index;
+        current
+    }
+
+    fn is_finished(&self, now: u64) -> bool {
+        let max_end = self.start + MAX_VOICE_SECONDS * SAMPLE_RATE as u64;
+        let release_end = self.release.map(|r| r + SAMPLE_RATE as u64 / 2);
+        now >= release_end.unwrap_or(max_end).min(max_end)
+    }
+}
+
+// xorshift, deterministic so renders of the same song are identical
+fn next_noise(seed: &mut u32) -> f32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    (*seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+}
+
+fn micros_to_sample(micros: u64) -> u64 {
+    micros * SAMPLE_RATE as u64 / 1_000_000
+}
+
+fn render(mut plucks: Vec<Pluck>, length_micros: u64) -> Vec<f32> {
+    plucks.sort_by_key(|pluck| pluck.start);
+    let total_samples = micros_to_sample(length_micros) + TAIL_SECONDS * SAMPLE_RATE as u64;
+    let mut samples = Vec::with_capacity(total_samples as usize);
+    let mut voices: Vec<Voice> = Vec::new();
+    let mut next_pluck = 0;
+    let mut seed = 0x2545_f491;
+
+    for now in 0..total_samples {
+        while next_pluck < plucks.len() && plucks[next_pluck].start <= now {
+            let pluck = &plucks[next_pluck];
+            if pluck.string.is_some() {
+                voices.retain(|voice| voice.string != pluck.string);
+            }
+            voices.push(Voice::new(pluck, &mut seed));
+            next_pluck += 1;
+        }
+
+        samples.push(voices.iter_mut().map(|v| v.next_sample(now)).sum());
+        voices.retain(|voice| !voice.is_finished(now));
+    }
+
+    normalize(&mut samples);
+    samples
+}
+
+fn normalize(samples: &mut [f32]) {
+    let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if peak > 0.0 {
+        let gain = 0.9 / peak;
+        samples.iter_mut().for_each(|s| *s *= gain);
+    }
+}
+
+/// Synthesizes what the game guitar will play: shifted and clamped notes on
+/// their assigned strings, with dropped notes left out
+pub fn render_arrangement(arrangement: &Arrangement) -> Vec<f32> {
+    let plucks = arrangement
+        .notes
+        .iter()
+        .filter_map(|note| {
+            let position = note.position?;
+            Some(Pluck {
+                start: micros_to_sample(note.micros),
+                release: None,
+                note: note.note,
+                amplitude: 0.8,
+                string: Some(position.string),
+            })
+        })
+        .collect();
+    render(plucks, arrangement.length_micros)
+}
+
+/// Synthesizes every note of the selected tracks as written in the file
+pub fn render_original(arrangement: &Arrangement) -> Vec<f32> {
+    let plucks = arrangement
+        .notes
+        .iter()
+        .map(|note| Pluck {
+            start: micros_to_sample(note.micros),
+            release: Some(micros_to_sample(note.end_micros)),
+            note: note.key,
+            amplitude: note.velocity as f32 / 127.0,
+            string: None,
+        })
+        .collect();
+    render(plucks, arrangement.length_micros)
+}
+
+pub fn write_wav(path: &Path, samples: &[f32]) -> Result<(), hound::Error> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    for sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()
+}
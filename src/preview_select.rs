@@ -0,0 +1,162 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, HighlightSpacing, List, ListState, Paragraph, Wrap},
+};
+use std::{cmp::Reverse, io, time::Duration};
+
+use crate::screen::FullScreen;
+
+// Columns given to the preview, the list gets the rest
+const PREVIEW_WIDTH: u16 = 52;
+// Rows moved by page up and page down
+const PAGE: usize = 10;
+// How often `changed` is asked while waiting for a key
+const CHANGE_INTERVAL: Duration = Duration::from_millis(250);
+// How often a preview that's still loading is asked for again
+const LOADING_INTERVAL: Duration = Duration::from_millis(50);
+
+pub enum Selection {
+    Picked(usize),
+    // The items are out of date, shown again with the same highlight and filter
+    Changed { highlighted: usize, query: String },
+}
+
+/// Works like dialoguer's fuzzy select with a pane beside the list showing
+/// `preview` of the highlighted item, or "Loading..." until `preview` has it.
+/// Type to filter, arrows to move, escape clears the filter and enter picks.
+/// Gives up early when `changed` says the items need to be listed again.
+pub fn select(
+    prompt: &str,
+    items: &[String],
+    default: usize,
+    query: &str,
+    mut preview: impl FnMut(usize) -> Option<Vec<String>>,
+    mut changed: impl FnMut() -> bool,
+) -> io::Result<Selection> {
+    let mut screen = FullScreen::enter()?;
+    let matcher = SkimMatcherV2::default();
+    let mut query = query.to_string();
+    let mut selected = default;
+    let mut list_state = ListState::default();
+
+    'draw: loop {
+        // Matches best first, or every item in order without a query
+        let matches: Vec<usize> = if query.is_empty() {
+            (0..items.len()).collect()
+        } else {
+            let mut scored: Vec<(i64, usize)> = items
+                .iter()
+                .enumerate()
+                .filter_map(|(i, item)| Some((matcher.fuzzy_match(item, &query)?, i)))
+                .collect();
+            scored.sort_by_key(|&(score, i)| (Reverse(score), i));
+            scored.into_iter().map(|(_, i)| i).collect()
+        };
+        let position = matches.iter().position(|&i| i == selected).unwrap_or(0);
+        if let Some(&i) = matches.get(position) {
+            selected = i;
+        }
+        list_state.select((!matches.is_empty()).then_some(position));
+        let pane = match matches.get(position) {
+            Some(&i) => preview(i),
+            None => Some(Vec::new()),
+        };
+        let loading = pane.is_none();
+
+        screen.draw(|frame| {
+            let mut prompt_lines = prompt.lines();
+            let mut header = vec![Line::from(vec![
+                Span::styled("? ", Style::default().fg(Color::Yellow)),
+                Span::from(prompt_lines.next().unwrap_or_default()).bold(),
+                Span::from(" › ").dim(),
+                Span::from(query.as_str()),
+            ])];
+            header.extend(prompt_lines.map(|line| Line::from(line).dim()));
+
+            let [header_area, body] =
+                Layout::vertical([Constraint::Length(header.len() as u16), Constraint::Min(0)])
+                    .areas(frame.area());
+            let [list_area, preview_area] =
+                Layout::horizontal([Constraint::Min(0), Constraint::Length(PREVIEW_WIDTH)])
+                    .areas(body);
+
+            frame.render_widget(Paragraph::new(header), header_area);
+            let list = List::new(matches.iter().map(|&i| items[i].as_str()))
+                .highlight_symbol("> ")
+                .highlight_spacing(HighlightSpacing::Always)
+                .highlight_style(Style::default().fg(Color::Cyan).bold());
+            frame.render_stateful_widget(list, list_area, &mut list_state);
+            let pane: Vec<Line> = match &pane {
+                Some(pane) => pane.iter().map(|line| Line::from(line.as_str())).collect(),
+                None => vec![Line::from("Loading...").dim()],
+            };
+            frame.render_widget(
+                Paragraph::new(pane)
+                    .wrap(Wrap { trim: false })
+                    .block(Block::bordered().title(" Preview ")),
+                preview_area,
+            );
+        })?;
+
+        let interval = if loading {
+            LOADING_INTERVAL
+        } else {
+            CHANGE_INTERVAL
+        };
+        while !event::poll(interval)? {
+            if changed() {
+                return Ok(Selection::Changed {
+                    highlighted: selected,
+                    query,
+                });
+            }
+            if loading && preview(selected).is_some() {
+                continue 'draw;
+            }
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let last = matches.len().saturating_sub(1);
+        let moved = match key.code {
+            KeyCode::Enter if !matches.is_empty() => return Ok(Selection::Picked(selected)),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "selection cancelled",
+                ));
+            }
+            KeyCode::Up if position == 0 => Some(last),
+            KeyCode::Up => Some(position - 1),
+            KeyCode::Down if position >= last => Some(0),
+            KeyCode::Down => Some(position + 1),
+            KeyCode::PageUp => Some(position.saturating_sub(PAGE)),
+            KeyCode::PageDown => Some((position + PAGE).min(last)),
+            KeyCode::Home => Some(0),
+            KeyCode::End => Some(last),
+            KeyCode::Char(c) => {
+                query.push(c);
+                None
+            }
+            KeyCode::Backspace => {
+                query.pop();
+                None
+            }
+            KeyCode::Esc => {
+                query.clear();
+                None
+            }
+            _ => None,
+        };
+        if let Some(&i) = moved.and_then(|position| matches.get(position)) {
+            selected = i;
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::{
+    arrangement::{note_name, ArrangeOptions, RangePolicy, MAX_NOTE, MIN_NOTE},
+    library, pause_and_exit,
+    picker::Picker,
+    songs::SongSource,
+    webfishing_player::PlayerSettings,
+};
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+use log::{error, info, warn};
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Every playback setting remembered for a song
+#[derive(Clone, Debug, PartialEq)]
+pub struct SongProfile {
+    // None plays every track
+    pub tracks: Option<Vec<usize>>,
+    // How many times to play the song in a row, 0 loops until it's stopped
+    pub loops: u32,
+    pub speed: f64,
+    pub options: ArrangeOptions,
+}
+
+impl Default for SongProfile {
+    fn default() -> Self {
+        SongProfile {
+            tracks: None,
+            loops: 1,
+            speed: 1.0,
+            options: ArrangeOptions::default(),
+        }
+    }
+}
+
+impl SongProfile {
+    pub fn apply(&self, settings: &mut PlayerSettings) {
+        settings.tracks = Some(
+            self.tracks
+                .clone()
+                .unwrap_or_else(|| (0..settings.smf.tracks.len()).collect()),
+        );
+        settings.loop_midi = self.loops == 0;
+        settings.options = self.options;
+        settings.speed = self.speed;
+    }
+
+    pub fn summary(&self) -> String {
+        let tracks = match &self.tracks {
+            Some(tracks) => format!("tracks {}", join_tracks(tracks)),
+            None => "all tracks".to_string(),
+        };
+        let loops = match self.loops {
+            0 => "loop".to_string(),
+            1 => "play once".to_string(),
+            loops => format!("play {} times", loops),
+        };
+        let shift = match self.options.shift {
+            Some(shift) => format!("shift {:+}", shift),
+            None => "auto shift".to_string(),
+        };
+        format!(
+            "{} | {} | {} | {:.2}x speed | out of range: {} | range {}-{}",
+            tracks,
+            loops,
+            shift,
+            self.speed,
+            self.options.policy.name(),
+            note_name(self.options.min_note),
+            note_name(self.options.max_note)
+        )
+    }
+}
+
+pub fn join_tracks(tracks: &[usize]) -> String {
+    tracks
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+pub fn parse_tracks(tracks: &str) -> Vec<usize> {
+    tracks
+        .split(',')
+        .filter_map(|id| id.parse::<usize>().ok())
+        .collect()
+}
+
+/// Reads a profile from a row starting with tracks, loops, shift, speed,
+/// policy, min_note and max_note at `first`
+pub fn profile_from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<SongProfile> {
+    let tracks: Option<String> = row.get(first)?;
+    let policy: String = row.get(first + 4)?;
+    Ok(SongProfile {
+        tracks: tracks.as_deref().map(parse_tracks),
+        loops: row.get(first + 1)?,
+        options: ArrangeOptions {
+            shift: row.get(first + 2)?,
+            policy: RangePolicy::from_name(&policy),
+            min_note: row.get(first + 5)?,
+            max_note: row.get(first + 6)?,
+        },
+        speed: row.get(first + 3)?,
+    })
+}
+
+/// Finds the settings saved for a song by its content so they survive moves
+/// and renames, falling back to its path. Saved tracks are dropped with a
+/// warning when the file at the path has changed since.
+pub fn get_profile(
+    conn: &Connection,
+    song: &SongSource,
+    content_hash: &str,
+) -> rusqlite::Result<Option<SongProfile>> {
+    let key = song.key();
+    let by_content = conn
+        .query_row(
+            "SELECT tracks, loops, shift, speed, policy, min_note, max_note
+            FROM song_profiles WHERE content_hash = ?1
+            ORDER BY song_key = ?2 DESC LIMIT 1;",
+            params![content_hash, key],
+            |row| profile_from_row(row, 0),
+        )
+        .optional()?;
+    if by_content.is_some() {
+        return Ok(by_content);
+    }
+
+    let by_path = conn
+        .query_row(
+            "SELECT tracks, loops, shift, speed, policy, min_note, max_note, content_hash
+            FROM song_profiles WHERE song_key = ?1;",
+            [&key],
+            |row| {
+                let saved_hash: Option<String> = row.get(7)?;
+                Ok((profile_from_row(row, 0)?, saved_hash))
+            },
+        )
+        .optional()?;
+    Ok(by_path.map(|(mut profile, saved_hash)| {
+        // Rows for files that were missing when hashes were added have none to
+        // compare, out of range tracks are still warned about when picking
+        if saved_hash.is_some() {
+            warn!(
+                "{} changed since its settings were saved, pick its tracks again",
+                song.display_name()
+            );
+            profile.tracks = None;
+        }
+        profile
+    }))
+}
+
+pub fn save_profile(
+    conn: &Connection,
+    song: &SongSource,
+    content_hash: &str,
+    profile: &SongProfile,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO song_profiles (song_key, content_hash, tracks, loops, shift, speed, policy,
+            min_note, max_note)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        ON CONFLICT(song_key) DO UPDATE SET content_hash = excluded.content_hash,
+            tracks = excluded.tracks, loops = excluded.loops, shift = excluded.shift,
+            speed = excluded.speed, policy = excluded.policy, min_note = excluded.min_note,
+            max_note = excluded.max_note;",
+        params![
+            song.key(),
+            content_hash,
+            profile.tracks.as_deref().map(join_tracks),
+            profile.loops,
+            profile.options.shift,
+            profile.speed,
+            profile.options.policy.name(),
+            profile.options.min_note,
+            profile.options.max_note,
+        ],
+    )?;
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum ProfileAction {
+    Use,
+    Edit,
+    Reset,
+    Copy,
+}
+
+impl ProfileAction {
+    const ALL: [ProfileAction; 4] = [
+        ProfileAction::Use,
+        ProfileAction::Edit,
+        ProfileAction::Reset,
+        ProfileAction::Copy,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            ProfileAction::Use => "Use these settings",
+            ProfileAction::Edit => "Edit settings",
+            ProfileAction::Reset => "Reset to defaults",
+            ProfileAction::Copy => "Copy settings to another song",
+        }
+    }
+}
+
+/// Shows the song's settings and lets the user change, reset or copy them,
+/// saves and returns the settings to play with
+pub fn review_profile(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+    song: &SongSource,
+    content_hash: &str,
+    mut profile: SongProfile,
+) -> Result<SongProfile, dialoguer::Error> {
+    loop {
+        let action = Select::with_theme(theme)
+            .with_prompt(format!("Settings: {}", profile.summary()))
+            .items(&ProfileAction::ALL.map(ProfileAction::label))
+            .default(0)
+            .interact()?;
+
+        match ProfileAction::ALL[action] {
+            ProfileAction::Use => {
+                save_profile(conn, song, content_hash, &profile).unwrap_or_else(|e| db_error(e));
+                return Ok(profile);
+            }
+            ProfileAction::Edit => edit_profile(theme, &mut profile)?,
+            // The tracks were only just picked so they stay
+            ProfileAction::Reset => {
+                profile = SongProfile {
+                    tracks: profile.tracks.take(),
+                    ..Default::default()
+                };
+            }
+            ProfileAction::Copy => {
+                let target = picker.select(theme, conn)?;
+                let target_hash = match target.load() {
+                    Ok(data) => library::content_hash(&data),
+                    Err(e) => {
+                        error!("Failed to read {}: {}", target.display_name(), e);
+                        continue;
+                    }
+                };
+                // Track numbers don't carry over between songs
+                let tracks = get_profile(conn, &target, &target_hash)
+                    .unwrap_or_else(|e| db_error(e))
+                    .and_then(|saved| saved.tracks);
+                save_profile(
+                    conn,
+                    &target,
+                    &target_hash,
+                    &SongProfile {
+                        tracks,
+                        ..profile.clone()
+                    },
+                )
+                .unwrap_or_else(|e| db_error(e));
+                info!(
+                    "Copied the settings of {} to {}",
+                    song.display_name(),
+                    target.display_name()
+                );
+            }
+        }
+    }
+}
+
+fn db_error(err: rusqlite::Error) -> ! {
+    error!("Failed to save song settings: {}", err);
+    pause_and_exit(-1);
+}
+
+fn edit_profile(theme: &ColorfulTheme, profile: &mut SongProfile) -> Result<(), dialoguer::Error> {
+    profile.loops = Input::with_theme(theme)
+        .with_prompt("How many times to play it? (0 loops until stopped)")
+        .default(profile.loops)
+        .interact_text()?;
+
+    let shift: String = Input::with_theme(theme)
+        .with_prompt("Shift in semitones, or auto")
+        .default(
+            profile
+                .options
+                .shift
+                .map_or("auto".to_string(), |s| s.to_string()),
+        )
+        .validate_with(|input: &String| -> Result<(), &str> {
+            match input.trim() {
+                "auto" => Ok(()),
+                shift => shift
+                    .parse::<i8>()
+                    .map(|_| ())
+                    .map_err(|_| "enter a number of semitones or auto"),
+            }
+        })
+        .interact_text()?;
+    profile.options.shift = shift.trim().parse().ok();
+
+    profile.speed = Input::with_theme(theme)
+        .with_prompt("Speed (1.0 is the original tempo)")
+        .default(profile.speed)
+        .validate_with(|speed: &f64| -> Result<(), &str> {
+            if (0.1..=4.0).contains(speed) {
+                Ok(())
+            } else {
+                Err("speed must be between 0.1 and 4.0")
+            }
+        })
+        .interact_text()?;
+
+    let policy = Select::with_theme(theme)
+        .with_prompt("What should happen to notes that are out of range?")
+        .items(&RangePolicy::ALL.map(RangePolicy::label))
+        .default(
+            RangePolicy::ALL
+                .iter()
+                .position(|&policy| policy == profile.options.policy)
+                .unwrap_or(0),
+        )
+        .interact()?;
+    profile.options.policy = RangePolicy::ALL[policy];
+
+    profile.options.min_note = ask_note(theme, "Lowest note", profile.options.min_note, MIN_NOTE)?;
+    profile.options.max_note = ask_note(
+        theme,
+        "Highest note",
+        profile.options.max_note,
+        profile.options.min_note,
+    )?;
+    if profile.options.max_note - profile.options.min_note < 12 {
+        warn!("The range is less than an octave, many notes will be clamped");
+    }
+    Ok(())
+}
+
+// Asks for a MIDI note number between `lowest` and MAX_NOTE
+fn ask_note(
+    theme: &ColorfulTheme,
+    prompt: &str,
+    default: u8,
+    lowest: u8,
+) -> Result<u8, dialoguer::Error> {
+    Input::with_theme(theme)
+        .with_prompt(format!(
+            "{} ({} = {} to {} = {})",
+            prompt,
+            lowest,
+            note_name(lowest),
+            MAX_NOTE,
+            note_name(MAX_NOTE)
+        ))
+        .default(default.clamp(lowest, MAX_NOTE))
+        .validate_with(move |note: &u8| -> Result<(), String> {
+            if (lowest..=MAX_NOTE).contains(note) {
+                Ok(())
+            } else {
+                Err(format!("pick a note from {} to {}", lowest, MAX_NOTE))
+            }
+        })
+        .interact_text()
+}
@@ -0,0 +1,410 @@
+use crate::{
+    library,
+    picker::{format_duration, Picker},
+    profiles::{self, SongProfile},
+    remote::Remote,
+    select_song,
+    songs::SongSource,
+    suggestion::{self, SuggestMode},
+    webfishing_player::PlayerSettings,
+};
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+use log::{error, info};
+use midly::Smf;
+use rusqlite::Connection;
+
+/// A song waiting to be played with the settings it was queued with
+#[derive(Clone, Debug)]
+pub struct QueuedSong {
+    pub song: SongSource,
+    pub profile: SongProfile,
+}
+
+impl QueuedSong {
+    /// Queues a song with its saved settings, or the suggested tracks if it
+    /// has none, for songs added without going through the menus
+    pub fn with_saved_profile(conn: &Connection, song: SongSource) -> Option<QueuedSong> {
+        let midi_data = match song.load() {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to read {}: {}", song.display_name(), e);
+                return None;
+            }
+        };
+
+        let content_hash = library::content_hash(&midi_data);
+        let mut profile = match profiles::get_profile(conn, &song, &content_hash) {
+            Ok(profile) => profile.unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to get song settings from database: {}", e);
+                SongProfile::default()
+            }
+        };
+        if profile.tracks.is_none() {
+            if let Ok(smf) = Smf::parse(&midi_data) {
+                let suggested =
+                    suggestion::suggest_tracks(&suggestion::score_tracks(&smf), SuggestMode::Band);
+                profile.tracks = (!suggested.is_empty()).then_some(suggested);
+            }
+        }
+        Some(QueuedSong { song, profile })
+    }
+
+    /// Loads and parses the song, logs why if it can't be played
+    pub fn settings<'a>(&self) -> Option<PlayerSettings<'a>> {
+        let midi_data = match self.song.load() {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to read {}: {}", self.song.display_name(), e);
+                return None;
+            }
+        };
+
+        let mut settings = match PlayerSettings::new(midi_data, false) {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!("Failed to parse {}: {}", self.song.display_name(), e);
+                return None;
+            }
+        };
+        self.profile.apply(&mut settings);
+        Some(settings)
+    }
+
+    fn label(&self, conn: &Connection) -> String {
+        let mut label = self.song.display_name();
+        if let Ok(Some(entry)) = library::get_entry(conn, &self.song.key()) {
+            label.push_str(&format!(" ({})", format_duration(entry.duration_secs)));
+        }
+        match self.profile.loops {
+            0 => label.push_str(" [loop]"),
+            1 => {}
+            loops => label.push_str(&format!(" [x{}]", loops)),
+        }
+        label
+    }
+}
+
+/// The songs to play in order, where playback is up to and whether to start
+/// over at the end
+#[derive(Default)]
+pub struct Queue {
+    pub songs: Vec<QueuedSong>,
+    pub position: usize,
+    pub repeat: bool,
+}
+
+impl Queue {
+    /// Adds the songs remote clients asked for
+    pub fn add_enqueued(&mut self, conn: &Connection, remote: &Remote) {
+        let position = self.position.min(self.songs.len());
+        for song in remote.take_play_next().into_iter().rev() {
+            info!("Playing {} next", song.display_name());
+            if let Some(queued) = QueuedSong::with_saved_profile(conn, song) {
+                self.songs.insert(position, queued);
+            }
+        }
+        for song in remote.take_enqueued() {
+            info!("Queued {} remotely", song.display_name());
+            self.songs
+                .extend(QueuedSong::with_saved_profile(conn, song));
+        }
+    }
+
+    /// Moves past the next song and returns its index, the caller checks
+    /// there is one
+    pub fn advance(&mut self) -> usize {
+        self.position += 1;
+        self.position - 1
+    }
+
+    /// Goes back to the song before the one that was just played, or plays
+    /// the first song again
+    pub fn previous(&mut self) {
+        self.position = self.position.saturating_sub(2);
+    }
+
+    /// Swaps two songs, the next song marker stays on the same song
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.songs.swap(a, b);
+        if self.position == a {
+            self.position = b;
+        } else if self.position == b {
+            self.position = a;
+        }
+    }
+
+    /// Adds a song before `index`, the next song stays the same
+    pub fn insert(&mut self, index: usize, queued: QueuedSong) {
+        self.songs.insert(index, queued);
+        if self.position > index {
+            self.position += 1;
+        }
+    }
+
+    /// Takes out a song, the next song stays the same unless it's the one removed
+    pub fn remove(&mut self, index: usize) -> QueuedSong {
+        if self.position > index {
+            self.position -= 1;
+        }
+        self.songs.remove(index)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.songs
+            .iter()
+            .map(|queued| queued.song.display_name())
+            .collect()
+    }
+}
+
+/// What the editor is being used for, playlists don't play or repeat
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    Playlist,
+    Queue,
+}
+
+enum Item {
+    Done,
+    Add,
+    Repeat,
+    Stop,
+    Song(usize),
+}
+
+#[derive(Clone, Copy)]
+enum SongAction {
+    PlayFromHere,
+    MoveUp,
+    MoveDown,
+    Loops,
+    Insert,
+    Remove,
+    Back,
+}
+
+impl SongAction {
+    const ALL: [SongAction; 7] = [
+        SongAction::PlayFromHere,
+        SongAction::MoveUp,
+        SongAction::MoveDown,
+        SongAction::Loops,
+        SongAction::Insert,
+        SongAction::Remove,
+        SongAction::Back,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            SongAction::PlayFromHere => "Play from here",
+            SongAction::MoveUp => "Move up",
+            SongAction::MoveDown => "Move down",
+            SongAction::Loops => "Set loop count",
+            SongAction::Insert => "Insert a song before",
+            SongAction::Remove => "Remove",
+            SongAction::Back => "Back",
+        }
+    }
+}
+
+/// Lets the user add, reorder and remove songs, returns false if they chose to
+/// stop instead of playing
+pub fn edit_queue(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+    queue: &mut Queue,
+    mode: EditMode,
+) -> Result<bool, dialoguer::Error> {
+    let mut selection = 0;
+    loop {
+        let mut items = vec![Item::Done, Item::Add];
+        if mode == EditMode::Queue {
+            items.extend([Item::Repeat, Item::Stop]);
+        }
+        items.extend((0..queue.songs.len()).map(Item::Song));
+
+        let labels: Vec<String> = items
+            .iter()
+            .map(|item| match item {
+                Item::Done if mode == EditMode::Queue => "[Play]".to_string(),
+                Item::Done => "[Done]".to_string(),
+                Item::Add => "[Add song]".to_string(),
+                Item::Repeat => format!(
+                    "[Repeat whole queue: {}]",
+                    if queue.repeat { "on" } else { "off" }
+                ),
+                Item::Stop => "[Stop]".to_string(),
+                Item::Song(i) => {
+                    let marker = if mode == EditMode::Queue && *i == queue.position {
+                        ">"
+                    } else {
+                        " "
+                    };
+                    format!("{} {}. {}", marker, i + 1, queue.songs[*i].label(conn))
+                }
+            })
+            .collect();
+
+        selection = Select::with_theme(theme)
+            .with_prompt(match mode {
+                EditMode::Playlist => "Songs",
+                EditMode::Queue => "Queue (> marks the next song)",
+            })
+            .items(&labels)
+            .default(selection.min(labels.len() - 1))
+            .interact()?;
+
+        match items[selection] {
+            Item::Done => return Ok(true),
+            Item::Add => {
+                if let Some(queued) = ask_song(theme, conn, picker)? {
+                    queue.songs.push(queued);
+                }
+            }
+            Item::Repeat => queue.repeat = !queue.repeat,
+            Item::Stop => return Ok(false),
+            Item::Song(index) => {
+                let actions: Vec<SongAction> = SongAction::ALL
+                    .into_iter()
+                    .filter(|action| {
+                        mode == EditMode::Queue || !matches!(action, SongAction::PlayFromHere)
+                    })
+                    .collect();
+                let action = Select::with_theme(theme)
+                    .with_prompt(queue.songs[index].label(conn))
+                    .items(
+                        &actions
+                            .iter()
+                            .map(|action| action.label())
+                            .collect::<Vec<_>>(),
+                    )
+                    .default(0)
+                    .interact()?;
+
+                match actions[action] {
+                    SongAction::PlayFromHere => {
+                        queue.position = index;
+                        return Ok(true);
+                    }
+                    SongAction::MoveUp if index > 0 => {
+                        queue.swap(index, index - 1);
+                        selection -= 1;
+                    }
+                    SongAction::MoveDown if index + 1 < queue.songs.len() => {
+                        queue.swap(index, index + 1);
+                        selection += 1;
+                    }
+                    SongAction::Loops => {
+                        queue.songs[index].profile.loops = Input::with_theme(theme)
+                            .with_prompt("How many times to play it? (0 loops until stopped)")
+                            .default(queue.songs[index].profile.loops)
+                            .interact_text()?;
+                    }
+                    SongAction::Insert => {
+                        if let Some(queued) = ask_song(theme, conn, picker)? {
+                            queue.insert(index, queued);
+                        }
+                    }
+                    SongAction::Remove => {
+                        queue.remove(index);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn ask_song(
+    theme: &ColorfulTheme,
+    conn: &Connection,
+    picker: &mut Picker,
+) -> Result<Option<QueuedSong>, dialoguer::Error> {
+    Ok(select_song(theme, conn, picker)?.map(|(queued, _)| queued))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn queue(names: &[&str], position: usize) -> Queue {
+        Queue {
+            songs: names
+                .iter()
+                .map(|name| QueuedSong {
+                    song: SongSource::file(PathBuf::from(format!("{}.mid", name))),
+                    profile: SongProfile::default(),
+                })
+                .collect(),
+            position,
+            repeat: false,
+        }
+    }
+
+    fn next(queue: &Queue) -> String {
+        queue.songs[queue.position].song.file_stem()
+    }
+
+    #[test]
+    fn advancing_returns_the_song_to_play() {
+        let mut queue = queue(&["a", "b", "c"], 0);
+        assert_eq!(queue.advance(), 0);
+        assert_eq!(queue.advance(), 1);
+        assert_eq!(queue.position, 2);
+    }
+
+    #[test]
+    fn previous_goes_back_before_the_song_just_played() {
+        let mut queue = queue(&["a", "b", "c"], 0);
+        queue.advance();
+        queue.advance();
+        queue.previous();
+        assert_eq!(next(&queue), "a");
+        // The first song plays again
+        queue.advance();
+        queue.previous();
+        assert_eq!(next(&queue), "a");
+    }
+
+    #[test]
+    fn swapping_keeps_the_next_song() {
+        let mut queue = queue(&["a", "b", "c"], 1);
+        queue.swap(1, 0);
+        assert_eq!(queue.names(), ["b.mid", "a.mid", "c.mid"]);
+        assert_eq!(next(&queue), "b");
+        queue.swap(0, 1);
+        assert_eq!(next(&queue), "b");
+        queue.swap(1, 2);
+        assert_eq!(next(&queue), "b");
+        assert_eq!(queue.position, 2);
+    }
+
+    #[test]
+    fn inserting_keeps_the_next_song() {
+        let mut queue = queue(&["a", "b"], 1);
+        queue.insert(0, queue.songs[0].clone());
+        assert_eq!(next(&queue), "b");
+        // A song inserted at the marker is played next
+        queue.insert(2, queue.songs[0].clone());
+        assert_eq!(queue.position, 2);
+        assert_eq!(next(&queue), "a");
+        queue.insert(4, queue.songs[0].clone());
+        assert_eq!(queue.position, 2);
+    }
+
+    #[test]
+    fn removing_keeps_the_next_song() {
+        let mut queue = queue(&["a", "b", "c"], 1);
+        queue.remove(0);
+        assert_eq!(next(&queue), "b");
+        assert_eq!(queue.remove(1).song.file_stem(), "c");
+        assert_eq!(next(&queue), "b");
+        // Removing the next song moves the marker on to the one after it
+        queue.remove(0);
+        assert_eq!(queue.position, 0);
+        assert!(queue.songs.is_empty());
+    }
+}
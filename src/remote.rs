@@ -0,0 +1,173 @@
+use crate::songs::SongSource;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+};
+
+/// Commands from outside the terminal, the player picks them up between ticks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Pause,
+    Resume,
+    Skip,
+    // Stops the whole queue, not just the current song
+    Stop,
+    // Seconds into the song at its original tempo
+    Seek(f64),
+    Speed(f64),
+}
+
+/// What the player is doing, as shown to remote clients
+#[derive(Clone, Debug, Serialize)]
+pub struct PlayerStatus {
+    pub song: Option<String>,
+    pub position_secs: f64,
+    pub length_secs: f64,
+    pub paused: bool,
+    pub speed: f64,
+    pub queue: Vec<String>,
+    // Index in the queue of the song playing
+    pub queue_position: Option<usize>,
+}
+
+impl Default for PlayerStatus {
+    fn default() -> Self {
+        PlayerStatus {
+            song: None,
+            position_secs: 0.0,
+            length_secs: 0.0,
+            paused: false,
+            speed: 1.0,
+            queue: Vec::new(),
+            queue_position: None,
+        }
+    }
+}
+
+/// Sent to every client of the event feed
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    SongStarted { song: String },
+    SongEnded { song: String, completed: bool },
+    Paused,
+    Resumed,
+    Seeked { position_secs: f64 },
+    SpeedChanged { speed: f64 },
+    QueueChanged { queue: Vec<String> },
+    QueueEnded,
+}
+
+/// Connects the player to the remote control servers. The player owns
+/// playback, so servers only leave commands here and read the status back.
+#[derive(Default)]
+pub struct Remote {
+    commands: Mutex<VecDeque<Command>>,
+    // Songs waiting to be added to the queue
+    enqueued: Mutex<Vec<SongSource>>,
+    // Songs to play right after the current one
+    play_next: Mutex<Vec<SongSource>>,
+    status: Mutex<PlayerStatus>,
+    listeners: Mutex<Vec<Sender<String>>>,
+}
+
+impl Remote {
+    pub fn send(&self, command: Command) {
+        self.commands.lock().unwrap().push_back(command);
+    }
+
+    pub fn next_command(&self) -> Option<Command> {
+        self.commands.lock().unwrap().pop_front()
+    }
+
+    pub fn enqueue(&self, song: SongSource) {
+        let queue = self.update_status(|status| {
+            status.queue.push(song.display_name());
+            status.queue.clone()
+        });
+        self.enqueued.lock().unwrap().push(song);
+        self.emit(Event::QueueChanged { queue });
+    }
+
+    /// Sends a command for the song playing, false when nothing is. Stop
+    /// also empties the queue so it always goes through.
+    pub fn send_playing(&self, command: Command) -> bool {
+        let status = self.status.lock().unwrap();
+        if status.song.is_none() && command != Command::Stop {
+            return false;
+        }
+        self.send(command);
+        true
+    }
+
+    /// Plays the song now, skipping the current one if something is playing
+    pub fn play_now(&self, song: SongSource) {
+        // Holding the status keeps the next song from starting in between, so
+        // the skip can't land on the song being added
+        let status = self.status.lock().unwrap();
+        self.play_next.lock().unwrap().push(song);
+        if status.song.is_some() {
+            self.send(Command::Skip);
+        }
+    }
+
+    pub fn take_enqueued(&self) -> Vec<SongSource> {
+        std::mem::take(&mut *self.enqueued.lock().unwrap())
+    }
+
+    pub fn take_play_next(&self) -> Vec<SongSource> {
+        std::mem::take(&mut *self.play_next.lock().unwrap())
+    }
+
+    /// Drops commands sent while nothing was playing
+    pub fn clear_commands(&self) {
+        self.commands.lock().unwrap().clear();
+    }
+
+    /// Marks `song` as playing. Commands left from the song before, other than
+    /// a stop, are dropped at the same time so they can't reach this one.
+    pub fn start_song(&self, song: String, update: impl FnOnce(&mut PlayerStatus)) {
+        let mut status = self.status.lock().unwrap();
+        // A stop is for the whole queue, so it still counts
+        self.commands
+            .lock()
+            .unwrap()
+            .retain(|&command| command == Command::Stop);
+        status.song = Some(song);
+        update(&mut status);
+    }
+
+    /// Marks that nothing is playing until the next song starts
+    pub fn end_song(&self) {
+        self.status.lock().unwrap().song = None;
+    }
+
+    pub fn status(&self) -> PlayerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn update_status<T>(&self, update: impl FnOnce(&mut PlayerStatus) -> T) -> T {
+        update(&mut self.status.lock().unwrap())
+    }
+
+    /// A feed of events as JSON, ends when the receiver is dropped
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = mpsc::channel();
+        self.listeners.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn emit(&self, event: Event) {
+        let Ok(json) = serde_json::to_string(&event) else {
+            return;
+        };
+        self.listeners
+            .lock()
+            .unwrap()
+            .retain(|listener| listener.send(json.clone()).is_ok());
+    }
+}
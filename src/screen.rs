@@ -0,0 +1,490 @@
+use log::{Log, Metadata, Record};
+use midly::{MidiMessage, Smf, TrackEventKind};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor::Show,
+        event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+        execute,
+        terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Gauge, Paragraph},
+    Frame, Terminal,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, IsTerminal, Stdout},
+    iter, panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, Once,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    arrangement::{note_name, ArrangeOptions, GuitarPosition, TempoMap, FRET_COUNT, STRING_COUNT},
+    picker::format_duration,
+    remote::PlayerStatus,
+    tablature::time_signatures,
+};
+
+// The screen is redrawn at most this often, drawing between every tick
+// would slow the player down
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+// How long a strummed string shows as sounding
+const RING_TIME: Duration = Duration::from_millis(300);
+const UPCOMING_NOTES: usize = 8;
+// Log lines kept for the log pane
+const LOG_LINES: usize = 100;
+// By string from the low E, the fretboard is drawn high E first like tab
+const STRING_LABELS: [&str; STRING_COUNT] = ["E", "A", "D", "G", "B", "e"];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// Log lines written while the screen is open, None when it's closed
+static CAPTURED: Mutex<Option<VecDeque<String>>> = Mutex::new(None);
+
+/// Whether songs play on the full-screen view instead of the plain log
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed) && io::stdout().is_terminal()
+}
+
+/// Sends log lines to the log pane while the screen is open and to the
+/// wrapped logger the rest of the time
+pub struct ScreenLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> ScreenLogger<L> {
+    pub fn new(inner: L) -> Self {
+        ScreenLogger { inner }
+    }
+}
+
+impl<L: Log> Log for ScreenLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        if let Some(lines) = CAPTURED.lock().unwrap().as_mut() {
+            if lines.len() == LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(format!("{:<5} {}", record.level(), record.args()));
+            return;
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Where the player is up to, passed in on every redraw
+pub struct Progress {
+    pub tick: u64,
+    pub paused: bool,
+    pub speed: f64,
+    pub shift: i8,
+    pub played: usize,
+    pub dropped: usize,
+    pub clamped: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct StringState {
+    fret: i32,
+    strummed: Option<Instant>,
+}
+
+struct SongNote {
+    tick: u64,
+    key: u8,
+    track: usize,
+}
+
+/// The terminal switched to its alternate screen with log lines held back
+/// for drawing, it goes back to normal when dropped
+pub struct FullScreen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl FullScreen {
+    pub fn enter() -> io::Result<Self> {
+        // A panic message would be lost on the alternate screen
+        static RESTORE_ON_PANIC: Once = Once::new();
+        RESTORE_ON_PANIC.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                restore_terminal();
+                previous(info);
+            }));
+        });
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        terminal.hide_cursor()?;
+        *CAPTURED.lock().unwrap() = Some(VecDeque::new());
+        Ok(FullScreen { terminal })
+    }
+
+    pub fn draw(&mut self, render: impl FnOnce(&mut Frame)) -> io::Result<()> {
+        self.terminal.draw(render)?;
+        Ok(())
+    }
+
+    /// The log lines written since the screen was entered, oldest first
+    pub fn log(&self) -> Vec<String> {
+        CAPTURED.lock().unwrap().iter().flatten().cloned().collect()
+    }
+
+    /// Whether Ctrl+C was pressed since the last call. Raw mode keeps it from
+    /// stopping the program, so it's read like any other key. Other keys are
+    /// thrown away so they don't turn up in the next prompt.
+    pub fn interrupted(&self) -> bool {
+        let mut interrupted = false;
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = event::read() {
+                interrupted |= key.kind == KeyEventKind::Press
+                    && key.code == KeyCode::Char('c')
+                    && key.modifiers.contains(KeyModifiers::CONTROL);
+            }
+        }
+        interrupted
+    }
+}
+
+impl Drop for FullScreen {
+    fn drop(&mut self) {
+        *CAPTURED.lock().unwrap() = None;
+        restore_terminal();
+    }
+}
+
+fn restore_terminal() {
+    let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+/// The full-screen view of the song playing
+pub struct Screen {
+    full_screen: FullScreen,
+    last_draw: Option<Instant>,
+    view: View,
+}
+
+// Everything drawn besides the progress, kept apart from the terminal so it
+// can be read while drawing
+struct View {
+    title: String,
+    // (index, queue length)
+    queue: Option<(usize, usize)>,
+    ticks_per_beat: u64,
+    tempo_map: TempoMap,
+    signatures: BTreeMap<u64, (u64, u64)>,
+    length_micros: u64,
+    // Every note of the tracks played, in order
+    notes: Vec<SongNote>,
+    options: ArrangeOptions,
+    // One fretboard per game window
+    guitars: Vec<[StringState; STRING_COUNT]>,
+}
+
+impl Screen {
+    pub fn open(
+        status: &PlayerStatus,
+        smf: &Smf,
+        tracks: &[usize],
+        options: ArrangeOptions,
+        ticks_per_beat: u64,
+        guitars: usize,
+    ) -> io::Result<Self> {
+        let mut notes: Vec<SongNote> = Vec::new();
+        for &track in tracks {
+            let mut tick = 0;
+            for event in smf.tracks.get(track).into_iter().flatten() {
+                tick += event.delta.as_int() as u64;
+                if let TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { key, vel },
+                    ..
+                } = event.kind
+                {
+                    if vel > 0 {
+                        notes.push(SongNote {
+                            tick,
+                            key: key.as_int(),
+                            track,
+                        });
+                    }
+                }
+            }
+        }
+        notes.sort_by_key(|note| note.tick);
+        let tempo_map = TempoMap::new(smf);
+        let last_tick = smf
+            .tracks
+            .iter()
+            .map(|track| track.iter().map(|event| event.delta.as_int() as u64).sum())
+            .max()
+            .unwrap_or(0);
+        let view = View {
+            title: status
+                .song
+                .clone()
+                .unwrap_or_else(|| "Unknown song".to_string()),
+            queue: status
+                .queue_position
+                .map(|position| (position, status.queue.len())),
+            ticks_per_beat: ticks_per_beat.max(1),
+            length_micros: tempo_map.micros_at(last_tick),
+            tempo_map,
+            signatures: time_signatures(smf),
+            notes,
+            options,
+            guitars: vec![[StringState::default(); STRING_COUNT]; guitars],
+        };
+
+        Ok(Screen {
+            full_screen: FullScreen::enter()?,
+            last_draw: None,
+            view,
+        })
+    }
+
+    /// Whether Ctrl+C was pressed since the last call
+    pub fn interrupted(&self) -> bool {
+        self.full_screen.interrupted()
+    }
+
+    /// A note was played on the guitar of window `guitar`
+    pub fn strummed(&mut self, guitar: usize, position: GuitarPosition) {
+        if let Some(string) = self
+            .view
+            .guitars
+            .get_mut(guitar)
+            .and_then(|strings| strings.get_mut(position.string as usize))
+        {
+            string.fret = position.fret;
+            string.strummed = Some(Instant::now());
+        }
+    }
+
+    /// Redraws the screen unless it was drawn very recently
+    pub fn draw(&mut self, progress: &Progress) {
+        if self
+            .last_draw
+            .is_some_and(|last| last.elapsed() < FRAME_INTERVAL)
+        {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        let log = self.full_screen.log();
+        let view = &self.view;
+        // Drawing only fails when the terminal is gone, there's nothing to do then
+        let _ = self
+            .full_screen
+            .draw(|frame| view.render(frame, progress, &log));
+    }
+}
+
+impl View {
+    fn render(&self, frame: &mut Frame, progress: &Progress, log: &[String]) {
+        let fretboard_height = STRING_COUNT as u16 + 3;
+        let [header, time, fretboards, notes, log_area] = Layout::vertical([
+            Constraint::Length(4),
+            Constraint::Length(3),
+            Constraint::Length(fretboard_height * self.guitars.len() as u16),
+            Constraint::Length(UPCOMING_NOTES as u16 + 2),
+            Constraint::Min(3),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(self.header(progress), header);
+        frame.render_widget(self.time(progress), time);
+        let boards = Layout::vertical(vec![
+            Constraint::Length(fretboard_height);
+            self.guitars.len()
+        ])
+        .split(fretboards);
+        for (i, area) in boards.iter().enumerate() {
+            frame.render_widget(self.fretboard(i), *area);
+        }
+
+        let [upcoming, counters] =
+            Layout::horizontal([Constraint::Fill(2), Constraint::Fill(1)]).areas(notes);
+        frame.render_widget(self.upcoming(progress), upcoming);
+        frame.render_widget(counters_widget(progress), counters);
+        frame.render_widget(log_widget(log, log_area), log_area);
+    }
+
+    fn header(&self, progress: &Progress) -> Paragraph<'_> {
+        let mut title = vec![Span::from(self.title.as_str()).bold()];
+        if let Some((position, length)) = self.queue {
+            title.push(Span::from(format!("  song {} of {}", position + 1, length)).dim());
+        }
+
+        let (bar, beat) = self.bar_beat(progress.tick);
+        let micros_per_beat = self
+            .tempo_map
+            .micros_at(progress.tick + self.ticks_per_beat)
+            - self.tempo_map.micros_at(progress.tick);
+        let bpm = 60_000_000.0 / micros_per_beat.max(1) as f64 * progress.speed;
+        let details = format!(
+            "Bar {} beat {}  |  {:.0} BPM  |  Speed {:.2}x  |  Transpose {:+}",
+            bar, beat, bpm, progress.speed, progress.shift
+        );
+
+        let state = if progress.paused {
+            " ⏸ Paused "
+        } else {
+            " ▶ Playing "
+        };
+        Paragraph::new(vec![Line::from(title), Line::from(details)])
+            .block(Block::bordered().title(state))
+    }
+
+    fn time(&self, progress: &Progress) -> Gauge<'_> {
+        let position = self
+            .tempo_map
+            .micros_at(progress.tick)
+            .min(self.length_micros);
+        let secs = |micros: u64| micros as f64 / 1_000_000.0 / progress.speed;
+        let label = format!(
+            "{} / {}  (-{})",
+            format_duration(secs(position)),
+            format_duration(secs(self.length_micros)),
+            format_duration(secs(self.length_micros - position))
+        );
+        Gauge::default()
+            .block(Block::bordered())
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .ratio(position as f64 / self.length_micros.max(1) as f64)
+            .label(label)
+    }
+
+    fn fretboard(&self, guitar: usize) -> Paragraph<'_> {
+        let now = Instant::now();
+        // Lined up with the cells below, after the string label
+        let mut lines = vec![Line::from(
+            iter::once("  ".to_string())
+                .chain((0..FRET_COUNT).map(|fret| format!("{:^3} ", fret)))
+                .collect::<String>(),
+        )
+        .dim()];
+        for string in (0..STRING_COUNT).rev() {
+            let state = self.guitars[guitar][string];
+            let sounding = state
+                .strummed
+                .is_some_and(|strummed| now - strummed < RING_TIME);
+            let held = if sounding {
+                Style::default()
+                    .fg(Color::Green)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Yellow)
+            };
+
+            let mut spans = vec![Span::styled(
+                format!("{} ", STRING_LABELS[string]),
+                if sounding { held } else { Style::default() },
+            )];
+            for fret in 0..FRET_COUNT {
+                let cell = match (fret == state.fret, fret) {
+                    (true, _) => Span::styled("─●─", held),
+                    (false, 0) => Span::from("   "),
+                    (false, _) => Span::from("───").dim(),
+                };
+                spans.push(cell);
+                spans.push(Span::from(if fret == 0 { "‖" } else { "┼" }).dim());
+            }
+            lines.push(Line::from(spans));
+        }
+
+        let title = if self.guitars.len() > 1 {
+            format!(" Window {} ", guitar + 1)
+        } else {
+            " Fretboard ".to_string()
+        };
+        Paragraph::new(lines).block(Block::bordered().title(title))
+    }
+
+    fn upcoming(&self, progress: &Progress) -> Paragraph<'_> {
+        let now = self.tempo_map.micros_at(progress.tick);
+        let next = self
+            .notes
+            .partition_point(|note| note.tick <= progress.tick);
+        let lines: Vec<Line> = self.notes[next..]
+            .iter()
+            .take(UPCOMING_NOTES)
+            .map(|note| {
+                let wait = (self.tempo_map.micros_at(note.tick) - now) as f64
+                    / 1_000_000.0
+                    / progress.speed;
+                let shifted = note.key as i16 + progress.shift as i16;
+                let played = match self.options.fit_note(note.key, progress.shift) {
+                    Some(fitted) if fitted as i16 == shifted => note_name(fitted),
+                    Some(fitted) => format!("{} (clamped)", note_name(fitted)),
+                    None => "out of range".to_string(),
+                };
+                Line::from(format!(
+                    "+{:>5.2}s  {:<16} track {}",
+                    wait, played, note.track
+                ))
+            })
+            .collect();
+        Paragraph::new(lines).block(Block::bordered().title(" Upcoming notes "))
+    }
+
+    // (bar, beat) playing at `tick`, both counted from 1
+    fn bar_beat(&self, tick: u64) -> (u64, u64) {
+        let (mut bar, mut bar_start) = (0, 0);
+        let mut signature = (4, 4);
+        for (&change, &next) in self.signatures.range(..=tick) {
+            bar += (change - bar_start).div_ceil(self.bar_ticks(signature));
+            bar_start = change;
+            signature = next;
+        }
+        let bar_ticks = self.bar_ticks(signature);
+        let beat_ticks = (self.ticks_per_beat * 4 / signature.1.max(1)).max(1);
+        (
+            bar + (tick - bar_start) / bar_ticks + 1,
+            (tick - bar_start) % bar_ticks / beat_ticks + 1,
+        )
+    }
+
+    fn bar_ticks(&self, (numerator, denominator): (u64, u64)) -> u64 {
+        (self.ticks_per_beat * 4 * numerator / denominator.max(1)).max(1)
+    }
+}
+
+fn counters_widget(progress: &Progress) -> Paragraph<'static> {
+    Paragraph::new(vec![
+        Line::from(format!("Played   {}", progress.played)),
+        Line::from(format!("Dropped  {}", progress.dropped)),
+        Line::from(format!("Clamped  {}", progress.clamped)),
+    ])
+    .block(Block::bordered().title(" Notes "))
+}
+
+// The newest lines that fit
+fn log_widget(log: &[String], area: Rect) -> Paragraph<'_> {
+    let shown = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = log[log.len().saturating_sub(shown)..]
+        .iter()
+        .map(|line| Line::from(line.as_str()))
+        .collect();
+    Paragraph::new(lines).block(Block::bordered().title(" Log "))
+}
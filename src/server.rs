@@ -0,0 +1,486 @@
+use crate::{
+    library,
+    remote::{Command, Remote},
+};
+use log::debug;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+pub const DEFAULT_PORT: u16 = 7878;
+// Requests are small JSON objects, anything bigger is refused
+const MAX_BODY: usize = 64 * 1024;
+// The request line and headers together, browsers send a few kilobytes at most
+const MAX_HEAD: u64 = 16 * 1024;
+// Clients that stop sending or reading are dropped after this long
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+const INDEX_HTML: &str = include_str!("remote.html");
+
+struct Request {
+    method: String,
+    path: String,
+    // The token from the query string, for the events WebSocket that can't send headers
+    token: Option<String>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Who may use the API. Browsers let any page send simple POSTs and open
+// WebSockets to any address, so requests from other sites are refused and the
+// LAN needs the token printed at startup.
+struct Access {
+    port: u16,
+    // Set when other devices can connect
+    token: Option<String>,
+}
+
+impl Access {
+    fn check(&self, request: &Request) -> Result<(), Response> {
+        let host = request.header("host").unwrap_or_default();
+        // Only the names of this computer, so another site's domain resolving
+        // to 127.0.0.1 doesn't count as the same site
+        if self.token.is_none()
+            && host != format!("127.0.0.1:{}", self.port)
+            && host != format!("localhost:{}", self.port)
+        {
+            return Err(Response::error(403, "unknown host"));
+        }
+        // Scripts and curl don't send an Origin, browsers always do for these requests
+        if let Some(origin) = request.header("origin") {
+            if origin != format!("http://{}", host) {
+                return Err(Response::error(
+                    403,
+                    "requests from other sites aren't allowed",
+                ));
+            }
+        }
+        if !request.path.starts_with("/api/") {
+            return Ok(());
+        }
+        if let Some(token) = &self.token {
+            let sent = request
+                .header("authorization")
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .or(request.token.as_deref());
+            if sent != Some(token.as_str()) {
+                return Err(Response::error(401, "missing or wrong token"));
+            }
+        }
+        if request.method == "POST"
+            && !request
+                .header("content-type")
+                .is_some_and(|value| value.starts_with("application/json"))
+        {
+            return Err(Response::error(
+                415,
+                "expected Content-Type: application/json",
+            ));
+        }
+        Ok(())
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, value: impl Serialize) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(&value).unwrap_or_default(),
+        }
+    }
+
+    fn ok() -> Self {
+        Response::json(200, json!({ "ok": true }))
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Response::json(status, json!({ "error": message }))
+    }
+}
+
+#[derive(Serialize)]
+struct LibrarySong {
+    key: String,
+    title: String,
+    path: String,
+    duration_secs: f64,
+    playability: f64,
+}
+
+#[derive(Deserialize)]
+struct EnqueueBody {
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct SeekBody {
+    position_secs: f64,
+}
+
+#[derive(Deserialize)]
+struct SpeedBody {
+    speed: f64,
+}
+
+/// Serves the remote control API on a background thread, only to this
+/// computer unless `lan` is set. Returns the address and, for the LAN, the
+/// token the page needs in its URL.
+pub fn start(
+    remote: Arc<Remote>,
+    db_path: &'static str,
+    port: u16,
+    lan: bool,
+) -> io::Result<(SocketAddr, Option<String>)> {
+    let ip = if lan {
+        Ipv4Addr::UNSPECIFIED
+    } else {
+        Ipv4Addr::LOCALHOST
+    };
+    let listener = TcpListener::bind((ip, port))?;
+    let addr = listener.local_addr()?;
+    let token = if lan {
+        // From the OS, the token is all that keeps the rest of the network out
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes).map_err(io::Error::other)?;
+        Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+    } else {
+        None
+    };
+    let access = Arc::new(Access {
+        port: addr.port(),
+        token: token.clone(),
+    });
+
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let remote = Arc::clone(&remote);
+            let access = Arc::clone(&access);
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &remote, &access, db_path) {
+                    debug!("Remote client error: {}", e);
+                }
+            });
+        }
+    });
+    Ok((addr, token))
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    remote: &Remote,
+    access: &Access,
+    db_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let request = read_request(&mut BufReader::new(stream.try_clone()?))?;
+    let upgrade = request
+        .header("upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let response = match access.check(&request) {
+        Ok(()) if request.path == "/api/events" && upgrade => {
+            return event_feed(stream, &request, remote);
+        }
+        Ok(()) => route(&request, remote, db_path),
+        Err(response) => response,
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        match response.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            415 => "Unsupported Media Type",
+            _ => "Internal Server Error",
+        },
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    Ok(())
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request, Box<dyn Error>> {
+    // Stops a client from sending one endless header
+    let mut head = reader.take(MAX_HEAD);
+    let mut line = String::new();
+    head.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or("empty request")?.to_string();
+    let target = parts.next().ok_or("missing path")?;
+    // The only query parameter is the token
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let token = query
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("token="))
+        .map(str::to_string);
+    let path = path.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            if head.limit() == 0 {
+                return Err("request headers too large".into());
+            }
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        token,
+        headers,
+        body: Vec::new(),
+    };
+    let length: usize = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        return Err("request body too large".into());
+    }
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+fn route(request: &Request, remote: &Remote, db_path: &str) -> Response {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => Response {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: INDEX_HTML.to_string(),
+        },
+        ("GET", "/api/library") => match library_songs(db_path) {
+            Ok(songs) => Response::json(200, songs),
+            Err(e) => Response::error(500, &e.to_string()),
+        },
+        ("GET", "/api/state") => Response::json(200, remote.status()),
+        ("GET", "/api/queue") => {
+            let status = remote.status();
+            Response::json(
+                200,
+                json!({ "queue": status.queue, "position": status.queue_position }),
+            )
+        }
+        ("POST", "/api/enqueue") => {
+            let Ok(body) = serde_json::from_slice::<EnqueueBody>(&request.body) else {
+                return Response::error(400, "expected {\"key\": <library key>}");
+            };
+            // Only songs from the library can be queued, not any path on the computer
+            let entry =
+                Connection::open(db_path).and_then(|conn| library::get_entry(&conn, &body.key));
+            match entry {
+                Ok(Some(entry)) => {
+                    remote.enqueue(entry.song);
+                    Response::ok()
+                }
+                Ok(None) => Response::error(404, "no song with that key in the library"),
+                Err(e) => Response::error(500, &e.to_string()),
+            }
+        }
+        ("POST", "/api/pause") => command(remote, Command::Pause),
+        ("POST", "/api/resume") => command(remote, Command::Resume),
+        ("POST", "/api/skip") => command(remote, Command::Skip),
+        ("POST", "/api/stop") => command(remote, Command::Stop),
+        ("POST", "/api/seek") => match serde_json::from_slice::<SeekBody>(&request.body) {
+            Ok(body) if body.position_secs >= 0.0 => {
+                command(remote, Command::Seek(body.position_secs))
+            }
+            _ => Response::error(400, "expected {\"position_secs\": <seconds>}"),
+        },
+        ("POST", "/api/speed") => match serde_json::from_slice::<SpeedBody>(&request.body) {
+            Ok(body) if (0.1..=4.0).contains(&body.speed) => {
+                command(remote, Command::Speed(body.speed))
+            }
+            _ => Response::error(400, "expected {\"speed\": <0.1 to 4.0>}"),
+        },
+        (_, "/" | "/api/library" | "/api/state" | "/api/queue") => Response::error(405, "use GET"),
+        (_, path) if path.starts_with("/api/") => Response::error(405, "use POST"),
+        _ => Response::error(404, "not found"),
+    }
+}
+
+fn command(remote: &Remote, command: Command) -> Response {
+    if remote.send_playing(command) {
+        Response::ok()
+    } else {
+        Response::error(409, "nothing playing")
+    }
+}
+
+fn library_songs(db_path: &str) -> rusqlite::Result<Vec<LibrarySong>> {
+    let conn = Connection::open(db_path)?;
+    let mut songs: Vec<LibrarySong> = library::load_entries(&conn)?
+        .into_iter()
+        .map(|(key, entry)| LibrarySong {
+            key,
+            title: entry.title,
+            path: entry.song.path.to_string_lossy().into_owned(),
+            duration_secs: entry.duration_secs,
+            playability: entry.playability,
+        })
+        .collect();
+    songs.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(songs)
+}
+
+// Upgrades the connection to a WebSocket and forwards playback events until
+// the client goes away
+fn event_feed(
+    mut stream: TcpStream,
+    request: &Request,
+    remote: &Remote,
+) -> Result<(), Box<dyn Error>> {
+    let key = request
+        .header("sec-websocket-key")
+        .ok_or("missing Sec-WebSocket-Key")?;
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    )?;
+
+    let events = remote.subscribe();
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    // Clients start from the current state, then follow the events
+    let state = json!({ "event": "state", "state": remote.status() });
+    socket.send(Message::text(state.to_string()))?;
+    for event in events {
+        socket.send(Message::text(event))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(raw: &str) -> Result<Request, Box<dyn Error>> {
+        read_request(&mut raw.as_bytes())
+    }
+
+    fn allowed(access: &Access, raw: &str) -> bool {
+        access.check(&request(raw).unwrap()).is_ok()
+    }
+
+    const LOCAL: Access = Access {
+        port: 7878,
+        token: None,
+    };
+
+    #[test]
+    fn same_site_posts_are_allowed() {
+        assert!(allowed(
+            &LOCAL,
+            "POST /api/stop HTTP/1.1\r\nHost: 127.0.0.1:7878\r\nOrigin: http://127.0.0.1:7878\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}"
+        ));
+        assert!(allowed(
+            &LOCAL,
+            "GET /api/state HTTP/1.1\r\nHost: localhost:7878\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn cross_site_requests_are_refused() {
+        // A simple form post from another site
+        assert!(!allowed(
+            &LOCAL,
+            "POST /api/stop HTTP/1.1\r\nHost: 127.0.0.1:7878\r\nOrigin: http://example.com\r\nContent-Type: application/json\r\n\r\n"
+        ));
+        assert!(!allowed(
+            &LOCAL,
+            "POST /api/stop HTTP/1.1\r\nHost: 127.0.0.1:7878\r\nContent-Type: text/plain\r\n\r\n"
+        ));
+        // Another domain pointed at this computer
+        assert!(!allowed(
+            &LOCAL,
+            "GET /api/state HTTP/1.1\r\nHost: evil.example:7878\r\n\r\n"
+        ));
+        assert!(!allowed(
+            &LOCAL,
+            "GET /api/events HTTP/1.1\r\nHost: 127.0.0.1:7878\r\nOrigin: http://example.com\r\nUpgrade: websocket\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn lan_needs_the_token() {
+        let lan = Access {
+            port: 7878,
+            token: Some("secret".to_string()),
+        };
+        assert!(allowed(
+            &lan,
+            "GET / HTTP/1.1\r\nHost: 192.168.1.2:7878\r\n\r\n"
+        ));
+        assert!(!allowed(
+            &lan,
+            "GET /api/state HTTP/1.1\r\nHost: 192.168.1.2:7878\r\n\r\n"
+        ));
+        assert!(allowed(
+            &lan,
+            "GET /api/state HTTP/1.1\r\nHost: 192.168.1.2:7878\r\nAuthorization: Bearer secret\r\n\r\n"
+        ));
+        assert!(allowed(
+            &lan,
+            "GET /api/events?token=secret HTTP/1.1\r\nHost: 192.168.1.2:7878\r\nOrigin: http://192.168.1.2:7878\r\n\r\n"
+        ));
+        assert!(!allowed(
+            &lan,
+            "GET /api/events?token=wrong HTTP/1.1\r\nHost: 192.168.1.2:7878\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn oversized_headers_are_refused() {
+        let raw = format!(
+            "GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(MAX_HEAD as usize)
+        );
+        assert!(request(&raw).is_err());
+        let raw = format!(
+            "GET / HTTP/1.1\r\nX-Long: {}",
+            "a".repeat(MAX_HEAD as usize)
+        );
+        assert!(request(&raw).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{abc, musicxml};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// A playable song, either a whole MIDI or MusicXML file or one tune of an
+/// ABC songbook
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SongSource {
+    pub path: PathBuf,
+    // Index of the tune in files that can hold several
+    pub tune: Option<usize>,
+    pub title: Option<String>,
+}
+
+impl SongSource {
+    /// A song that is a whole file
+    pub fn file(path: PathBuf) -> Self {
+        SongSource {
+            path,
+            tune: None,
+            title: None,
+        }
+    }
+
+    /// Identifies the song in the database
+    pub fn key(&self) -> String {
+        let path = self.path.to_string_lossy();
+        match self.tune {
+            Some(tune) => format!("{}#{}", path, tune),
+            None => path.into_owned(),
+        }
+    }
+
+    /// Finds the song a key or path points at, the first tune for songbooks
+    pub fn from_key(key: &str) -> Option<Self> {
+        let path = Path::new(key);
+        if path.is_file() {
+            return songs_in_file(path).into_iter().next();
+        }
+        let (path, tune) = key.rsplit_once('#')?;
+        let tune: usize = tune.parse().ok()?;
+        songs_in_file(Path::new(path))
+            .into_iter()
+            .find(|song| song.tune == Some(tune))
+    }
+
+    pub fn display_name(&self) -> String {
+        let file_name = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match (&self.title, self.tune) {
+            (Some(title), _) => format!("{}: {}", file_name, title),
+            (None, Some(tune)) => format!("{}: #{}", file_name, tune + 1),
+            (None, None) => file_name,
+        }
+    }
+
+    /// A file name friendly name for exports
+    pub fn file_stem(&self) -> String {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "song".to_string());
+        match self.tune {
+            Some(tune) => format!("{}-{}", stem, tune + 1),
+            None => stem,
+        }
+    }
+
+    /// Reads the song as Standard MIDI File bytes, converting other formats
+    pub fn load(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        match self.tune {
+            None if is_musicxml(&self.path) => musicxml::load(&self.path),
+            None => Ok(fs::read(&self.path)?),
+            Some(tune) => {
+                let text = fs::read_to_string(&self.path)?;
+                abc::split_tunes(&text)
+                    .get(tune)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "tune not found"))?
+                    .to_smf()
+            }
+        }
+    }
+}
+
+/// Lists the songs in a file, ABC files can hold several tunes
+pub fn songs_in_file(path: &Path) -> Vec<SongSource> {
+    read_songs_in_file(path).unwrap_or_default()
+}
+
+/// Like `songs_in_file`, with the reason a songbook couldn't be read
+pub fn read_songs_in_file(path: &Path) -> io::Result<Vec<SongSource>> {
+    Ok(match extension(path).as_deref() {
+        _ if is_midi(path) || is_musicxml(path) => vec![SongSource::file(path.to_path_buf())],
+        Some("abc") => {
+            let text = fs::read_to_string(path)?;
+            abc::split_tunes(&text)
+                .into_iter()
+                .enumerate()
+                .map(|(index, tune)| SongSource {
+                    path: path.to_path_buf(),
+                    tune: Some(index),
+                    title: tune
+                        .title
+                        .or_else(|| tune.number.map(|number| format!("X:{}", number))),
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    })
+}
+
+/// Whether the file has an extension the player can read
+pub fn is_song_file(path: &Path) -> bool {
+    is_midi(path) || is_musicxml(path) || extension(path).as_deref() == Some("abc")
+}
+
+fn is_midi(path: &Path) -> bool {
+    matches!(extension(path).as_deref(), Some("mid" | "midi" | "smf"))
+}
+
+fn is_musicxml(path: &Path) -> bool {
+    match extension(path).as_deref() {
+        Some("musicxml" | "mxl") => true,
+        // Plenty of other files are .xml, so look for a score at the start
+        Some("xml") => has_score_root(path),
+        _ => false,
+    }
+}
+
+fn has_score_root(path: &Path) -> bool {
+    let Ok(file) = fs::File::open(path) else {
+        return false;
+    };
+    // Room for the declaration, doctype and comments before the root element
+    let mut start = Vec::new();
+    if file.take(4096).read_to_end(&mut start).is_err() {
+        return false;
+    }
+    let start = String::from_utf8_lossy(&start);
+    // Only partwise scores can be loaded, timewise ones aren't listed
+    start.contains("<score-partwise")
+}
+
+/// The file's extension in lower case, so .MID and .Mid work like .mid
+pub fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .map(str::to_ascii_lowercase)
+}
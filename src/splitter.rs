@@ -0,0 +1,455 @@
+use crate::{
+    arrangement::{note_name, ArrangeOptions, ArrangedNote, Arrangement, Fretboard},
+    guitar::STRUM_KEYS,
+};
+use midly::Smf;
+use serde::Serialize;
+use std::{cmp::Reverse, fs::File, io, iter, path::Path};
+use tabled::{builder::Builder, settings::Style};
+
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    // The highest note at every moment
+    Melody,
+    Harmony,
+    // The lowest note whenever there's more than one
+    Bass,
+}
+
+impl Role {
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Melody => "Melody",
+            Role::Harmony => "Harmony",
+            Role::Bass => "Bass",
+        }
+    }
+}
+
+/// How each part is saved
+#[derive(Clone, Copy)]
+pub enum PartFormat {
+    Midi,
+    Timeline,
+}
+
+impl PartFormat {
+    pub const ALL: [PartFormat; 2] = [PartFormat::Midi, PartFormat::Timeline];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PartFormat::Midi => "MIDI, one file per part",
+            PartFormat::Timeline => "Action timeline (JSON), the clicks and keys for each part",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            PartFormat::Midi => "mid",
+            PartFormat::Timeline => "json",
+        }
+    }
+}
+
+pub struct Part {
+    pub role: Role,
+    pub arrangement: Arrangement,
+}
+
+/// A song shared out between several guitars, with the single guitar
+/// arrangement it's measured against
+pub struct BandSplit {
+    pub parts: Vec<Part>,
+    single: Arrangement,
+    // Notes that were the highest of their moment
+    melody_notes: usize,
+    melody_played: usize,
+    melody_played_single: usize,
+    // Melody notes no string of the first part could play
+    melody_dropped: Vec<ArrangedNote>,
+}
+
+impl BandSplit {
+    /// Splits the selected tracks between `players` guitars. The top note of
+    /// every moment goes to the first part and the lowest to the last, the
+    /// rest are dealt out to the parts between. A harmony or bass note that
+    /// finds no free string in its own part is given to any part that has
+    /// one, melody notes stay in the first part or are dropped.
+    pub fn new(smf: &Smf, tracks: &[usize], options: &ArrangeOptions, players: usize) -> Self {
+        let players = players.clamp(MIN_PLAYERS, MAX_PLAYERS);
+        let single = Arrangement::new(smf, tracks, options);
+        let shift = single.shift;
+
+        let roles: Vec<Role> = (0..players)
+            .map(|part| match part {
+                0 => Role::Melody,
+                part if part == players - 1 => Role::Bass,
+                _ => Role::Harmony,
+            })
+            .collect();
+        let mut fretboards: Vec<Fretboard> = (0..players).map(|_| Fretboard::default()).collect();
+        let mut notes: Vec<Vec<ArrangedNote>> = vec![Vec::new(); players];
+        let mut next_harmony = 0;
+        let (mut melody_notes, mut melody_played, mut melody_played_single) = (0, 0, 0);
+        let mut melody_dropped = Vec::new();
+
+        for moment in single.notes.chunk_by(|a, b| a.tick == b.tick) {
+            for fretboard in &mut fretboards {
+                fretboard.next_chord();
+            }
+            let mut by_pitch: Vec<&ArrangedNote> = moment.iter().collect();
+            by_pitch.sort_by_key(|note| Reverse(note.key));
+
+            let last = by_pitch.len() - 1;
+            let mut ranked: Vec<(usize, usize, &ArrangedNote)> = by_pitch
+                .into_iter()
+                .enumerate()
+                .map(|(rank, note)| {
+                    let preferred = if rank == 0 {
+                        0
+                    } else if rank == last || players == 2 {
+                        players - 1
+                    } else {
+                        next_harmony += 1;
+                        1 + (next_harmony - 1) % (players - 2)
+                    };
+                    (rank, preferred, note)
+                })
+                .collect();
+            // The melody and the bass get their strings before the harmony
+            ranked.sort_by_key(|&(rank, ..)| match rank {
+                0 => 0,
+                rank if rank == last => 1,
+                _ => 2,
+            });
+
+            for (rank, preferred, note) in ranked {
+                // Overflow goes to the bass and harmony before the melody, and
+                // the melody never leaves its part
+                let mut candidates = iter::once(preferred)
+                    .chain((0..players).rev().filter(|&p| rank != 0 && p != preferred));
+                let placed = options.fit_note(note.key, shift).and_then(|fitted| {
+                    candidates.find_map(|part| {
+                        let position = fretboards[part].find_best_string(fitted)?;
+                        fretboards[part].mark_played(position.string);
+                        Some((part, position))
+                    })
+                });
+
+                if rank == 0 {
+                    melody_notes += 1;
+                    melody_played += usize::from(placed.is_some());
+                    melody_played_single += usize::from(note.position.is_some());
+                    if placed.is_none() {
+                        melody_dropped.push(note.clone());
+                    }
+                }
+                let part = placed.map_or(preferred, |(part, _)| part);
+                notes[part].push(ArrangedNote {
+                    position: placed.map(|(_, position)| position),
+                    ..note.clone()
+                });
+            }
+        }
+
+        let parts = roles
+            .into_iter()
+            .zip(notes)
+            .map(|(role, notes)| Part {
+                role,
+                arrangement: Arrangement {
+                    shift,
+                    notes,
+                    length_micros: single.length_micros,
+                },
+            })
+            .collect();
+
+        BandSplit {
+            parts,
+            single,
+            melody_notes,
+            melody_played,
+            melody_played_single,
+            melody_dropped,
+        }
+    }
+
+    /// Name of a part for file names and the report, e.g. "part2-harmony"
+    pub fn part_name(&self, index: usize) -> String {
+        format!(
+            "part{}-{}",
+            index + 1,
+            self.parts[index].role.label().to_lowercase()
+        )
+    }
+
+    /// Compares how much of the selection the band keeps against one guitar,
+    /// followed by what each part plays
+    pub fn report(&self) -> String {
+        let total = self.single.notes.len();
+        let share = |count: usize| percent(count, total);
+
+        let single_played = total - self.single.dropped_notes();
+        let single_written = as_written(self.single.notes.iter());
+        let band_played: usize = self
+            .parts
+            .iter()
+            .map(|part| part.arrangement.notes.len() - part.arrangement.dropped_notes())
+            .sum();
+        let band_written = as_written(
+            self.parts
+                .iter()
+                .flat_map(|part| part.arrangement.notes.iter()),
+        );
+
+        let mut builder = Builder::new();
+        builder.push_record(["Arrangement", "Notes played", "Preserved", "As written"]);
+        builder.push_record([
+            "One guitar".to_string(),
+            format!("{} of {}", single_played, total),
+            share(single_played),
+            share(single_written),
+        ]);
+        builder.push_record([
+            format!("Band of {}", self.parts.len()),
+            format!("{} of {}", band_played, total),
+            share(band_played),
+            share(band_written),
+        ]);
+        let summary = builder.build().with(Style::psql()).to_string();
+
+        let mut builder = Builder::new();
+        builder.push_record(["Part", "Role", "Notes", "Largest chord", "Range"]);
+        for (i, part) in self.parts.iter().enumerate() {
+            let played: Vec<&ArrangedNote> = part
+                .arrangement
+                .notes
+                .iter()
+                .filter(|note| note.position.is_some())
+                .collect();
+            let largest_chord = played
+                .chunk_by(|a, b| a.tick == b.tick)
+                .map(<[_]>::len)
+                .max()
+                .unwrap_or(0);
+            let range = match (
+                played.iter().map(|note| note.note).min(),
+                played.iter().map(|note| note.note).max(),
+            ) {
+                (Some(low), Some(high)) => format!("{} - {}", note_name(low), note_name(high)),
+                _ => "-".to_string(),
+            };
+            builder.push_record([
+                (i + 1).to_string(),
+                part.role.label().to_string(),
+                played.len().to_string(),
+                largest_chord.to_string(),
+                range,
+            ]);
+        }
+        let parts = builder.build().with(Style::psql()).to_string();
+
+        let mut melody = format!(
+            "Melody: {} of {} notes kept ({} with one guitar)",
+            self.melody_played, self.melody_notes, self.melody_played_single
+        );
+        if !self.melody_dropped.is_empty() {
+            let dropped: Vec<String> = self
+                .melody_dropped
+                .iter()
+                .map(|note| {
+                    format!(
+                        "{} at {:.2}s",
+                        note_name(note.note),
+                        note.micros as f64 / 1_000_000.0
+                    )
+                })
+                .collect();
+            melody.push_str(&format!("\nDropped melody notes: {}", dropped.join(", ")));
+        }
+
+        format!("{}\n\n{}\n\n{}", summary, melody, parts)
+    }
+}
+
+fn as_written<'a>(notes: impl Iterator<Item = &'a ArrangedNote>) -> usize {
+    notes
+        .filter(|note| note.position.is_some() && !note.clamped)
+        .count()
+}
+
+fn percent(count: usize, total: usize) -> String {
+    if total == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", count as f64 / total as f64 * 100.0)
+}
+
+#[derive(Serialize)]
+struct Timeline<'a> {
+    part: &'a str,
+    actions: Vec<Action>,
+}
+
+// One note as the inputs that play it, at the original tempo
+#[derive(Serialize)]
+struct Action {
+    time_ms: f64,
+    // 1 is the low E
+    string: i32,
+    fret: i32,
+    key: char,
+    note: String,
+}
+
+/// Writes the fret clicks and strum keys of a part as JSON
+pub fn write_timeline(arrangement: &Arrangement, part: &str, path: &Path) -> io::Result<()> {
+    let actions = arrangement
+        .notes
+        .iter()
+        .filter_map(|note| {
+            let position = note.position?;
+            Some(Action {
+                time_ms: note.micros as f64 / 1000.0,
+                string: position.string + 1,
+                fret: position.fret,
+                key: STRUM_KEYS[position.string as usize],
+                note: note_name(note.note),
+            })
+        })
+        .collect();
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(file, &Timeline { part, actions })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arrangement::{tests::song, RangePolicy, OPEN_STRINGS};
+
+    fn split(notes: &[(u64, u8, u64)], options: &ArrangeOptions, players: usize) -> BandSplit {
+        BandSplit::new(&song(notes), &[0], options, players)
+    }
+
+    fn keys(part: &Part) -> Vec<u8> {
+        let mut keys: Vec<u8> = part.arrangement.notes.iter().map(|note| note.key).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn two_players_share_the_melody_and_the_rest() {
+        let band = split(
+            &[(0, 60, 480), (0, 52, 480), (0, 45, 480)],
+            &ArrangeOptions::default(),
+            2,
+        );
+        let roles: Vec<Role> = band.parts.iter().map(|part| part.role).collect();
+        assert_eq!(roles, [Role::Melody, Role::Bass]);
+        assert_eq!(keys(&band.parts[0]), [60]);
+        assert_eq!(keys(&band.parts[1]), [45, 52]);
+        assert_eq!(band.part_name(1), "part2-bass");
+    }
+
+    #[test]
+    fn harmony_is_dealt_out_between_the_middle_parts() {
+        let band = split(
+            &[
+                (0, 60, 480),
+                (0, 55, 480),
+                (0, 52, 480),
+                (0, 45, 480),
+                (480, 62, 480),
+                (480, 57, 480),
+                (480, 53, 480),
+                (480, 47, 480),
+            ],
+            &ArrangeOptions::default(),
+            4,
+        );
+        let roles: Vec<Role> = band.parts.iter().map(|part| part.role).collect();
+        assert_eq!(
+            roles,
+            [Role::Melody, Role::Harmony, Role::Harmony, Role::Bass]
+        );
+        assert_eq!(keys(&band.parts[0]), [60, 62]);
+        assert_eq!(keys(&band.parts[1]), [55, 57]);
+        assert_eq!(keys(&band.parts[2]), [52, 53]);
+        assert_eq!(keys(&band.parts[3]), [45, 47]);
+        assert!(band
+            .parts
+            .iter()
+            .all(|part| part.arrangement.dropped_notes() == 0));
+    }
+
+    #[test]
+    fn player_counts_are_limited() {
+        let notes = [(0, 60, 480)];
+        let options = ArrangeOptions::default();
+        assert_eq!(split(&notes, &options, 1).parts.len(), MIN_PLAYERS);
+        assert_eq!(split(&notes, &options, 20).parts.len(), MAX_PLAYERS);
+    }
+
+    #[test]
+    fn melody_notes_without_a_string_are_dropped_and_listed() {
+        let options = ArrangeOptions {
+            shift: Some(0),
+            policy: RangePolicy::Drop,
+            ..ArrangeOptions::default()
+        };
+        // The top note is above the range, so no string of the first part plays it
+        let band = split(&[(0, 90, 480), (0, 60, 480), (480, 64, 480)], &options, 2);
+
+        assert_eq!(keys(&band.parts[0]), [64, 90]);
+        assert_eq!(band.parts[0].arrangement.dropped_notes(), 1);
+        assert_eq!(keys(&band.parts[1]), [60]);
+        assert_eq!(band.melody_notes, 2);
+        assert_eq!(band.melody_played, 1);
+        assert_eq!(band.melody_played_single, 1);
+        assert_eq!(band.melody_dropped.len(), 1);
+        assert_eq!(band.melody_dropped[0].key, 90);
+
+        let report = band.report();
+        assert!(report.contains("Melody: 1 of 2 notes kept (1 with one guitar)"));
+        assert!(report.contains("Dropped melody notes: "));
+        assert!(report.contains(" at 0.00s"));
+    }
+
+    #[test]
+    fn report_compares_the_band_with_one_guitar() {
+        // Only the low E string reaches its open note, so one guitar plays
+        // one of the three and every extra player adds one
+        let low_e = OPEN_STRINGS[0];
+        let notes = [(0, low_e, 480), (0, low_e, 480), (0, low_e, 480)];
+        let options = ArrangeOptions::default();
+
+        let duo = split(&notes, &options, 2).report();
+        assert!(duo.contains("1 of 3"));
+        assert!(duo.contains("33.3%"));
+        assert!(duo.contains("Band of 2"));
+        assert!(duo.contains("2 of 3"));
+        assert!(duo.contains("66.7%"));
+
+        let trio = split(&notes, &options, 3).report();
+        assert!(trio.contains("Band of 3"));
+        assert!(trio.contains("3 of 3"));
+        assert!(trio.contains("100.0%"));
+    }
+
+    #[test]
+    fn empty_selections_report_no_percentages() {
+        assert_eq!(percent(0, 0), "-");
+        assert_eq!(percent(1, 3), "33.3%");
+        let band = split(&[], &ArrangeOptions::default(), 2);
+        assert!(band
+            .parts
+            .iter()
+            .all(|part| part.arrangement.notes.is_empty()));
+        assert!(band.report().contains("0 of 0"));
+    }
+}
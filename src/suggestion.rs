@@ -0,0 +1,137 @@
+use crate::{
+    analysis::track_keys,
+    arrangement::{optimal_shift, ArrangeOptions, Arrangement},
+};
+use midly::{MidiMessage, Smf, TrackEventKind};
+use std::collections::HashMap;
+
+// Tracks scoring at least this are suggested for the full band
+const BAND_THRESHOLD: f64 = 40.0;
+// Notes per second a guitar part can comfortably play
+const COMFORTABLE_RATE: f64 = 6.0;
+// Tracks with fewer notes than this are likely fills or count-ins
+const MIN_NOTES: usize = 16;
+
+/// Which tracks to pre-select when a song has no saved selection
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SuggestMode {
+    Band,
+    Melody,
+}
+
+impl SuggestMode {
+    pub const ALL: [SuggestMode; 2] = [SuggestMode::Band, SuggestMode::Melody];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SuggestMode::Band => "Full band (every track that suits the guitar)",
+            SuggestMode::Melody => "Melody only (the best single track)",
+        }
+    }
+}
+
+// How well each General MIDI family of 8 programs suits the guitar, in the
+// order of INSTRUMENTS
+const FAMILY_WEIGHTS: [f64; 16] = [
+    0.8, // Piano
+    0.6, // Chromatic Percussion
+    0.6, // Organ
+    1.0, // Guitar
+    0.4, // Bass
+    0.6, // Strings
+    0.5, // Ensemble
+    0.7, // Brass
+    0.8, // Reed
+    0.8, // Pipe
+    0.9, // Synth Lead
+    0.2, // Synth Pad
+    0.2, // Synth Effects
+    0.7, // Ethnic
+    0.1, // Percussive
+    0.0, // Sound Effects
+];
+
+/// Scores every track from 0 to 100 for how well it suits the guitar, None for
+/// tracks without notes
+pub fn score_tracks(smf: &Smf) -> Vec<Option<f64>> {
+    (0..smf.tracks.len())
+        .map(|track| score_track(smf, track))
+        .collect()
+}
+
+fn score_track(smf: &Smf, track: usize) -> Option<f64> {
+    let keys = track_keys(smf, track);
+    if keys.is_empty() {
+        return None;
+    }
+
+    let mut program = None;
+    for event in &smf.tracks[track] {
+        if let TrackEventKind::Midi { channel, message } = event.kind {
+            match message {
+                // Notes on the rhythm channel are drum sounds, not pitches
+                MidiMessage::NoteOn { .. } if channel == 9 => return Some(0.0),
+                MidiMessage::ProgramChange { program: p } if program.is_none() => {
+                    program = Some(p.as_int())
+                }
+                _ => {}
+            }
+        }
+    }
+    // Players default to the piano without a program change
+    let family = FAMILY_WEIGHTS[program.unwrap_or(0) as usize / 8];
+
+    let options = ArrangeOptions::default();
+    let shift = optimal_shift(&keys, options.min_note, options.max_note).0;
+    let arrangement = Arrangement::new(
+        smf,
+        &[track],
+        &ArrangeOptions {
+            shift: Some(shift),
+            ..options
+        },
+    );
+    let notes = &arrangement.notes;
+    let range_fit = arrangement.playability() / 100.0;
+
+    // Share of onsets that are single notes rather than chords
+    let mut onsets: HashMap<u64, usize> = HashMap::new();
+    for note in notes {
+        *onsets.entry(note.tick).or_default() += 1;
+    }
+    let melodic = onsets.values().filter(|&&count| count == 1).count() as f64 / onsets.len() as f64;
+
+    let span_secs = (notes[notes.len() - 1].micros - notes[0].micros) as f64 / 1_000_000.0;
+    let rate = notes.len() as f64 / span_secs.max(1.0);
+    let mut density = (COMFORTABLE_RATE / rate).min(1.0);
+    if notes.len() < MIN_NOTES {
+        density *= notes.len() as f64 / MIN_NOTES as f64;
+    }
+
+    Some(100.0 * family * range_fit * density * (0.5 + 0.5 * melodic))
+}
+
+/// The tracks to pre-select, best first
+pub fn suggest_tracks(scores: &[Option<f64>], mode: SuggestMode) -> Vec<usize> {
+    let mut ranked: Vec<(usize, f64)> = scores
+        .iter()
+        .enumerate()
+        .filter_map(|(track, score)| Some((track, (*score)?)))
+        .filter(|&(_, score)| score > 0.0)
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let band: Vec<usize> = ranked
+        .iter()
+        .filter(|&&(_, score)| score >= BAND_THRESHOLD)
+        .map(|&(track, _)| track)
+        .collect();
+    match mode {
+        SuggestMode::Band if !band.is_empty() => band,
+        // Nothing scores well so the best track is still better than none
+        _ => ranked
+            .first()
+            .map(|&(track, _)| vec![track])
+            .unwrap_or_default(),
+    }
+}
@@ -0,0 +1,114 @@
+use crate::arrangement::{Arrangement, STRING_COUNT};
+use midly::{MetaMessage, Smf, Timing, TrackEventKind};
+use std::{collections::BTreeMap, fmt::Write};
+
+// Printed from the top line (high E) down to the low E
+const STRING_LABELS: [&str; STRING_COUNT] = ["E", "A", "D", "G", "B", "E"];
+// Systems are wrapped once a line would get wider than this
+const MAX_LINE_WIDTH: usize = 100;
+// Notes are snapped to sixteenth notes
+const COLUMNS_PER_BEAT: u64 = 4;
+
+/// Renders the arrangement as ASCII tab with a bar line per measure
+pub fn render_tab(smf: &Smf, arrangement: &Arrangement) -> String {
+    let ticks_per_beat = ticks_per_beat(smf.header.timing);
+    let column_ticks = (ticks_per_beat / COLUMNS_PER_BEAT).max(1);
+
+    // column -> fret on each string
+    let mut columns: BTreeMap<u64, [Option<i32>; STRING_COUNT]> = BTreeMap::new();
+    for note in &arrangement.notes {
+        if let Some(position) = note.position {
+            let column = (note.tick + column_ticks / 2) / column_ticks;
+            let frets = columns.entry(column).or_default();
+            // Keep the first note when two land on the same string after snapping
+            frets[position.string as usize].get_or_insert(position.fret);
+        }
+    }
+    let last_column = columns.keys().last().copied().unwrap_or(0);
+
+    let mut bars: Vec<[String; STRING_COUNT]> = Vec::new();
+    let mut bar_start = 0;
+    let signatures = time_signatures(smf);
+    while bar_start <= last_column {
+        let (numerator, denominator) = signatures
+            .range(..=bar_start * column_ticks)
+            .next_back()
+            .map_or((4, 4), |(_, &sig)| sig);
+        let bar_columns = (numerator * COLUMNS_PER_BEAT * 4 / denominator).max(1);
+
+        let mut bar: [String; STRING_COUNT] = Default::default();
+        for column in bar_start..bar_start + bar_columns {
+            let frets = columns.get(&column).copied().unwrap_or_default();
+            let cells = frets.map(|fret| fret.map_or(String::new(), |f| f.to_string()));
+            let width = cells.iter().map(String::len).max().unwrap_or(0).max(1);
+            for (line, cell) in bar.iter_mut().zip(&cells) {
+                write!(line, "-{:-<width$}", cell, width = width).unwrap();
+            }
+        }
+        for line in &mut bar {
+            line.push_str("-|");
+        }
+        bars.push(bar);
+        bar_start += bar_columns;
+    }
+
+    let mut tab = format!(
+        "Shift: {} | Notes: {} | Dropped: {}\n",
+        arrangement.shift,
+        arrangement.notes.len(),
+        arrangement.dropped_notes()
+    );
+    let mut system: Vec<&[String; STRING_COUNT]> = Vec::new();
+    let mut width = 0;
+    for (i, bar) in bars.iter().enumerate() {
+        let bar_width = bar[0].chars().count();
+        if !system.is_empty() && width + bar_width > MAX_LINE_WIDTH {
+            write_system(&mut tab, &system, i - system.len());
+            system.clear();
+            width = 0;
+        }
+        system.push(bar);
+        width += bar_width;
+    }
+    if !system.is_empty() {
+        write_system(&mut tab, &system, bars.len() - system.len());
+    }
+    tab
+}
+
+fn write_system(tab: &mut String, bars: &[&[String; STRING_COUNT]], first_bar: usize) {
+    writeln!(tab, "\nBar {}", first_bar + 1).unwrap();
+    for string in (0..STRING_COUNT).rev() {
+        tab.push_str(STRING_LABELS[string]);
+        tab.push('|');
+        for bar in bars {
+            tab.push_str(&bar[string]);
+        }
+        tab.push('\n');
+    }
+}
+
+fn ticks_per_beat(timing: Timing) -> u64 {
+    match timing {
+        Timing::Metrical(ppq) => ppq.as_int().max(1) as u64,
+        // No beats in timecode files, use half a second (120 BPM)
+        Timing::Timecode(fps, subframe) => (fps.as_int() as u64 * subframe as u64 / 2).max(1),
+    }
+}
+
+// tick -> (numerator, denominator)
+pub fn time_signatures(smf: &Smf) -> BTreeMap<u64, (u64, u64)> {
+    let mut signatures = BTreeMap::new();
+    for track in &smf.tracks {
+        let mut tick = 0;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator, ..)) =
+                event.kind
+            {
+                signatures.insert(tick, (numerator as u64, 1u64 << denominator.min(6)));
+            }
+        }
+    }
+    signatures
+}
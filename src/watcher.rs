@@ -0,0 +1,63 @@
+use crate::songs;
+use log::{debug, warn};
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{RecommendedWatcher, RecursiveMode},
+    DebounceEventResult, Debouncer,
+};
+use std::{
+    path::Path,
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+// Changes closer together than this are reported once, a file being copied in
+// writes many times
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the song folders for files being added, renamed or deleted
+pub struct LibraryWatcher {
+    // Stops watching when dropped
+    _debouncer: Debouncer<RecommendedWatcher>,
+    receiver: Receiver<DebounceEventResult>,
+}
+
+impl LibraryWatcher {
+    pub fn start(root: &Path) -> notify_debouncer_mini::notify::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let mut debouncer = new_debouncer(DEBOUNCE, sender)?;
+        debouncer.watcher().watch(root, RecursiveMode::Recursive)?;
+        Ok(LibraryWatcher {
+            _debouncer: debouncer,
+            receiver,
+        })
+    }
+
+    /// Whether a song or folder changed since the last call
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        while let Ok(result) = self.receiver.try_recv() {
+            match result {
+                Ok(events) => {
+                    for event in events.iter().filter(|event| is_watched(&event.path)) {
+                        debug!("Changed: {}", event.path.display());
+                        changed = true;
+                    }
+                }
+                Err(e) => warn!("Failed to watch the song folder: {}", e),
+            }
+        }
+        changed
+    }
+}
+
+// Songs and folders, so other files like an editor's temporary ones don't
+// cause a rescan. Deleted folders can't be told apart from files so anything
+// without an extension counts, and deleted .xml files can't be checked for a
+// score so they count too.
+fn is_watched(path: &Path) -> bool {
+    songs::is_song_file(path)
+        || path.is_dir()
+        || path.extension().is_none()
+        || songs::extension(path).as_deref() == Some("xml")
+}
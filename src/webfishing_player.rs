@@ -1,15 +1,9 @@
-use device_query::{DeviceQuery, DeviceState, Keycode};
-use enigo::{
-    Button, Coordinate,
-    Direction::{Click, Press, Release},
-    Enigo, Key, Keyboard, Mouse, Settings,
-};
 use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
 use log::{debug, info, warn};
-use midly::{Format, Smf, TrackEvent, TrackEventKind};
+use midly::{Format, MetaMessage, Smf, TrackEvent, TrackEventKind};
 use std::{
     cmp::Ordering,
-    collections::{BinaryHeap, HashMap},
+    collections::BinaryHeap,
     fmt::Write,
     io::Error,
     sync::{
@@ -18,12 +12,48 @@ use std::{
         Arc,
     },
     thread::sleep,
-    time::{Duration, Instant},
+    time::Duration,
 };
 use xcap::Window;
 
-const MIN_NOTE: u8 = 40;
-const MAX_NOTE: u8 = 79;
+use crate::{
+    arrangement::{calculate_optimal_shift, get_notes, ArrangeOptions, TempoMap, DEFAULT_TEMPO},
+    guitar::{Guitar, GuitarStats},
+    hotkeys::{HotkeyAction, Hotkeys},
+    remote::{Command, Event, Remote},
+    screen::{self, Progress, Screen},
+};
+
+/// Why playback of a song ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayEnd {
+    Finished,
+    // Escape was pressed, the queue editor opens
+    Interrupted,
+    // Move on to the next song in the queue
+    Skipped,
+    // Stop the whole queue
+    Stopped,
+    // Go back to the song before this one in the queue
+    Previous,
+}
+
+// What the player should do after checking the inputs
+enum Control {
+    End(PlayEnd),
+    // Carry on from this tick
+    Seek(u64),
+}
+
+/// Which game window plays each note when there are several
+#[derive(Clone, Debug, PartialEq)]
+pub enum Routing {
+    // Notes are dealt out to the windows in turn, across chords as well as
+    // within them
+    Split,
+    // The tracks each window plays, by window
+    Tracks(Vec<Vec<usize>>),
+}
 
 #[derive(Debug, Eq, PartialEq)]
 struct TimedEvent<'a> {
@@ -50,6 +80,9 @@ pub struct PlayerSettings<'a> {
     pub smf: Smf<'a>,
     pub loop_midi: bool,
     pub tracks: Option<Vec<usize>>,
+    pub options: ArrangeOptions,
+    // Playback speed multiplier, 1.0 is the original tempo
+    pub speed: f64,
 }
 
 impl<'a> PlayerSettings<'a> {
@@ -63,6 +96,8 @@ impl<'a> PlayerSettings<'a> {
             smf,
             loop_midi,
             tracks: None,
+            options: ArrangeOptions::default(),
+            speed: 1.0,
         })
     }
 }
@@ -70,28 +105,36 @@ impl<'a> PlayerSettings<'a> {
 pub struct WebfishingPlayer<'a> {
     smf: Smf<'a>,
     shift: i8,
+    options: ArrangeOptions,
+    speed: f64,
     micros_per_tick: u64,
     events: BinaryHeap<TimedEvent<'a>>,
-    enigo: Enigo,
-    window: &'a Window,
-    cur_string_positions: HashMap<i32, i32>,
-    strings_played: [bool; 6],
-    last_string_usage_time: [Instant; 6],
+    tempo_map: TempoMap,
+    final_tick: u64,
+    guitars: Vec<Guitar<'a>>,
+    routing: Routing,
+    // Notes dealt out so far, picks the window for the next one when splitting
+    dealt_notes: usize,
+    // The guitar that played last, it's the one with focus
+    last_guitar: usize,
     input_sleep_duration: u64,
     loop_midi: bool,
     wait_for_user: bool,
     tracks: Vec<usize>,
     multi: &'a MultiProgress,
+    remote: &'a Remote,
+    hotkeys: &'a Hotkeys,
     paused: Arc<AtomicBool>,
     song_elapsed_micros: Arc<AtomicU64>,
+    // Notes skipped for being out of range or having no free string
+    dropped_notes: usize,
+    // Notes moved into range by the range policy
+    clamped_notes: usize,
+    // The full-screen view, open while a song plays unless the plain log is used
+    screen: Option<Screen>,
     _data: Vec<u8>,
 }
 
-struct GuitarPosition {
-    string: i32, // 0-5, where 0 is the lowest E string
-    fret: i32,   // 0 means open string, 1-15 for frets
-}
-
 impl<'a> WebfishingPlayer<'a> {
     pub fn new(
         settings: PlayerSettings<'a>,
@@ -99,40 +142,78 @@ impl<'a> WebfishingPlayer<'a> {
         input_sleep_duration: u64,
         window: &'a Window,
         multi: &'a MultiProgress,
+        remote: &'a Remote,
+        hotkeys: &'a Hotkeys,
     ) -> Result<Self, Error> {
+        WebfishingPlayer::with_guitars(
+            settings,
+            wait_for_user,
+            vec![Guitar::new(window, input_sleep_duration)],
+            Routing::Split,
+            multi,
+            remote,
+            hotkeys,
+        )
+    }
+
+    /// Plays on several game windows at once, sharing the notes out by `routing`
+    pub fn with_guitars(
+        settings: PlayerSettings<'a>,
+        wait_for_user: bool,
+        guitars: Vec<Guitar<'a>>,
+        routing: Routing,
+        multi: &'a MultiProgress,
+        remote: &'a Remote,
+        hotkeys: &'a Hotkeys,
+    ) -> Result<Self, Error> {
+        let input_sleep_duration = guitars
+            .iter()
+            .map(Guitar::input_sleep_duration)
+            .max()
+            .ok_or_else(|| Error::other("no game windows to play on"))?;
         let smf = settings.smf;
         if smf.header.format != Format::Parallel {
             warn!("Format not parallel");
         }
 
-        let notes = WebfishingPlayer::get_notes(&smf);
-        let shift = WebfishingPlayer::calculate_optimal_shift(&notes);
+        let notes = get_notes(&smf);
+        let shift = calculate_optimal_shift(&notes, &settings.options);
+        let tempo_map = TempoMap::new(&smf);
         let mut player = WebfishingPlayer {
             smf,
             shift,
+            options: settings.options,
+            speed: settings.speed,
             micros_per_tick: 0,
             events: BinaryHeap::new(),
-            enigo: Enigo::new(&Settings::default()).unwrap(),
-            window,
-            cur_string_positions: HashMap::new(),
-            strings_played: [false; 6],
-            last_string_usage_time: [Instant::now(); 6],
+            tempo_map,
+            final_tick: 0,
+            guitars,
+            routing,
+            dealt_notes: 0,
+            last_guitar: 0,
             input_sleep_duration,
             loop_midi: settings.loop_midi,
             wait_for_user,
-            tracks: settings.tracks.unwrap_or(Vec::new()),
+            tracks: settings.tracks.unwrap_or_default(),
             multi,
+            remote,
+            hotkeys,
             paused: Arc::new(AtomicBool::new(false)),
             song_elapsed_micros: Arc::new(AtomicU64::new(0)),
+            dropped_notes: 0,
+            clamped_notes: 0,
+            screen: None,
             _data: settings._data,
         };
 
-        // For each 6 strings initialize the cur pos as 0
-        for i in 0..6 {
-            player.cur_string_positions.insert(i, 0);
-        }
-
         player.prepare_events();
+        player.final_tick = player
+            .events
+            .iter()
+            .map(|timed_event| timed_event.absolute_time)
+            .max()
+            .unwrap_or(0);
         Ok(player)
     }
 
@@ -156,118 +237,246 @@ impl<'a> WebfishingPlayer<'a> {
         }
     }
 
-    fn find_best_string(&mut self, note: u8) -> Option<GuitarPosition> {
-        let string_notes = [
-            [
-                40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55,
-            ], // low E
-            [
-                45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60,
-            ], // A
-            [
-                50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65,
-            ], // D
-            [
-                55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70,
-            ], // G
-            [
-                59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74,
-            ], // B
-            [
-                64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79,
-            ], // high E
-        ];
-
-        let int_note = note as i32;
-        let current_time = Instant::now();
-
-        // Create a vector to hold candidates based on last usage time
-        let mut candidates: Vec<(i32, i32)> = Vec::new();
-
-        for (string_index, notes) in string_notes.iter().enumerate() {
-            if self.strings_played[string_index] {
-                continue; // Skip if this string has already been played
-            }
+    pub fn dropped_notes(&self) -> usize {
+        self.dropped_notes
+    }
 
-            if let Some(fret) = notes.iter().position(|&n| n == int_note) {
-                // Found a match, add to candidates
-                candidates.push((string_index as i32, fret.try_into().unwrap()));
-            }
-        }
+    /// Timing of the inputs sent to each window
+    pub fn guitar_stats(&self) -> Vec<GuitarStats> {
+        self.guitars.iter().map(Guitar::stats).collect()
+    }
 
-        // Sort candidates by last usage time (ascending order)
-        candidates.sort_by_key(|&index| {
-            let string_index = index.0 as usize;
-            self.last_string_usage_time[string_index]
-        });
+    fn next_chord(&mut self) {
+        for guitar in &mut self.guitars {
+            guitar.next_chord();
+        }
+    }
 
-        // Select the best candidate (the one with the least last usage time)
-        if let Some(&(string_index, fret)) = candidates.first() {
-            // Update last usage time for the selected string
-            self.last_string_usage_time[string_index as usize] = current_time;
+    fn is_paused(&self) -> bool {
+        self.paused.load(atomic::Ordering::Relaxed)
+    }
 
-            return Some(GuitarPosition {
-                string: string_index,
-                fret,
+    fn set_paused(&self, paused: bool) {
+        if self.paused.swap(paused, atomic::Ordering::Relaxed) != paused {
+            self.remote.update_status(|status| status.paused = paused);
+            self.remote.emit(if paused {
+                Event::Paused
+            } else {
+                Event::Resumed
             });
         }
+    }
 
-        None // No suitable string found
+    fn set_tempo(&mut self, tempo: u32, ticks_per_beat: u64) {
+        // Timecode files run at a fixed rate whatever tempo they set
+        let tempo = match self.smf.header.timing {
+            midly::Timing::Metrical(_) => tempo,
+            midly::Timing::Timecode(..) => DEFAULT_TEMPO as u32,
+        };
+        self.micros_per_tick = (tempo as f64 / ticks_per_beat as f64 / self.speed) as u64;
     }
 
-    fn is_paused(&self) -> bool {
-        self.paused.load(atomic::Ordering::Relaxed)
+    fn transpose(&mut self, semitones: i8) {
+        self.shift = self.shift.saturating_add(semitones);
+        info!("Transposed to {:+} semitones", self.shift);
     }
 
-    fn toggle_pause(&self) {
-        self.paused.fetch_xor(true, atomic::Ordering::Relaxed);
+    // Checks the hotkeys and the commands from remote clients
+    fn check_inputs(&mut self, ticks_per_beat: u64) -> Option<Control> {
+        // The full-screen view takes Ctrl+C from the terminal, it stops the
+        // queue like the stop hotkey
+        if self.screen.as_ref().is_some_and(Screen::interrupted) {
+            return Some(Control::End(PlayEnd::Stopped));
+        }
+        while let Some(action) = self.hotkeys.try_next() {
+            match action {
+                HotkeyAction::Start => {}
+                HotkeyAction::Interrupt => return Some(Control::End(PlayEnd::Interrupted)),
+                HotkeyAction::TogglePause => self.set_paused(!self.is_paused()),
+                HotkeyAction::Next => return Some(Control::End(PlayEnd::Skipped)),
+                HotkeyAction::Previous => return Some(Control::End(PlayEnd::Previous)),
+                HotkeyAction::Restart => {
+                    return Some(Control::Seek(self.seek(0.0, ticks_per_beat)))
+                }
+                HotkeyAction::TransposeUp => self.transpose(1),
+                HotkeyAction::TransposeDown => self.transpose(-1),
+                HotkeyAction::StopQueue => return Some(Control::End(PlayEnd::Stopped)),
+            }
+        }
+
+        while let Some(command) = self.remote.next_command() {
+            match command {
+                Command::Pause => self.set_paused(true),
+                Command::Resume => self.set_paused(false),
+                Command::Skip => return Some(Control::End(PlayEnd::Skipped)),
+                Command::Stop => return Some(Control::End(PlayEnd::Stopped)),
+                Command::Seek(secs) => return Some(Control::Seek(self.seek(secs, ticks_per_beat))),
+                Command::Speed(speed) => {
+                    self.micros_per_tick =
+                        (self.micros_per_tick as f64 * self.speed / speed) as u64;
+                    self.speed = speed;
+                    info!("Speed changed to {:.2}x", speed);
+                    self.remote.update_status(|status| status.speed = speed);
+                    self.remote.emit(Event::SpeedChanged { speed });
+                }
+            }
+        }
+        None
+    }
+
+    // Restarts the events from `secs` into the song, returns the tick to
+    // continue from
+    fn seek(&mut self, secs: f64, ticks_per_beat: u64) -> u64 {
+        let target = self
+            .tempo_map
+            .tick_at((secs.max(0.0) * 1_000_000.0) as u64)
+            .min(self.final_tick);
+        self.events.clear();
+        self.prepare_events();
+        // Tempo changes before the target still apply
+        self.set_tempo(DEFAULT_TEMPO as u32, ticks_per_beat);
+        while self
+            .events
+            .peek()
+            .is_some_and(|timed_event| timed_event.absolute_time < target)
+        {
+            if let Some(TimedEvent {
+                event:
+                    TrackEvent {
+                        kind: TrackEventKind::Meta(MetaMessage::Tempo(tempo)),
+                        ..
+                    },
+                ..
+            }) = self.events.pop()
+            {
+                self.set_tempo(tempo.as_int(), ticks_per_beat);
+            }
+        }
+        self.next_chord();
+
+        let position = self.tempo_map.micros_at(target);
+        self.song_elapsed_micros.store(
+            (position as f64 / self.speed) as u64,
+            atomic::Ordering::Relaxed,
+        );
+        let position_secs = position as f64 / 1_000_000.0;
+        info!("Seeked to {:.1}s", position_secs);
+        self.remote
+            .update_status(|status| status.position_secs = position_secs);
+        self.remote.emit(Event::Seeked { position_secs });
+        target
     }
 
-    // returns true if the user wants to quit
-    fn check_inputs(&mut self, device_state: &DeviceState) -> bool {
-        let keys = device_state.get_keys();
-        if keys.contains(&Keycode::Escape) {
-            return true;
+    pub fn play(&mut self) -> PlayEnd {
+        let end = self.play_events();
+        // Back to the plain terminal for whatever comes after the song
+        self.screen = None;
+        end
+    }
+
+    // Shows how far the song is on the progress bar or the screen
+    fn show_progress(&mut self, pb: &ProgressBar, tick: u64) {
+        pb.set_position(tick);
+        if self.screen.is_none() {
+            return;
         }
-        if keys.contains(&Keycode::RShift) {
-            self.toggle_pause();
-            // Add a small delay to prevent multiple toggles
-            sleep(Duration::from_millis(200));
+        let progress = Progress {
+            tick,
+            paused: self.is_paused(),
+            speed: self.speed,
+            shift: self.shift,
+            played: self.guitars.iter().map(|guitar| guitar.stats().notes).sum(),
+            dropped: self.dropped_notes,
+            clamped: self.clamped_notes,
+        };
+        if let Some(screen) = &mut self.screen {
+            screen.draw(&progress);
         }
-        false
     }
 
-    pub fn play(&mut self) {
+    fn play_events(&mut self) -> PlayEnd {
         let timing = self.smf.header.timing;
         let ticks_per_beat = match timing {
             midly::Timing::Metrical(ppq) => ppq.as_int() as u64,
-            _ => unimplemented!("Timecode timing not supported"),
+            // Timecode files have no beats, one is counted as the default
+            // tempo's half second
+            midly::Timing::Timecode(fps, subframe) => {
+                (fps.as_int() as u64 * subframe as u64 / 2).max(1)
+            }
         };
+        // Until the song sets one
+        self.set_tempo(DEFAULT_TEMPO as u32, ticks_per_beat);
+
+        let speed = self.speed;
+        let length_secs = self.tempo_map.micros_at(self.final_tick) as f64 / 1_000_000.0;
+        self.remote.update_status(|status| {
+            status.position_secs = 0.0;
+            status.length_secs = length_secs;
+            status.paused = false;
+            status.speed = speed;
+        });
 
-        let device_state = DeviceState::new();
-
-        println!("Escape to stop the song, right shift to pause/play");
+        // Keys pressed in the menus aren't meant for the song
+        self.hotkeys.clear();
+        let bindings = self.hotkeys.bindings();
+        println!(
+            "{}",
+            bindings.help(&[
+                HotkeyAction::Interrupt,
+                HotkeyAction::TogglePause,
+                HotkeyAction::Restart,
+                HotkeyAction::Previous,
+                HotkeyAction::Next,
+                HotkeyAction::StopQueue,
+                HotkeyAction::TransposeUp,
+                HotkeyAction::TransposeDown,
+            ])
+        );
         if self.wait_for_user {
-            // Attempt to press space in-case the user's OS requires a permission pop-up for input
-            self.enigo.key(Key::Space, Click).unwrap();
-            println!("Tab over to the game and press backspace to start playing");
-            loop {
-                if device_state.get_keys().contains(&Keycode::Backspace) {
-                    break;
-                }
+            self.guitars[0].request_input_permission();
+            println!(
+                "Tab over to the game and press {} to start playing",
+                bindings.key(HotkeyAction::Start)
+            );
+            match self.hotkeys.wait_for(&[
+                HotkeyAction::Start,
+                HotkeyAction::Interrupt,
+                HotkeyAction::StopQueue,
+            ]) {
+                Some(HotkeyAction::Interrupt) => return PlayEnd::Interrupted,
+                Some(HotkeyAction::StopQueue) => return PlayEnd::Stopped,
+                Some(_) => {}
+                None => warn!("The keyboard can't be read, starting now"),
             }
         }
 
-        // Reset the guitar to all open string
-        self.set_fret(6, 0);
+        for guitar in &mut self.guitars {
+            guitar.reset();
+        }
+        if screen::enabled() {
+            match Screen::open(
+                &self.remote.status(),
+                &self.smf,
+                &self.tracks,
+                self.options,
+                ticks_per_beat,
+                self.guitars.len(),
+            ) {
+                Ok(screen) => self.screen = Some(screen),
+                Err(e) => warn!("Can't open the full-screen view, using the log: {}", e),
+            }
+        }
 
-        let final_tick = self.events.iter().last().unwrap().absolute_time;
+        let final_tick = self.final_tick;
         loop {
             // Start a new loop for playback
             let mut last_tick = 0; // Reset last_time for each loop iteration
             self.song_elapsed_micros.store(0, atomic::Ordering::Relaxed);
-
-            let pb = self.multi.add(ProgressBar::new(final_tick));
+            // The screen has its own progress, the bar would draw over it
+            let pb = match self.screen {
+                Some(_) => ProgressBar::hidden(),
+                None => self.multi.add(ProgressBar::new(final_tick)),
+            };
             let paused = Arc::clone(&self.paused);
             let elapsed = Arc::clone(&self.song_elapsed_micros);
             pb.set_style(
@@ -291,20 +500,27 @@ impl<'a> WebfishingPlayer<'a> {
                     }),
             );
 
-            while let Some(timed_event) = self.events.pop() {
-                if self.check_inputs(&device_state) {
-                    info!("Song interrupted");
-                    return;
+            'events: while let Some(timed_event) = self.events.pop() {
+                match self.check_inputs(ticks_per_beat) {
+                    Some(Control::End(end)) => return end,
+                    Some(Control::Seek(tick)) => {
+                        last_tick = tick;
+                        self.show_progress(&pb, tick);
+                        continue 'events;
+                    }
+                    None => {}
                 }
 
                 let wait_ticks = timed_event.absolute_time - last_tick;
                 if wait_ticks > 0 {
-                    self.strings_played = [false; 6];
+                    self.next_chord();
                     // Sleep for one tick at a time so we can check for escape
                     // and update the progress bar more smoothly
-                    for current_tick in last_tick..timed_event.absolute_time {
+                    let mut current_tick = last_tick;
+                    while current_tick < timed_event.absolute_time {
                         sleep(Duration::from_micros(self.micros_per_tick));
-                        pb.set_position(current_tick + 1);
+                        current_tick += 1;
+                        self.show_progress(&pb, current_tick);
 
                         // Update elapsed
                         let new_elapsed = self.song_elapsed_micros.load(atomic::Ordering::Relaxed)
@@ -312,10 +528,20 @@ impl<'a> WebfishingPlayer<'a> {
                         self.song_elapsed_micros
                             .store(new_elapsed, atomic::Ordering::Relaxed);
 
+                        let position_secs =
+                            self.tempo_map.micros_at(current_tick) as f64 / 1_000_000.0;
+                        self.remote
+                            .update_status(|status| status.position_secs = position_secs);
+
                         // Check for inputs during the wait
-                        if self.check_inputs(&device_state) {
-                            info!("Song interrupted");
-                            return;
+                        match self.check_inputs(ticks_per_beat) {
+                            Some(Control::End(end)) => return end,
+                            Some(Control::Seek(tick)) => {
+                                last_tick = tick;
+                                self.show_progress(&pb, tick);
+                                continue 'events;
+                            }
+                            None => {}
                         }
                     }
                 }
@@ -323,16 +549,22 @@ impl<'a> WebfishingPlayer<'a> {
 
                 // Wait while paused
                 while self.is_paused() {
+                    self.show_progress(&pb, last_tick);
                     sleep(Duration::from_millis(100));
-                    if self.check_inputs(&device_state) {
-                        info!("Song interrupted");
-                        return;
+                    match self.check_inputs(ticks_per_beat) {
+                        Some(Control::End(end)) => return end,
+                        Some(Control::Seek(tick)) => {
+                            last_tick = tick;
+                            self.show_progress(&pb, tick);
+                            continue 'events;
+                        }
+                        None => {}
                     }
                 }
 
                 match timed_event.event.kind {
-                    TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) => {
-                        self.micros_per_tick = tempo.as_int() as u64 / ticks_per_beat;
+                    TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                        self.set_tempo(tempo.as_int(), ticks_per_beat);
                         info!(
                             "Tempo change: {}µs per tick - track {}",
                             self.micros_per_tick, timed_event.track
@@ -340,46 +572,66 @@ impl<'a> WebfishingPlayer<'a> {
                     }
                     TrackEventKind::Midi {
                         channel: _,
-                        message,
-                    } => match message {
-                        midly::MidiMessage::NoteOn { key, vel } => {
-                            if vel.as_int() > 0 {
-                                let note = (key.as_int() as i8 + self.shift) as u8;
+                        message: midly::MidiMessage::NoteOn { key, vel },
+                    } if vel.as_int() > 0 => {
+                        match self.options.fit_note(key.as_int(), self.shift) {
+                            Some(note) => {
+                                if note as i16 != key.as_int() as i16 + self.shift as i16 {
+                                    self.clamped_notes += 1;
+                                }
                                 self.play_note(note, timed_event.track);
-
-                                // Update elapsed for the input sleep
-                                let new_elapsed =
-                                    self.song_elapsed_micros.load(atomic::Ordering::Relaxed)
-                                        + self.input_sleep_duration * 1000; // Convert ms to µs
-                                self.song_elapsed_micros
-                                    .store(new_elapsed, atomic::Ordering::Relaxed);
+                            }
+                            None => {
+                                debug!("Skipping out of range note {}", key);
+                                self.dropped_notes += 1;
                             }
                         }
-                        _ => {}
-                    },
+
+                        // Update elapsed for the input sleep
+                        let new_elapsed = self.song_elapsed_micros.load(atomic::Ordering::Relaxed)
+                            + self.input_sleep_duration * 1000; // Convert ms to µs
+                        self.song_elapsed_micros
+                            .store(new_elapsed, atomic::Ordering::Relaxed);
+                    }
                     _ => {}
                 }
 
-                pb.set_position(timed_event.absolute_time as u64);
+                self.show_progress(&pb, timed_event.absolute_time);
             }
 
             pb.finish();
             self.multi.remove(&pb);
 
             if self.loop_midi {
-                info!("Looping the MIDI playback (Hold ESC to stop)");
+                info!(
+                    "Looping the MIDI playback ({} to stop)",
+                    self.hotkeys.bindings().key(HotkeyAction::Interrupt)
+                );
                 self.prepare_events();
             } else {
-                break;
+                return PlayEnd::Finished;
             }
         }
     }
 
     fn play_note(&mut self, note: u8, track: u32) {
-        let note = note.clamp(MIN_NOTE, MAX_NOTE);
+        let index = match &self.routing {
+            Routing::Split => self.dealt_notes % self.guitars.len(),
+            Routing::Tracks(parts) => parts
+                .iter()
+                .position(|tracks| tracks.contains(&(track as usize)))
+                .unwrap_or(0),
+        };
+        self.dealt_notes += 1;
+        if index != self.last_guitar {
+            self.guitars[index].focus_lost();
+            self.last_guitar = index;
+        }
 
-        // Use the find_best_string function to get the guitar position
-        if let Some(position) = self.find_best_string(note) {
+        if let Some(position) = self.guitars[index].play_note(note) {
+            if let Some(screen) = &mut self.screen {
+                screen.strummed(index, position);
+            }
             info!(
                 "Playing note {} on string {} fret {} - track {}",
                 note,
@@ -387,121 +639,9 @@ impl<'a> WebfishingPlayer<'a> {
                 position.fret,
                 track
             );
-
-            // Set fret position
-            self.set_fret(position.string, position.fret);
-
-            // Strum the string
-            self.strum_string(position.string);
-
-            self.strings_played[position.string as usize] = true;
         } else {
             warn!("No suitable string found for note {}", note);
+            self.dropped_notes += 1;
         }
     }
-
-    fn set_fret(&mut self, string: i32, fret: i32) {
-        // Don't attempt to change to this position if it's already set
-        // It will just unset it
-        if self.cur_string_positions.get(&string).unwrap_or(&-1) == &fret {
-            return;
-        }
-
-        let cur_string_pos = self.cur_string_positions.entry(string).or_default();
-        *cur_string_pos = fret;
-
-        // These values need to be adjusted based on your screen resolution and game window position
-        let scale_x = self.window.width() as f32 / 2560.0;
-        let scale_y = self.window.height() as f32 / 1440.0;
-
-        // Offset from the left where the strings start
-        let scaled_left = (460.0 * scale_x) as i32;
-        // Offset from the top where the frets start
-        let scaled_top = (130.0 * scale_y) as i32;
-        // Distance centre to centre of the strings
-        let scaled_string = (44.0 * scale_x) as i32;
-        // Distance centre to centre of the frets
-        let scaled_fret = (82.0 * scale_y) as i32;
-
-        let fret_x = self.window.x() + (scaled_left + (string * scaled_string));
-        let fret_y = self.window.y() + (scaled_top + (fret * scaled_fret));
-
-        debug!(
-            "x: {} y: {} | scale_x {:.3} scale_y {:.3}",
-            fret_x, fret_y, scale_x, scale_y
-        );
-
-        self.enigo
-            .move_mouse(fret_x, fret_y, Coordinate::Abs)
-            .unwrap();
-        self.enigo.button(Button::Left, Click).unwrap();
-    }
-
-    fn strum_string(&mut self, string: i32) {
-        let key = match string {
-            0 => Key::Unicode('q'),
-            1 => Key::Unicode('w'),
-            2 => Key::Unicode('e'),
-            3 => Key::Unicode('r'),
-            4 => Key::Unicode('t'),
-            5 => Key::Unicode('y'),
-            _ => return,
-        };
-
-        self.enigo.key(key, Press).unwrap();
-        // NOTE: This sleep is needed for the game to read the input
-        // espesially when it is low FPS since it checks input
-        // once per frame
-        sleep(Duration::from_millis(self.input_sleep_duration));
-        self.enigo.key(key, Release).unwrap();
-    }
-
-    fn get_notes(smf: &Smf) -> Vec<u8> {
-        smf.tracks
-            .iter()
-            .flat_map(|track| track)
-            .filter_map(|event| match event.kind {
-                TrackEventKind::Midi { ref message, .. } => Some(message),
-                _ => None,
-            })
-            .filter_map(|message| match message {
-                midly::MidiMessage::NoteOn { key, .. } => Some(key.as_int()),
-                _ => None,
-            })
-            .collect()
-    }
-
-    fn calculate_optimal_shift(notes: &Vec<u8>) -> i8 {
-        let mut best_shift: i16 = 0;
-        let mut max_playable_notes = 0;
-        let total_notes = notes.len();
-
-        for shift in -127..=127i16 {
-            let playable_notes = notes
-                .iter()
-                .filter(|&&n| {
-                    (n as i16 + shift) >= MIN_NOTE as i16 && (n as i16 + shift) <= MAX_NOTE as i16
-                })
-                .count();
-
-            // The best shift is the one with the most playable notes that is closest to 0
-            if playable_notes > max_playable_notes
-                || (playable_notes == max_playable_notes && shift.abs() < best_shift.abs())
-            {
-                max_playable_notes = playable_notes;
-                best_shift = shift;
-            }
-        }
-
-        info!("Optimal shift: {}", best_shift);
-        info!(
-            "Total notes: {} | Playable notes: {} | Clamped notes {} : {}% playable",
-            total_notes,
-            max_playable_notes,
-            total_notes - max_playable_notes,
-            max_playable_notes as f32 / total_notes as f32 * 100.0
-        );
-
-        best_shift as i8
-    }
 }